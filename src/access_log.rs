@@ -0,0 +1,185 @@
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::{net::IpAddr, time::Duration};
+
+use crate::http::{HttpMethod, HttpRequest, HttpResponse};
+
+/// Format of the access log line written for every request served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// Common Log Format, as written by most web servers.
+    Common,
+    /// Common Log Format followed by the time taken to serve the request, in microseconds.
+    CommonWithDuration,
+    /// One JSON object per line.
+    Json,
+}
+
+/// What gets recorded about a served request.
+#[derive(Debug)]
+pub struct AccessLogEntry<'a> {
+    pub timestamp: DateTime<Utc>,
+    pub peer_ip: IpAddr,
+    pub method: String,
+    pub url: &'a str,
+    pub version: String,
+    pub status: u16,
+    pub bytes: usize,
+    pub duration: Duration,
+    pub request_id: Option<&'a str>,
+}
+
+impl<'a> AccessLogEntry<'a> {
+    pub fn new(
+        request: &'a HttpRequest,
+        response: &HttpResponse,
+        timestamp: DateTime<Utc>,
+        duration: Duration,
+    ) -> Self {
+        Self {
+            timestamp,
+            peer_ip: request.peer_ip,
+            method: request.method.to_string(),
+            url: request.request_target(),
+            version: request.version.to_string(),
+            status: response.status_code,
+            bytes: match request.method {
+                HttpMethod::HEAD => 0,
+                _ => response.body_len() as usize,
+            },
+            duration,
+            request_id: request
+                .get_header("X-Request-Id")
+                .map(|header| header.value.as_str()),
+        }
+    }
+
+    pub fn format(&self, format: AccessLogFormat) -> String {
+        match format {
+            AccessLogFormat::Common => self.to_common(),
+            AccessLogFormat::CommonWithDuration => {
+                format!("{} {}", self.to_common(), self.duration.as_micros())
+            }
+            AccessLogFormat::Json => self.to_json(),
+        }
+    }
+
+    fn to_common(&self) -> String {
+        let bytes = match self.bytes {
+            0 => "-".to_owned(),
+            bytes => bytes.to_string(),
+        };
+
+        format!(
+            "{} - - [{}] \"{} {} {}\" {} {}",
+            self.peer_ip,
+            self.timestamp.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.method,
+            self.url,
+            self.version,
+            self.status,
+            bytes
+        )
+    }
+
+    fn to_json(&self) -> String {
+        json!({
+            "timestamp": self.timestamp.to_rfc3339(),
+            "method": self.method,
+            "url": self.url,
+            "status": self.status,
+            "bytes": self.bytes,
+            "duration_ms": self.duration.as_secs_f64() * 1000.0,
+            "peer_ip": self.peer_ip.to_string(),
+            "request_id": self.request_id,
+        })
+        .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serde_json::Value;
+    use std::{net::Ipv4Addr, str::FromStr};
+
+    use crate::http::{HttpHeader, HttpRequestRaw, HttpResponseBuilder};
+
+    use super::*;
+
+    fn get_served_request() -> (HttpRequest, HttpResponse) {
+        let request = HttpRequest::from_raw_request(HttpRequestRaw {
+            request_line: "GET /users?page=2 HTTP/1.1".to_owned(),
+            headers: vec![HttpHeader::new("X-Request-Id", "abc-123")],
+            body: vec![],
+            trailers: vec![],
+            peer_ip: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20)),
+            local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+        })
+        .unwrap();
+
+        let response = HttpResponseBuilder::new()
+            .set_raw_body(b"0123456789".to_vec())
+            .build()
+            .unwrap();
+
+        (request, response)
+    }
+
+    fn get_timestamp() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 10, 10, 13, 55, 36).unwrap()
+    }
+
+    #[test]
+    fn test_common_log_format() {
+        let (request, response) = get_served_request();
+        let entry = AccessLogEntry::new(
+            &request,
+            &response,
+            get_timestamp(),
+            Duration::from_millis(3),
+        );
+
+        assert_eq!(
+            "192.168.1.20 - - [10/Oct/2024:13:55:36 +0000] \"GET /users?page=2 HTTP/1.1\" 200 10",
+            entry.format(AccessLogFormat::Common)
+        );
+    }
+
+    #[test]
+    fn test_common_log_format_with_duration() {
+        let (request, response) = get_served_request();
+        let entry = AccessLogEntry::new(
+            &request,
+            &response,
+            get_timestamp(),
+            Duration::from_micros(2500),
+        );
+
+        assert_eq!(
+            "192.168.1.20 - - [10/Oct/2024:13:55:36 +0000] \"GET /users?page=2 HTTP/1.1\" 200 10 2500",
+            entry.format(AccessLogFormat::CommonWithDuration)
+        );
+    }
+
+    #[test]
+    fn test_json_log_format() {
+        let (request, response) = get_served_request();
+        let entry = AccessLogEntry::new(
+            &request,
+            &response,
+            get_timestamp(),
+            Duration::from_micros(2500),
+        );
+
+        let log: Value = serde_json::from_str(&entry.format(AccessLogFormat::Json)).unwrap();
+        assert_eq!("2024-10-10T13:55:36+00:00", log["timestamp"]);
+        assert_eq!("GET", log["method"]);
+        assert_eq!("/users?page=2", log["url"]);
+        assert_eq!(200, log["status"]);
+        assert_eq!(10, log["bytes"]);
+        assert_eq!(2.5, log["duration_ms"]);
+        assert_eq!("192.168.1.20", log["peer_ip"]);
+        assert_eq!("abc-123", log["request_id"]);
+    }
+}
@@ -0,0 +1,332 @@
+use anyhow::Result;
+use flate2::{write::GzEncoder, Compression};
+use log::trace;
+use std::io::Write;
+
+use crate::http::{HttpHeader, HttpRequest, HttpResponse};
+
+/// Default size under which bodies are not worth compressing.
+pub const DEFAULT_GZIP_MIN_SIZE: usize = 1024;
+
+/// Media types that are already compressed, entries ending with `/` exclude a whole family.
+const COMPRESSED_MEDIA_TYPES: [&str; 14] = [
+    "image/",
+    "audio/",
+    "video/",
+    "application/gzip",
+    "application/x-gzip",
+    "application/zip",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+    "application/zstd",
+    "application/pdf",
+    "font/woff",
+    "font/woff2",
+];
+
+/// Which responses get compressed with gzip once enabled with
+/// [`WebServer::with_compression`](crate::web_server::WebServer::with_compression).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressionConfig {
+    min_size: usize,
+    excluded_types: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CompressionConfig {
+    /// Compresses bodies of at least 1 KiB, except audio, video, archives and images other than
+    /// SVG.
+    pub fn new() -> Self {
+        Self {
+            min_size: DEFAULT_GZIP_MIN_SIZE,
+            excluded_types: COMPRESSED_MEDIA_TYPES
+                .iter()
+                .map(|&media_type| media_type.to_owned())
+                .collect(),
+        }
+    }
+
+    /// Sets the size under which bodies are sent as is.
+    pub fn min_size(mut self, bytes: usize) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Adds media types never compressed, an entry ending with `/` (e.g. `image/`) excludes
+    /// the whole family.
+    pub fn exclude_types(mut self, media_types: &[&str]) -> Self {
+        self.excluded_types.extend(
+            media_types
+                .iter()
+                .map(|media_type| media_type.to_ascii_lowercase()),
+        );
+        self
+    }
+
+    /// Whether compressing a body of this content type can make it meaningfully smaller.
+    ///
+    /// XML and JSON based types (`+xml`, `+json`) are text and always compressible.
+    pub fn is_compressible(&self, content_type: &str) -> bool {
+        let media_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+
+        if media_type.ends_with("+xml") || media_type.ends_with("+json") {
+            return true;
+        }
+
+        !self
+            .excluded_types
+            .iter()
+            .any(|excluded| match excluded.ends_with('/') {
+                true => media_type.starts_with(excluded.as_str()),
+                false => media_type == *excluded,
+            })
+    }
+}
+
+/// Whether the client accepts gzip encoded responses, i.e. lists `gzip` or `*` in its
+/// `Accept-Encoding` header without `q=0`.
+pub fn accepts_gzip(request: &HttpRequest) -> bool {
+    request
+        .header_values("Accept-Encoding")
+        .iter()
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut params = coding.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default();
+            let rejected = params.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+
+            !rejected && (name.eq_ignore_ascii_case("gzip") || name == "*")
+        })
+}
+
+/// Compresses the body of `response` with gzip when the client accepts it and `config` allows
+/// it for the content type and size of the body.
+///
+/// In-memory bodies are compressed right away and get a new `Content-Length`, streamed ones are
+/// compressed as they are written with chunked encoding. Returns whether the body is compressed.
+pub fn gzip_response(
+    request: &HttpRequest,
+    response: &mut HttpResponse,
+    config: &CompressionConfig,
+) -> Result<bool> {
+    if response.gzip || response.headers.contains_key("Content-Encoding") {
+        return Ok(false);
+    }
+
+    // `Content-Range` describes the identity bytes, they must be sent as they are
+    if response.status_code == 206 || response.headers.contains_key("Content-Range") {
+        return Ok(false);
+    }
+
+    let compressible = response
+        .headers
+        .get("Content-Type")
+        .is_some_and(|header| config.is_compressible(&header.value));
+    if !compressible || response.body_len() < config.min_size as u64 {
+        return Ok(false);
+    }
+
+    // the response depends on the header even when it is not compressed this time
+    add_vary(response, "Accept-Encoding");
+
+    if !accepts_gzip(request) {
+        return Ok(false);
+    }
+
+    if let Some(stream) = &response.stream {
+        // events must reach the client as soon as they are sent, not once a block is compressed
+        if stream.content_length().is_none() {
+            return Ok(false);
+        }
+
+        if !request.supports_chunked() {
+            return Ok(false);
+        }
+
+        trace!("compressing streamed body on the fly");
+        response.gzip = true;
+        return Ok(true);
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&response.body)?;
+    let compressed = encoder.finish()?;
+    trace!(
+        "compressed body from {} to {} bytes",
+        response.body.len(),
+        compressed.len()
+    );
+
+    set_header(response, "Content-Length", &compressed.len().to_string());
+    set_header(response, "Content-Encoding", "gzip");
+    response.body = compressed;
+    Ok(true)
+}
+
+/// Adds `header_name` to the `Vary` header of the response, keeping the names already there.
+pub(crate) fn add_vary(response: &mut HttpResponse, header_name: &str) {
+    let vary = match response.headers.get("Vary") {
+        Some(header)
+            if header
+                .value
+                .split(',')
+                .any(|v| v.trim().eq_ignore_ascii_case(header_name)) =>
+        {
+            return
+        }
+        Some(header) => format!("{}, {header_name}", header.value),
+        None => header_name.to_owned(),
+    };
+    set_header(response, "Vary", &vary);
+}
+
+fn set_header(response: &mut HttpResponse, name: &str, value: &str) {
+    response
+        .headers
+        .insert(name.to_owned(), HttpHeader::new(name, value));
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::read::GzDecoder;
+    use serde_json::json;
+    use std::{io::Read, net::IpAddr, str::FromStr};
+
+    use crate::http::{HttpRequestRaw, HttpResponseBuilder};
+
+    use super::*;
+
+    fn get_request(accept_encoding: &str) -> HttpRequest {
+        HttpRequest::from_raw_request(HttpRequestRaw {
+            request_line: "GET /page HTTP/1.1".to_owned(),
+            headers: vec![HttpHeader::new("Accept-Encoding", accept_encoding)],
+            body: vec![],
+            trailers: vec![],
+            peer_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+            local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_accepts_gzip() {
+        assert!(accepts_gzip(&get_request("gzip")));
+        assert!(accepts_gzip(&get_request("deflate, GZIP;q=0.5")));
+        assert!(accepts_gzip(&get_request("*")));
+        assert!(!accepts_gzip(&get_request("br, deflate")));
+        assert!(!accepts_gzip(&get_request("gzip;q=0, br")));
+    }
+
+    #[test]
+    fn test_is_compressible() {
+        let config = CompressionConfig::new();
+        assert!(config.is_compressible("text/html"));
+        assert!(config.is_compressible("application/json; charset=utf-8"));
+        assert!(config.is_compressible("image/svg+xml"));
+        assert!(!config.is_compressible("image/png"));
+        assert!(!config.is_compressible("video/mp4"));
+        assert!(!config.is_compressible("application/zip"));
+
+        let config = config.exclude_types(&["text/csv", "model/"]);
+        assert!(!config.is_compressible("text/csv"));
+        assert!(!config.is_compressible("model/gltf-binary"));
+        assert!(config.is_compressible("text/plain"));
+    }
+
+    #[test]
+    fn test_gzip_response_small_or_image_untouched() {
+        let request = get_request("gzip");
+        let config = CompressionConfig::new();
+
+        let mut response = HttpResponseBuilder::new()
+            .set_html_body("tiny")
+            .build()
+            .unwrap();
+        assert!(!gzip_response(&request, &mut response, &config).unwrap());
+        assert_eq!(b"tiny\r\n".to_vec(), response.body);
+
+        let mut response = HttpResponseBuilder::new()
+            .set_raw_body(vec![0; 4096])
+            .set_content_type("image/png")
+            .build()
+            .unwrap();
+        assert!(!gzip_response(&request, &mut response, &config).unwrap());
+        assert!(!response.headers.contains_key("Content-Encoding"));
+        assert_eq!(vec![0; 4096], response.body);
+    }
+
+    #[test]
+    fn test_gzip_response_partial_content_untouched() {
+        let request = HttpRequest::from_raw_request(HttpRequestRaw {
+            request_line: "GET /page HTTP/1.1".to_owned(),
+            headers: vec![
+                HttpHeader::new("Accept-Encoding", "gzip"),
+                HttpHeader::new("Range", "bytes=0-2047,3000-"),
+            ],
+            body: vec![],
+            trailers: vec![],
+            peer_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+            local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+        })
+        .unwrap();
+        let config = CompressionConfig::new();
+
+        let mut response = HttpResponseBuilder::new()
+            .set_html_body(&"a".repeat(4096))
+            .with_range_support(&request)
+            .unwrap()
+            .build()
+            .unwrap();
+        let body = response.body.clone();
+
+        assert_eq!(206, response.status_code);
+        assert!(!gzip_response(&request, &mut response, &config).unwrap());
+        assert!(!response.headers.contains_key("Content-Encoding"));
+        assert_eq!(body, response.body);
+    }
+
+    #[test]
+    fn test_gzip_response_json_above_threshold() {
+        let config = CompressionConfig::new().min_size(64);
+        let items: Vec<_> = (0..20)
+            .map(|i| json!({ "id": i, "name": "item" }))
+            .collect();
+        let mut response = HttpResponseBuilder::new()
+            .set_json_body(&items)
+            .unwrap()
+            .build()
+            .unwrap();
+        let body = response.body.clone();
+
+        assert!(gzip_response(&get_request("gzip"), &mut response, &config).unwrap());
+        assert_eq!("gzip", response.headers["Content-Encoding"].value);
+        assert_eq!("Accept-Encoding", response.headers["Vary"].value);
+        assert_eq!(
+            response.body.len().to_string(),
+            response.headers["Content-Length"].value
+        );
+
+        let mut decompressed = vec![];
+        GzDecoder::new(response.body.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(body, decompressed);
+    }
+}
@@ -0,0 +1,395 @@
+use anyhow::Result;
+use log::debug;
+
+use crate::{
+    compression::add_vary,
+    http::{
+        response_status_codes::HttpStatusCode, HttpHeader, HttpMethod, HttpRequest, HttpResponse,
+        HttpResponseBuilder,
+    },
+    middleware::Middleware,
+};
+
+/// Cross-Origin Resource Sharing policy applied by the router once enabled with
+/// [`Router::enable_cors`](crate::router::Router::enable_cors).
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    allowed_origins: Vec<String>,
+    allowed_methods: Vec<HttpMethod>,
+    allowed_headers: Vec<String>,
+    allow_credentials: bool,
+    max_age: Option<u64>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CorsConfig {
+    /// Allows any origin to use `GET`, `HEAD` and `POST`, without credentials.
+    pub fn new() -> Self {
+        Self {
+            allowed_origins: vec![],
+            allowed_methods: vec![HttpMethod::GET, HttpMethod::HEAD, HttpMethod::POST],
+            allowed_headers: vec![],
+            allow_credentials: false,
+            max_age: None,
+        }
+    }
+
+    /// Restricts the origins allowed, can be called multiple times. Any origin is allowed when
+    /// none is given or when `*` is one of them.
+    ///
+    /// Credentialed requests cannot use the `*` wildcard, the request origin is sent back
+    /// instead when credentials are allowed.
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        self.allowed_origins.push(origin.to_owned());
+        self
+    }
+
+    pub fn allow_methods(mut self, methods: &[HttpMethod]) -> Self {
+        self.allowed_methods = methods.to_vec();
+        self
+    }
+
+    pub fn allow_headers(mut self, headers: &[&str]) -> Self {
+        self.allowed_headers = headers.iter().map(|&header| header.to_owned()).collect();
+        self
+    }
+
+    pub fn allow_credentials(mut self, allow: bool) -> Self {
+        self.allow_credentials = allow;
+        self
+    }
+
+    /// Sets for how many seconds browsers may cache the result of a preflight request.
+    pub fn max_age(mut self, seconds: u64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn is_preflight(request: &HttpRequest) -> bool {
+        request.method == HttpMethod::OPTIONS
+            && request.get_header("Origin").is_some()
+            && request
+                .get_header("Access-Control-Request-Method")
+                .is_some()
+    }
+
+    fn allows_any_origin(&self) -> bool {
+        self.allowed_origins.is_empty() || self.allowed_origins.iter().any(|o| o == "*")
+    }
+
+    /// Value of `Access-Control-Allow-Origin` for the request, `None` if its origin is not allowed.
+    fn allowed_origin(&self, request: &HttpRequest) -> Option<String> {
+        let origin = &request.get_header("Origin")?.value;
+        if self.allows_any_origin() {
+            // the wildcard cannot be used for credentialed requests
+            return match self.allow_credentials {
+                true => Some(origin.to_owned()),
+                false => Some("*".to_owned()),
+            };
+        }
+
+        self.allowed_origins
+            .iter()
+            .find(|allowed| *allowed == origin)
+            .cloned()
+    }
+
+    pub fn preflight_response(&self, request: &HttpRequest) -> Result<HttpResponse> {
+        let mut response = HttpResponseBuilder::new()
+            .set_status(HttpStatusCode::NoContent)
+            .build()?;
+
+        if self.allowed_origin(request).is_none() {
+            self.apply_headers(request, &mut response);
+            return Ok(response);
+        }
+
+        let methods: Vec<_> = self
+            .allowed_methods
+            .iter()
+            .map(|method| method.to_string())
+            .collect();
+        set_header(
+            &mut response,
+            "Access-Control-Allow-Methods",
+            &methods.join(", "),
+        );
+
+        if !self.allowed_headers.is_empty() {
+            set_header(
+                &mut response,
+                "Access-Control-Allow-Headers",
+                &self.allowed_headers.join(", "),
+            );
+        }
+
+        if let Some(max_age) = self.max_age {
+            set_header(
+                &mut response,
+                "Access-Control-Max-Age",
+                &max_age.to_string(),
+            );
+        }
+
+        self.apply_headers(request, &mut response);
+        Ok(response)
+    }
+
+    /// Adds the CORS headers allowing the request origin to read the response.
+    ///
+    /// Unless any origin gets the `*` wildcard, the response depends on the origin and gets
+    /// `Vary: Origin` even when it is not allowed, so that a shared cache does not serve it to
+    /// another origin.
+    pub fn apply_headers(&self, request: &HttpRequest, response: &mut HttpResponse) {
+        if !self.allows_any_origin() || self.allow_credentials {
+            add_vary(response, "Origin");
+        }
+
+        let Some(origin) = self.allowed_origin(request) else {
+            return;
+        };
+
+        set_header(response, "Access-Control-Allow-Origin", &origin);
+        if self.allow_credentials {
+            set_header(response, "Access-Control-Allow-Credentials", "true");
+        }
+    }
+}
+
+impl Middleware for CorsConfig {
+    fn before(&self, request: &HttpRequest) -> Result<Option<HttpResponse>> {
+        if !Self::is_preflight(request) {
+            return Ok(None);
+        }
+
+        debug!("answering CORS preflight request");
+        Ok(Some(self.preflight_response(request)?))
+    }
+
+    fn after(&self, request: &HttpRequest, response: &mut HttpResponse) -> Result<()> {
+        self.apply_headers(request, response);
+        Ok(())
+    }
+}
+
+fn set_header(response: &mut HttpResponse, name: &str, value: &str) {
+    response
+        .headers
+        .insert(name.to_owned(), HttpHeader::new(name, value));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, str::FromStr};
+
+    use crate::{
+        http::{HttpHeader, HttpRequestRaw},
+        router::{Router, RoutingData},
+    };
+
+    use super::*;
+
+    fn get_hello(_request: &HttpRequest, _routing_data: &RoutingData) -> Result<HttpResponse> {
+        HttpResponseBuilder::new().set_html_body("hello").build()
+    }
+
+    fn get_cors_router() -> Router {
+        let cors = CorsConfig::new()
+            .allow_origin("https://app.example.com")
+            .allow_methods(&[HttpMethod::GET, HttpMethod::PUT])
+            .allow_headers(&["Content-Type", "X-Token"])
+            .allow_credentials(true)
+            .max_age(600);
+
+        Router::new()
+            .get("/hello", get_hello)
+            .unwrap()
+            .enable_cors(cors)
+    }
+
+    fn get_request(request_line: &str, headers: &[(&str, &str)]) -> HttpRequest {
+        HttpRequest::from_raw_request(HttpRequestRaw {
+            request_line: request_line.to_owned(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| HttpHeader::new(name, value))
+                .collect(),
+            body: vec![],
+            trailers: vec![],
+            peer_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+            local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+        })
+        .unwrap()
+    }
+
+    fn header_value<'a>(response: &'a HttpResponse, name: &str) -> Option<&'a str> {
+        response
+            .headers
+            .get(name)
+            .map(|header| header.value.as_str())
+    }
+
+    #[test]
+    fn test_cors_preflight_request() {
+        let request = get_request(
+            "OPTIONS /hello HTTP/1.1",
+            &[
+                ("Origin", "https://app.example.com"),
+                ("Access-Control-Request-Method", "PUT"),
+            ],
+        );
+
+        let response = get_cors_router().handle_request(&request).unwrap();
+
+        assert_eq!("204 No Content", response.status);
+        assert_eq!(
+            Some("https://app.example.com"),
+            header_value(&response, "Access-Control-Allow-Origin")
+        );
+        assert_eq!(
+            Some("GET, PUT"),
+            header_value(&response, "Access-Control-Allow-Methods")
+        );
+        assert_eq!(
+            Some("Content-Type, X-Token"),
+            header_value(&response, "Access-Control-Allow-Headers")
+        );
+        assert_eq!(
+            Some("true"),
+            header_value(&response, "Access-Control-Allow-Credentials")
+        );
+        assert_eq!(
+            Some("600"),
+            header_value(&response, "Access-Control-Max-Age")
+        );
+        assert_eq!(Some("Origin"), header_value(&response, "Vary"));
+    }
+
+    #[test]
+    fn test_cors_simple_request() {
+        let request = get_request(
+            "GET /hello HTTP/1.1",
+            &[("Origin", "https://app.example.com")],
+        );
+
+        let response = get_cors_router().handle_request(&request).unwrap();
+
+        assert_eq!("200 OK", response.status);
+        assert_eq!(
+            Some("https://app.example.com"),
+            header_value(&response, "Access-Control-Allow-Origin")
+        );
+        assert_eq!(
+            Some("true"),
+            header_value(&response, "Access-Control-Allow-Credentials")
+        );
+        assert_eq!(None, header_value(&response, "Access-Control-Max-Age"));
+    }
+
+    #[test]
+    fn test_cors_disallowed_origin_has_no_headers() {
+        let request = get_request("GET /hello HTTP/1.1", &[("Origin", "https://evil.com")]);
+
+        let response = get_cors_router().handle_request(&request).unwrap();
+
+        assert_eq!("200 OK", response.status);
+        assert_eq!(None, header_value(&response, "Access-Control-Allow-Origin"));
+        assert_eq!(Some("Origin"), header_value(&response, "Vary"));
+
+        let request = get_request("GET /hello HTTP/1.1", &[]);
+        let response = get_cors_router().handle_request(&request).unwrap();
+        assert_eq!(Some("Origin"), header_value(&response, "Vary"));
+    }
+
+    fn get_hello_varying(
+        _request: &HttpRequest,
+        _routing_data: &RoutingData,
+    ) -> Result<HttpResponse> {
+        HttpResponseBuilder::new()
+            .set_html_body("hello")
+            .set_header("Vary", "Accept-Language")
+            .build()
+    }
+
+    #[test]
+    fn test_cors_vary_merged_with_handler_vary() {
+        let router = Router::new()
+            .get("/hello", get_hello_varying)
+            .unwrap()
+            .enable_cors(CorsConfig::new().allow_origin("https://app.example.com"));
+        let request = get_request(
+            "GET /hello HTTP/1.1",
+            &[("Origin", "https://app.example.com")],
+        );
+
+        let response = router.handle_request(&request).unwrap();
+
+        assert_eq!(
+            Some("Accept-Language, Origin"),
+            header_value(&response, "Vary")
+        );
+    }
+
+    #[test]
+    fn test_cors_wildcard_with_credentials_echoes_origin() {
+        let cors = CorsConfig::new().allow_origin("*").allow_credentials(true);
+        let router = Router::new()
+            .get("/hello", get_hello)
+            .unwrap()
+            .enable_cors(cors);
+        let request = get_request("GET /hello HTTP/1.1", &[("Origin", "https://a.com")]);
+
+        let response = router.handle_request(&request).unwrap();
+
+        assert_eq!(
+            Some("https://a.com"),
+            header_value(&response, "Access-Control-Allow-Origin")
+        );
+        assert_eq!(
+            Some("true"),
+            header_value(&response, "Access-Control-Allow-Credentials")
+        );
+        assert_eq!(Some("Origin"), header_value(&response, "Vary"));
+    }
+
+    #[test]
+    fn test_cors_options_without_request_method_is_not_preflight() {
+        let request = get_request(
+            "OPTIONS /hello HTTP/1.1",
+            &[("Origin", "https://app.example.com")],
+        );
+
+        let response = get_cors_router().handle_request(&request).unwrap();
+
+        assert_eq!(
+            HttpStatusCode::MethodNotAllowed.to_string(),
+            response.status
+        );
+        assert_eq!(
+            None,
+            header_value(&response, "Access-Control-Allow-Methods")
+        );
+    }
+
+    #[test]
+    fn test_cors_any_origin_uses_wildcard() {
+        let router = Router::new()
+            .get("/hello", get_hello)
+            .unwrap()
+            .enable_cors(CorsConfig::new());
+        let request = get_request("GET /hello HTTP/1.1", &[("Origin", "https://a.com")]);
+
+        let response = router.handle_request(&request).unwrap();
+
+        assert_eq!(
+            Some("*"),
+            header_value(&response, "Access-Control-Allow-Origin")
+        );
+        assert_eq!(None, header_value(&response, "Vary"));
+    }
+}
@@ -1,9 +1,12 @@
 use anyhow::{bail, Context, Result};
 use std::{
     collections::HashMap,
+    fs,
     path::{Component, Path, PathBuf},
 };
 
+use crate::http::{percent_encoding, HttpRequest, HttpResponse, HttpResponseBuilder};
+
 #[derive(Debug, Hash, PartialEq, Eq)]
 struct MountPoint {
     pub route: String,
@@ -11,9 +14,20 @@ struct MountPoint {
     pub is_directory: bool,
 }
 
+type AccessCheck = fn(&HttpRequest, &Path) -> bool;
+
+type Rewrite = fn(&str) -> Option<String>;
+
 #[derive(Debug)]
 pub struct FileServer {
     mount_points: HashMap<String, MountPoint>,
+    access_check: Option<AccessCheck>,
+    rewrite: Option<Rewrite>,
+    index_files: Vec<String>,
+    default_mime: String,
+    directory_listing: bool,
+    show_hidden_files: bool,
+    redirect_directories: bool,
 }
 
 impl Default for FileServer {
@@ -26,6 +40,89 @@ impl FileServer {
     pub fn new() -> Self {
         Self {
             mount_points: HashMap::new(),
+            access_check: None,
+            rewrite: None,
+            index_files: vec!["index.html".to_owned()],
+            default_mime: "application/octet-stream".to_owned(),
+            directory_listing: false,
+            show_hidden_files: false,
+            redirect_directories: true,
+        }
+    }
+
+    /// Sets the file names looked up, in order, when a request resolves to a directory.
+    ///
+    /// Defaults to `index.html`.
+    pub fn index_files(mut self, index_files: &[&str]) -> Self {
+        self.index_files = index_files.iter().map(|&name| name.to_owned()).collect();
+        self
+    }
+
+    /// When enabled, directories without an index file are served as an HTML listing of their
+    /// entries.
+    pub fn directory_listing(mut self, enabled: bool) -> Self {
+        self.directory_listing = enabled;
+        self
+    }
+
+    /// When enabled, directory listings include hidden files (starting with a `.`).
+    pub fn show_hidden_files(mut self, enabled: bool) -> Self {
+        self.show_hidden_files = enabled;
+        self
+    }
+
+    /// When enabled, a directory requested without a trailing slash is redirected to the path
+    /// ending with one, so that relative links of its index resolve inside the directory.
+    ///
+    /// Enabled by default.
+    pub fn redirect_directories(mut self, enabled: bool) -> Self {
+        self.redirect_directories = enabled;
+        self
+    }
+
+    /// Whether `file` names a directory lacking its trailing slash that should be redirected.
+    pub fn needs_trailing_slash(&self, file: &str) -> bool {
+        self.redirect_directories
+            && !file.ends_with('/')
+            && self
+                .get_file_path(file)
+                .is_ok_and(|file_path| file_path.is_dir())
+    }
+
+    /// Sets the content type used when none can be guessed from the file extension.
+    ///
+    /// Defaults to `application/octet-stream`.
+    pub fn default_mime(mut self, mime: &str) -> Self {
+        self.default_mime = mime.to_owned();
+        self
+    }
+
+    pub fn mime_type(&self, file_path: &Path) -> String {
+        mime_guess::from_path(file_path)
+            .first_raw()
+            .map(|mime| mime.to_owned())
+            .unwrap_or_else(|| self.default_mime.clone())
+    }
+
+    /// Registers a check run before serving any file, access is denied when it returns `false`.
+    pub fn with_access_check(mut self, access_check: AccessCheck) -> Self {
+        self.access_check = Some(access_check);
+        self
+    }
+
+    /// Registers a rewrite of the request path run before it is mapped to the file system,
+    /// returning `None` keeps the path as is.
+    ///
+    /// The rewritten path goes through the same safety checks as the original one.
+    pub fn with_rewrite(mut self, rewrite: Rewrite) -> Self {
+        self.rewrite = Some(rewrite);
+        self
+    }
+
+    pub fn is_access_allowed(&self, request: &HttpRequest, file_path: &Path) -> bool {
+        match self.access_check {
+            Some(access_check) => access_check(request, file_path),
+            None => true,
         }
     }
 
@@ -61,8 +158,16 @@ impl FileServer {
         self.map(route, file_path, false)
     }
 
+    fn is_under_route(file: &str, route: &str) -> bool {
+        route.is_empty()
+            || file
+                .strip_prefix(route)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
     fn get_file_path(&self, file: &str) -> Result<PathBuf> {
-        let file = file.trim_matches('/');
+        let rewritten = self.rewrite.and_then(|rewrite| rewrite(file));
+        let file = rewritten.as_deref().unwrap_or(file).trim_matches('/');
         if !Self::is_safe_relative_subpath(Path::new(file)) {
             bail!("file location is not safe: {file}");
         }
@@ -78,11 +183,13 @@ impl FileServer {
             return Ok(file_path);
         }
 
+        // the most specific mount wins, a root mount (`/`) matches everything
         let dir_mount_point = self
             .mount_points
             .values()
             .filter(|mp| mp.is_directory)
-            .find(|mp| file.starts_with(&mp.route));
+            .filter(|mp| Self::is_under_route(file, &mp.route))
+            .max_by_key(|mp| mp.route.len());
 
         if let Some(dir_mount_point) = dir_mount_point {
             let file_name = file
@@ -108,16 +215,107 @@ impl FileServer {
         Ok(())
     }
 
+    fn find_index_file(&self, dir_path: &Path) -> Option<PathBuf> {
+        self.index_files
+            .iter()
+            .map(|index_file| dir_path.join(index_file))
+            .find(|index_path| index_path.is_file())
+    }
+
+    /// Resolves the file to serve for a request path.
+    ///
+    /// With directory listing enabled, this is a directory when it has no index file.
     pub fn handle_file_access(&self, file: &str) -> Result<PathBuf> {
-        let file_path = self.get_file_path(file)?;
+        let mut file_path = self.get_file_path(file)?;
+        if file_path.is_dir() {
+            match self.find_index_file(&file_path) {
+                Some(index_path) => file_path = index_path,
+                None if self.directory_listing => return Ok(file_path),
+                None => {}
+            }
+        }
+
         Self::validate_file_exists(&file_path)?;
         Ok(file_path)
     }
+
+    /// Builds an HTML page linking to the entries of `dir_path`, which is served at `url`.
+    pub fn render_directory_listing(&self, url: &str, dir_path: &Path) -> Result<HttpResponse> {
+        let mut entries = vec![];
+        for entry in fs::read_dir(dir_path)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') && !self.show_hidden_files {
+                continue;
+            }
+
+            entries.push((name, entry.file_type()?.is_dir()));
+        }
+        entries.sort();
+
+        let segments: Vec<_> = url.split('/').filter(|part| !part.is_empty()).collect();
+        let base: String = segments
+            .iter()
+            .map(|segment| format!("/{}", percent_encoding::encode_path_segment(segment)))
+            .collect();
+        let title = escape_html(&format!("Index of {base}/"));
+
+        let mut body = format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n\
+<body>\n<h1>{title}</h1>\n<ul>\n"
+        );
+
+        if !segments.is_empty() {
+            let parent: String = segments[..segments.len() - 1]
+                .iter()
+                .map(|segment| format!("/{}", percent_encoding::encode_path_segment(segment)))
+                .collect();
+            body.push_str(&format!("<li><a href=\"{parent}/\">../</a></li>\n"));
+        }
+
+        for (name, is_dir) in entries {
+            let suffix = if is_dir { "/" } else { "" };
+            let href = format!(
+                "{base}/{}{suffix}",
+                percent_encoding::encode_path_segment(&name)
+            );
+            body.push_str(&format!(
+                "<li><a href=\"{}\">{}{suffix}</a></li>\n",
+                escape_html(&href),
+                escape_html(&name)
+            ));
+        }
+
+        body.push_str("</ul>\n</body>\n</html>");
+        HttpResponseBuilder::new()
+            .set_raw_body(body.into_bytes())
+            .set_content_type("text/html; charset=utf-8")
+            .build()
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
 }
 
 #[cfg(test)]
 mod tests {
-    use std::path::PathBuf;
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
 
     use super::FileServer;
 
@@ -192,6 +390,24 @@ mod tests {
         assert!(res.is_err());
     }
 
+    fn strip_version(file: &str) -> Option<String> {
+        let rest = file.strip_prefix("/v2/")?;
+        Some(format!("/static/{rest}"))
+    }
+
+    #[test]
+    fn test_get_file_path_rewrite() {
+        let fs = get_dummy_file_server().with_rewrite(strip_version);
+
+        let actual_path = fs.get_file_path("/v2/asset.js").unwrap();
+        assert_eq!(PathBuf::from("assets/asset.js"), actual_path);
+
+        let actual_path = fs.get_file_path("/static/dog.png").unwrap();
+        assert_eq!(PathBuf::from("assets/dog.png"), actual_path);
+
+        assert!(fs.get_file_path("/v2/../../etc/passwd").is_err());
+    }
+
     #[test]
     fn test_get_file_path_dir_map_nesting_ok() {
         let fs = get_dummy_file_server();
@@ -205,4 +421,157 @@ mod tests {
         let actual_path = fs.get_file_path("static/animals/birds/dove.jpeg/").unwrap();
         assert_eq!(PathBuf::from("assets/animals/birds/dove.jpeg"), actual_path)
     }
+
+    #[test]
+    fn test_handle_file_access_index_files_order() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.htm"), "<p>htm index</p>").unwrap();
+
+        let fs = FileServer::new()
+            .map_dir("/site", dir.path().to_str().unwrap())
+            .unwrap()
+            .index_files(&["index.html", "index.htm", "default.html"]);
+
+        let actual_path = fs.handle_file_access("/site/").unwrap();
+        assert_eq!(dir.path().join("index.htm"), actual_path);
+    }
+
+    #[test]
+    fn test_handle_file_access_root_mount_index() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.html"), "<p>home</p>").unwrap();
+
+        let fs = FileServer::new()
+            .map_dir("/", dir.path().to_str().unwrap())
+            .unwrap();
+
+        let actual_path = fs.handle_file_access("/").unwrap();
+        assert_eq!(dir.path().join("index.html"), actual_path);
+    }
+
+    #[test]
+    fn test_handle_file_access_subdirectory_index() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("docs")).unwrap();
+        fs::write(dir.path().join("docs").join("index.html"), "<p>docs</p>").unwrap();
+        fs::create_dir(dir.path().join("empty")).unwrap();
+
+        let fs = FileServer::new()
+            .map_dir("/", dir.path().to_str().unwrap())
+            .unwrap();
+
+        let expected = dir.path().join("docs").join("index.html");
+        assert_eq!(expected, fs.handle_file_access("/docs/").unwrap());
+        assert_eq!(expected, fs.handle_file_access("/docs").unwrap());
+        assert!(fs.handle_file_access("/empty/").is_err());
+    }
+
+    #[test]
+    fn test_get_file_path_most_specific_mount() {
+        let fs = FileServer::new()
+            .map_dir("/", "public/")
+            .unwrap()
+            .map_dir("/static", "assets/")
+            .unwrap();
+
+        assert_eq!(
+            PathBuf::from("assets/dog.png"),
+            fs.get_file_path("/static/dog.png").unwrap()
+        );
+        assert_eq!(
+            PathBuf::from("public/staticfiles/dog.png"),
+            fs.get_file_path("/staticfiles/dog.png").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_handle_file_access_no_index_file_err() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.htm"), "<p>htm index</p>").unwrap();
+
+        let fs = FileServer::new()
+            .map_dir("/site", dir.path().to_str().unwrap())
+            .unwrap();
+
+        assert!(fs.handle_file_access("/site/").is_err());
+    }
+
+    #[test]
+    fn test_mime_type_default_fallback() {
+        let fs = FileServer::new();
+        assert_eq!("text/css", fs.mime_type(Path::new("style.css")));
+        assert_eq!(
+            "application/octet-stream",
+            fs.mime_type(Path::new("data.unknownext"))
+        );
+    }
+
+    #[test]
+    fn test_mime_type_configured_fallback() {
+        let fs = FileServer::new().default_mime("text/plain");
+        assert_eq!("text/plain", fs.mime_type(Path::new("data.unknownext")));
+        assert_eq!("text/plain", fs.mime_type(Path::new("LICENSE")));
+        assert_eq!("image/png", fs.mime_type(Path::new("dog.png")));
+    }
+
+    fn get_listed_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("b file.txt"), "b").unwrap();
+        fs::write(dir.path().join("a&<b>.html"), "a").unwrap();
+        fs::write(dir.path().join(".secret"), "hidden").unwrap();
+        fs::create_dir(dir.path().join("docs")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_handle_file_access_directory_listing() {
+        let dir = get_listed_dir();
+        let fs = FileServer::new()
+            .map_dir("/files", dir.path().to_str().unwrap())
+            .unwrap();
+        assert!(fs.handle_file_access("/files/").is_err());
+
+        let fs = fs.directory_listing(true);
+        assert_eq!(
+            dir.path().to_path_buf(),
+            fs.handle_file_access("/files/").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_render_directory_listing() {
+        let dir = get_listed_dir();
+        let fs = FileServer::new().directory_listing(true);
+
+        let response = fs
+            .render_directory_listing("/my files/", dir.path())
+            .unwrap();
+        let body = String::from_utf8(response.body).unwrap();
+
+        assert_eq!(
+            "text/html; charset=utf-8",
+            response.headers.get("Content-Type").unwrap().value
+        );
+        assert!(body.contains("<title>Index of /my%20files/</title>"));
+        let expected_entries = "<li><a href=\"/\">../</a></li>\n\
+<li><a href=\"/my%20files/a%26%3Cb%3E.html\">a&amp;&lt;b&gt;.html</a></li>\n\
+<li><a href=\"/my%20files/b%20file.txt\">b file.txt</a></li>\n\
+<li><a href=\"/my%20files/docs/\">docs/</a></li>\n";
+        assert!(body.contains(expected_entries));
+        assert!(!body.contains(".secret"));
+    }
+
+    #[test]
+    fn test_render_directory_listing_hidden_files() {
+        let dir = get_listed_dir();
+        let fs = FileServer::new()
+            .directory_listing(true)
+            .show_hidden_files(true);
+
+        let response = fs.render_directory_listing("/", dir.path()).unwrap();
+        let body = String::from_utf8(response.body).unwrap();
+
+        assert!(body.contains("<li><a href=\"/.secret\">.secret</a></li>"));
+        assert!(!body.contains("../"));
+    }
 }
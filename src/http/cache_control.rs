@@ -0,0 +1,30 @@
+use std::fmt::Display;
+
+/// Common `Cache-Control` policies, `max_age` is expressed in seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Never store the response.
+    NoStore,
+    /// Store the response but revalidate it with the server before every use.
+    NoCache,
+    /// Any cache, including shared ones, may store the response.
+    Public { max_age: u64 },
+    /// Only the browser may store the response.
+    Private { max_age: u64 },
+    /// The response never changes, e.g. fingerprinted assets.
+    Immutable { max_age: u64 },
+}
+
+impl Display for CachePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CachePolicy::NoStore => write!(f, "no-store"),
+            CachePolicy::NoCache => write!(f, "no-cache"),
+            CachePolicy::Public { max_age } => write!(f, "public, max-age={max_age}"),
+            CachePolicy::Private { max_age } => write!(f, "private, max-age={max_age}"),
+            CachePolicy::Immutable { max_age } => {
+                write!(f, "public, max-age={max_age}, immutable")
+            }
+        }
+    }
+}
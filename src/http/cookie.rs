@@ -1,13 +1,24 @@
 use anyhow::{bail, Context, Result};
+use base64::{prelude::BASE64_URL_SAFE_NO_PAD, Engine};
 use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
 use log::debug;
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 use std::{
+    borrow::Cow,
     fmt::Display,
     hash::{Hash, Hasher},
     str::FromStr,
 };
 
+use super::http_date;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Separates the value of a signed cookie from its signature.
+const SIGNATURE_SEPARATOR: char = '.';
+
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
 pub enum SameSitePolicy {
     Strict,
@@ -38,13 +49,14 @@ impl Display for SameSitePolicy {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+// `Hash` only uses the name, which stays consistent with the derived `Eq` and `Clone`
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct HttpCookie {
     pub name: String,
     pub value: String,
 
     pub domain: Option<String>,
-    #[serde(skip_serializing, skip_deserializing)]
+    #[serde(default, with = "rfc3339")]
     pub expires: Option<DateTime<Utc>>,
     pub http_only: bool,
     pub max_age: Option<i32>,
@@ -54,6 +66,34 @@ pub struct HttpCookie {
     pub secure: bool,
 }
 
+/// (De)serializes `expires` as an RFC 3339 string, e.g. `2024-10-29T16:56:32+00:00`.
+mod rfc3339 {
+    use chrono::{DateTime, Utc};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        date: &Option<DateTime<Utc>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match date {
+            Some(date) => serializer.serialize_some(&date.to_rfc3339()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<DateTime<Utc>>, D::Error> {
+        let Some(date) = Option::<String>::deserialize(deserializer)? else {
+            return Ok(None);
+        };
+
+        DateTime::parse_from_rfc3339(&date)
+            .map(|date| Some(date.with_timezone(&Utc)))
+            .map_err(D::Error::custom)
+    }
+}
+
 impl Hash for HttpCookie {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.name.hash(state);
@@ -79,6 +119,16 @@ impl HttpCookie {
         }
     }
 
+    /// Cookie telling browsers to delete `name`, with an empty value, `Max-Age=0` and an
+    /// `Expires` in 1970.
+    ///
+    /// Its `Path` and `Domain` must match the ones the cookie was set with.
+    pub fn expired(name: &str) -> HttpCookie {
+        HttpCookie::new(name, "")
+            .set_max_age(Some(0))
+            .set_expires(Some(DateTime::UNIX_EPOCH))
+    }
+
     fn get_attribute(attributes: &[String], attribute: &str) -> Option<String> {
         attributes
             .iter()
@@ -93,17 +143,24 @@ impl HttpCookie {
         }
     }
 
+    /// Parses a date in the RFC 2822 format or in any of the HTTP-date formats, the first that
+    /// succeeds is used.
     fn get_date_attribute(attributes: &[String], attribute: &str) -> Result<Option<DateTime<Utc>>> {
-        match Self::get_str_attribute(attributes, attribute) {
-            Some(str_val) => match DateTime::parse_from_rfc2822(&str_val) {
-                Ok(date) => Ok(Some(date.with_timezone(&Utc))),
-                Err(error) => bail!(
-                    "failed to parse string '{}' to DateTime<Utc>: {}",
-                    str_val,
-                    error
-                ),
-            },
-            None => Ok(None),
+        let Some(str_val) = Self::get_str_attribute(attributes, attribute) else {
+            return Ok(None);
+        };
+
+        if let Ok(date) = DateTime::parse_from_rfc2822(&str_val) {
+            return Ok(Some(date.with_timezone(&Utc)));
+        }
+
+        match http_date::parse(&str_val) {
+            Ok(date) => Ok(Some(date)),
+            Err(error) => bail!(
+                "failed to parse string '{}' to DateTime<Utc>: {}",
+                str_val,
+                error
+            ),
         }
     }
 
@@ -132,7 +189,7 @@ impl HttpCookie {
         for cookie_def in cookie_defs.iter() {
             if let Some((name, value)) = cookie_def
                 .split_once('=')
-                .map(|(n, v)| (n.trim(), v.trim()))
+                .map(|(n, v)| (n.trim(), unquote(v.trim())))
             {
                 let cookie = HttpCookie::new(name, value);
                 cookies.push(cookie);
@@ -226,6 +283,38 @@ impl HttpCookie {
         self
     }
 
+    /// Appends to the value an HMAC-SHA256 signature of the name and value, keyed with
+    /// `secret`, so that [`HttpCookie::verify_signature`] can tell if the client changed it.
+    ///
+    /// The signature is base64url encoded and the value is still checked when sent.
+    pub fn signed(mut self, secret: &[u8]) -> Self {
+        let signature =
+            BASE64_URL_SAFE_NO_PAD.encode(self.mac(secret, &self.value).finalize().into_bytes());
+        self.value = format!("{}{SIGNATURE_SEPARATOR}{signature}", self.value);
+        self
+    }
+
+    /// Returns the cookie with its signature removed, `None` if it is not signed with `secret`
+    /// or was tampered with.
+    pub fn verify_signature(&self, secret: &[u8]) -> Option<HttpCookie> {
+        let (value, signature) = self.value.rsplit_once(SIGNATURE_SEPARATOR)?;
+        let signature = BASE64_URL_SAFE_NO_PAD.decode(signature).ok()?;
+        // `verify_slice` compares in constant time
+        self.mac(secret, value).verify_slice(&signature).ok()?;
+
+        let mut cookie = self.clone();
+        cookie.value = value.to_owned();
+        Some(cookie)
+    }
+
+    fn mac(&self, secret: &[u8], value: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any size");
+        mac.update(self.name.as_bytes());
+        mac.update(b"=");
+        mac.update(value.as_bytes());
+        mac
+    }
+
     fn validate(&self) -> Result<()> {
         if !is_name_valid(&self.name) {
             bail!("invalid characters in cookie name. See MDN: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Set-Cookie");
@@ -241,6 +330,22 @@ impl HttpCookie {
             }
         }
 
+        if self.name.starts_with("__Secure-") && !self.secure {
+            bail!("cookie with the `__Secure-` prefix must have `Secure`");
+        }
+
+        if self.name.starts_with("__Host-") {
+            if !self.secure {
+                bail!("cookie with the `__Host-` prefix must have `Secure`");
+            }
+            if self.domain.is_some() {
+                bail!("cookie with the `__Host-` prefix cannot have a `Domain`");
+            }
+            if self.path.as_deref() != Some("/") {
+                bail!("cookie with the `__Host-` prefix must have `Path=/`");
+            }
+        }
+
         Ok(())
     }
 
@@ -248,14 +353,14 @@ impl HttpCookie {
         self.validate()?;
 
         let mut attributes = Vec::new();
-        attributes.push(format!("{}={}", self.name, self.value));
+        attributes.push(format!("{}={}", self.name, quote_if_needed(&self.value)));
 
         if let Some(domain) = &self.domain {
             attributes.push(format!("Domain={}", domain));
         }
 
         if let Some(expires) = &self.expires {
-            let expires = expires.to_rfc2822();
+            let expires = http_date::format(*expires);
             attributes.push(format!("Expires={}", expires));
         }
 
@@ -287,6 +392,15 @@ impl HttpCookie {
     }
 }
 
+/// Removes the double quotes wrapping a cookie value, if any.
+fn unquote(cookie_value: &str) -> &str {
+    if cookie_value.len() >= 2 && cookie_value.starts_with('"') && cookie_value.ends_with('"') {
+        &cookie_value[1..cookie_value.len() - 1]
+    } else {
+        cookie_value
+    }
+}
+
 fn is_name_valid(cookie_name: &str) -> bool {
     let has_illegal_chars = cookie_name.chars().any(|ch| {
         ch as u8 <= 31 || ch as u8 >= 127 || BANNED_NAME_CHARS.contains(ch) || ch.is_whitespace()
@@ -295,23 +409,34 @@ fn is_name_valid(cookie_name: &str) -> bool {
     cookie_name.is_ascii() && !has_illegal_chars
 }
 
-fn is_value_valid(cookie_value: &str) -> bool {
-    // Remove first and last char if double quotes (allow to be wrapped in double quotes)
-    let cookie_value = if cookie_value.starts_with("\"") && cookie_value.ends_with("\"") {
-        &cookie_value[1..cookie_value.len() - 1]
-    } else {
-        cookie_value
-    };
+/// Wraps a cookie value containing spaces in double quotes, the only form in which browsers keep
+/// them.
+fn quote_if_needed(cookie_value: &str) -> Cow<'_, str> {
+    let is_quoted = unquote(cookie_value).len() != cookie_value.len();
+    match !is_quoted && cookie_value.contains(' ') {
+        true => Cow::Owned(format!("\"{cookie_value}\"")),
+        false => Cow::Borrowed(cookie_value),
+    }
+}
 
-    let has_illegal_chars = cookie_value.chars().any(|ch| {
-        ch as u8 <= 31 || ch as u8 >= 127 || BANNED_VALUE_CHARS.contains(ch) || ch.is_whitespace()
+fn is_value_valid(cookie_value: &str) -> bool {
+    // spaces are sent inside double quotes, see `quote_if_needed`
+    let unquoted = unquote(cookie_value);
+
+    let has_illegal_chars = unquoted.chars().any(|ch| {
+        ch as u8 <= 31
+            || ch as u8 >= 127
+            || BANNED_VALUE_CHARS.contains(ch)
+            || (ch.is_whitespace() && ch != ' ')
     });
 
-    cookie_value.is_ascii() && !has_illegal_chars
+    unquoted.is_ascii() && !has_illegal_chars
 }
 
 #[cfg(test)]
 mod tests {
+    use chrono::TimeZone;
+
     use super::*;
 
     #[test]
@@ -321,6 +446,15 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_cookie_clone_eq() {
+        let cookie = HttpCookie::new("foo", "bar")
+            .set_path(Some("/"))
+            .set_secure(true);
+
+        assert_eq!(cookie, cookie.clone());
+    }
+
     #[test]
     fn test_cookie_name_illegal() {
         assert!(HttpCookie::new("f<oo", "bar").to_str().is_err())
@@ -344,8 +478,8 @@ mod tests {
 
     #[test]
     fn test_cookie_attr_expires() {
-        let expires_str = "Tue, 29 Oct 2024 16:56:32 +0000";
-        let expires = DateTime::parse_from_rfc2822(expires_str)
+        let expires_str = "Tue, 29 Oct 2024 16:56:32 GMT";
+        let expires = DateTime::parse_from_rfc2822("Tue, 29 Oct 2024 16:56:32 +0000")
             .unwrap()
             .with_timezone(&Utc);
 
@@ -358,6 +492,94 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_cookie_json_round_trip_keeps_expires() {
+        let expires = Utc.with_ymd_and_hms(2024, 10, 29, 16, 56, 32).unwrap();
+        let cookie = HttpCookie::new("foo", "bar")
+            .set_expires(Some(expires))
+            .set_path(Some("/"));
+
+        let json = serde_json::to_string(&cookie).unwrap();
+        assert!(json.contains("\"expires\":\"2024-10-29T16:56:32+00:00\""));
+        let actual: HttpCookie = serde_json::from_str(&json).unwrap();
+        assert_eq!(cookie, actual);
+
+        let without_expires = HttpCookie::new("foo", "bar");
+        let json = serde_json::to_string(&without_expires).unwrap();
+        assert_eq!(without_expires, serde_json::from_str(&json).unwrap());
+    }
+
+    #[test]
+    fn test_signed_cookie_verifies() {
+        let secret = b"server secret";
+        let cookie = HttpCookie::new("session", "user-42").signed(secret);
+
+        assert!(cookie.value.starts_with("user-42."));
+        assert!(cookie.to_str().is_ok());
+
+        let verified = cookie.verify_signature(secret).unwrap();
+        assert_eq!(HttpCookie::new("session", "user-42"), verified);
+    }
+
+    #[test]
+    fn test_signed_cookie_tampered_rejected() {
+        let secret = b"server secret";
+        let cookie = HttpCookie::new("session", "user-42").signed(secret);
+        let signature = cookie.value.rsplit_once('.').unwrap().1;
+
+        let tampered = HttpCookie::new("session", &format!("admin-1.{signature}"));
+        assert_eq!(None, tampered.verify_signature(secret));
+
+        let renamed = HttpCookie::new("admin_session", &cookie.value);
+        assert_eq!(None, renamed.verify_signature(secret));
+
+        assert_eq!(None, cookie.verify_signature(b"other secret"));
+        assert_eq!(
+            None,
+            HttpCookie::new("session", "user-42").verify_signature(secret)
+        );
+    }
+
+    #[test]
+    fn test_cookie_host_prefix() {
+        let cookie = HttpCookie::new("__Host-session", "abc")
+            .set_secure(true)
+            .set_path(Some("/"));
+        assert_eq!(
+            "__Host-session=abc; Path=/; Secure",
+            cookie.to_str().unwrap()
+        );
+
+        let error = cookie.set_domain(Some("example.com")).to_str().unwrap_err();
+        assert_eq!(
+            "cookie with the `__Host-` prefix cannot have a `Domain`",
+            error.to_string()
+        );
+
+        let no_path = HttpCookie::new("__Host-session", "abc").set_secure(true);
+        assert!(no_path.to_str().is_err());
+    }
+
+    #[test]
+    fn test_cookie_secure_prefix() {
+        let cookie = HttpCookie::new("__Secure-token", "abc");
+        assert!(cookie.clone().to_str().is_err());
+        assert!(cookie.set_secure(true).to_str().is_ok());
+    }
+
+    #[test]
+    fn test_cookie_expired() {
+        let actual = HttpCookie::expired("session")
+            .set_path(Some("/app"))
+            .to_str()
+            .unwrap();
+
+        assert_eq!(
+            "session=; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0; Path=/app",
+            actual
+        );
+    }
+
     #[test]
     fn test_cookie_attr_http_only_true() {
         let expected = "foo=bar; HttpOnly";
@@ -526,10 +748,11 @@ mod tests {
 
     #[test]
     fn test_cookie_from_request_multi_cookies_same_header_all_valid() {
-        let mut expected = vec![];
-        expected.push(HttpCookie::new("foo", "foov"));
-        expected.push(HttpCookie::new("bar", "barv"));
-        expected.push(HttpCookie::new("baz", "bazv"));
+        let expected = vec![
+            HttpCookie::new("foo", "foov"),
+            HttpCookie::new("bar", "barv"),
+            HttpCookie::new("baz", "bazv"),
+        ];
 
         let actual =
             HttpCookie::from_req_header_cookie_line("foo =foov; bar=barv; baz= bazv  ").unwrap();
@@ -539,9 +762,10 @@ mod tests {
 
     #[test]
     fn test_cookie_from_request_multi_same_header_skips_malformed() {
-        let mut expected = vec![];
-        expected.push(HttpCookie::new("foo", "foov"));
-        expected.push(HttpCookie::new("baz", "bazv"));
+        let expected = vec![
+            HttpCookie::new("foo", "foov"),
+            HttpCookie::new("baz", "bazv"),
+        ];
 
         let actual =
             HttpCookie::from_req_header_cookie_line("foo =foov; b; rrr; baz= bazv  ").unwrap();
@@ -549,6 +773,42 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_cookie_from_request_quoted_value() {
+        let expected = vec![
+            HttpCookie::new("session", "abc def"),
+            HttpCookie::new("foo", "bar"),
+        ];
+
+        let actual =
+            HttpCookie::from_req_header_cookie_line("session=\"abc def\"; foo=\"bar\"").unwrap();
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_cookie_quoted_value_with_spaces_round_trip() {
+        let actual = HttpCookie::from_req_header_cookie_line("id=\"a b c\"").unwrap();
+        assert_eq!(vec![HttpCookie::new("id", "a b c")], actual);
+        assert_eq!("id=\"a b c\"", actual[0].to_str().unwrap());
+
+        // values already quoted are sent as is, banned characters are never allowed
+        assert_eq!(
+            "id=\"a b c\"",
+            HttpCookie::new("id", "\"a b c\"").to_str().unwrap()
+        );
+        assert!(HttpCookie::new("id", "\"a\tb\"").to_str().is_err());
+        assert!(HttpCookie::new("id", "\"a;b c\"").to_str().is_err());
+    }
+
+    #[test]
+    fn test_cookie_from_request_lone_quote_kept() {
+        let expected = vec![HttpCookie::new("foo", "\"")];
+        let actual = HttpCookie::from_req_header_cookie_line("foo=\"").unwrap();
+
+        assert_eq!(expected, actual);
+    }
+
     #[test]
     fn test_cookie_from_set_cookie_header_line_missing_name_val_err() {
         let cookie_line = "HttpOnly; Max-Age=3600";
@@ -579,6 +839,25 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_cookie_from_set_cookie_header_line_expires_formats() {
+        let expected = Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap();
+
+        for expires in [
+            "Wed, 21 Oct 2015 07:28:00 GMT",
+            "Wed, 21 Oct 2015 07:28:00 +0000",
+            "Wednesday, 21-Oct-15 07:28:00 GMT",
+            "Wed Oct 21 07:28:00 2015",
+        ] {
+            let cookie_line = format!("foo=bar; Expires={expires}; Path=/");
+            let actual = HttpCookie::from_set_cookie_header_line(&cookie_line).unwrap();
+            assert_eq!(Some(expected), actual.expires, "{expires}");
+        }
+
+        let cookie_line = "foo=bar; Expires=21/10/2015";
+        assert!(HttpCookie::from_set_cookie_header_line(cookie_line).is_err());
+    }
+
     #[test]
     fn test_cookie_from_set_cookie_header_line_unknown_attr_ok() {
         let expires = DateTime::parse_from_rfc2822("Tue, 29 Oct 2024 16:56:32 +0000")
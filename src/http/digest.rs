@@ -0,0 +1,104 @@
+use base64::{prelude::BASE64_STANDARD, Engine};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+use super::response_status_codes::HttpStatusCode;
+
+/// Why the `Digest` header of a request does not vouch for its body.
+#[derive(Debug)]
+pub enum DigestError {
+    /// The header has no `sha-256` digest, holds the header value.
+    UnsupportedAlgorithm(String),
+    /// The `sha-256` digest is not valid base64.
+    Malformed(String),
+    /// The body does not hash to the digest sent.
+    Mismatch { expected: String, actual: String },
+    /// The body could not be read from the connection.
+    UnreadableBody(anyhow::Error),
+}
+
+impl DigestError {
+    /// Status code of the response to send back for this error.
+    pub fn status_code(&self) -> HttpStatusCode {
+        HttpStatusCode::BadRequest
+    }
+}
+
+impl fmt::Display for DigestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DigestError::UnsupportedAlgorithm(value) => {
+                write!(f, "Digest header has no sha-256 digest: {value}")
+            }
+            DigestError::Malformed(digest) => write!(f, "malformed sha-256 digest: {digest}"),
+            DigestError::Mismatch { expected, actual } => write!(
+                f,
+                "body digest mismatch: expected sha-256={expected} but got sha-256={actual}"
+            ),
+            DigestError::UnreadableBody(error) => write!(f, "cannot read body to digest: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for DigestError {}
+
+/// Value of a `Digest` header for `body`, e.g. `sha-256=X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=`.
+pub fn sha256_header_value(body: &[u8]) -> String {
+    format!("sha-256={}", BASE64_STANDARD.encode(Sha256::digest(body)))
+}
+
+/// Checks `body` against the `sha-256` entry of a `Digest` header value, other algorithms are
+/// ignored.
+pub fn verify(header_value: &str, body: &[u8]) -> Result<(), DigestError> {
+    let expected = header_value
+        .split(',')
+        .filter_map(|entry| entry.trim().split_once('='))
+        .find(|(algorithm, _)| algorithm.trim().eq_ignore_ascii_case("sha-256"))
+        .map(|(_, digest)| digest.trim())
+        .ok_or_else(|| DigestError::UnsupportedAlgorithm(header_value.to_owned()))?;
+
+    let expected_bytes = BASE64_STANDARD
+        .decode(expected)
+        .map_err(|_| DigestError::Malformed(expected.to_owned()))?;
+    let actual = Sha256::digest(body);
+    if expected_bytes != actual.as_slice() {
+        return Err(DigestError::Mismatch {
+            expected: expected.to_owned(),
+            actual: BASE64_STANDARD.encode(actual),
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256_header_value() {
+        assert_eq!(
+            "sha-256=X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=",
+            sha256_header_value(b"{\"hello\": \"world\"}")
+        );
+    }
+
+    #[test]
+    fn test_verify() {
+        let body = b"{\"hello\": \"world\"}";
+        assert!(verify(
+            "md5=irrelevant, SHA-256=X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=",
+            body
+        )
+        .is_ok());
+
+        assert!(matches!(
+            verify("md5=HUXZLQLMuI/KZ5KDcJPcOA==", body),
+            Err(DigestError::UnsupportedAlgorithm(_))
+        ));
+        assert!(matches!(
+            verify("sha-256=not base64!", body),
+            Err(DigestError::Malformed(_))
+        ));
+    }
+}
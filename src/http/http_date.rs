@@ -0,0 +1,75 @@
+use anyhow::{bail, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+/// Preferred HTTP-date format, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+const IMF_FIXDATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Obsolete format still accepted, e.g. `Sunday, 06-Nov-94 08:49:37 GMT`.
+const RFC_850: &str = "%A, %d-%b-%y %H:%M:%S GMT";
+
+/// Format of C's `asctime()`, e.g. `Sun Nov  6 08:49:37 1994`.
+const ASCTIME: &str = "%a %b %e %H:%M:%S %Y";
+
+/// Parses an HTTP-date in any of the three formats recipients must accept.
+pub fn parse(value: &str) -> Result<DateTime<Utc>> {
+    let value = value.trim();
+    for format in [IMF_FIXDATE, RFC_850, ASCTIME] {
+        if let Ok(date) = NaiveDateTime::parse_from_str(value, format) {
+            return Ok(date.and_utc());
+        }
+    }
+
+    bail!("invalid HTTP date: {value}")
+}
+
+/// Formats `date` as an IMF-fixdate, the only format HTTP senders should use.
+pub fn format(date: DateTime<Utc>) -> String {
+    date.format(IMF_FIXDATE).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn get_expected() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(1994, 11, 6, 8, 49, 37).unwrap()
+    }
+
+    #[test]
+    fn test_parse_imf_fixdate() {
+        assert_eq!(
+            get_expected(),
+            parse("Sun, 06 Nov 1994 08:49:37 GMT").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_rfc_850() {
+        assert_eq!(
+            get_expected(),
+            parse("Sunday, 06-Nov-94 08:49:37 GMT").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_asctime() {
+        assert_eq!(get_expected(), parse("Sun Nov  6 08:49:37 1994").unwrap());
+    }
+
+    #[test]
+    fn test_parse_invalid_err() {
+        assert!(parse("06/11/1994").is_err());
+        assert!(parse("Sun, 06 Nov 1994 08:49:37 +0100").is_err());
+        // the day of the week must match the date
+        assert!(parse("Mon, 06 Nov 1994 08:49:37 GMT").is_err());
+    }
+
+    #[test]
+    fn test_format_round_trip() {
+        let formatted = format(get_expected());
+        assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT", formatted);
+        assert_eq!(get_expected(), parse(&formatted).unwrap());
+    }
+}
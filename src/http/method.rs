@@ -2,7 +2,7 @@ use anyhow::bail;
 use serde::{Deserialize, Serialize};
 use std::{fmt::Display, str::FromStr};
 
-#[derive(Hash, PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize, Clone)]
 pub enum HttpMethod {
     GET,
     HEAD,
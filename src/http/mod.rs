@@ -1,21 +1,42 @@
+pub mod cache_control;
 pub mod cookie;
+pub mod digest;
 pub mod header;
+pub mod http_date;
 pub mod method;
 pub mod multipart;
+pub mod percent_encoding;
+pub mod range;
 pub mod request;
+pub mod request_body;
 pub mod request_raw;
 pub mod response;
 pub mod response_builder;
 pub mod response_status_codes;
+pub mod sse;
 pub mod version;
+pub mod websocket;
 
+pub use self::cache_control::CachePolicy;
 pub use self::cookie::HttpCookie;
+pub use self::digest::DigestError;
 pub use self::header::HttpHeader;
 pub use self::method::HttpMethod;
 pub use self::multipart::MultipartBody;
 pub use self::multipart::MultipartBodyPart;
+pub use self::multipart::MultipartConfig;
+pub use self::multipart::MultipartError;
 pub use self::request::HttpRequest;
+pub use self::request_body::RequestBody;
+pub use self::request_body::SharedReader;
 pub use self::request_raw::HttpRequestRaw;
+pub use self::request_raw::RequestReadConfig;
+pub use self::request_raw::RequestReadError;
+pub use self::response::BodyStream;
 pub use self::response::HttpResponse;
 pub use self::response_builder::HttpResponseBuilder;
+pub use self::sse::EventSender;
+pub use self::sse::EventStream;
+pub use self::sse::SseEvent;
 pub use self::version::HttpVersion;
+pub use self::websocket::WebSocket;
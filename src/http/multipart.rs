@@ -1,6 +1,88 @@
 use anyhow::{bail, Context, Result};
-use log::{trace, warn};
-use std::io::{BufRead, BufReader, Cursor, Read};
+use log::trace;
+use std::fmt;
+
+use super::response_status_codes::HttpStatusCode;
+
+/// Why a request body could not be read as multipart.
+#[derive(Debug)]
+pub enum MultipartError {
+    /// The request is not `multipart/form-data`, holds the Content-Type received if any.
+    NotMultipart(Option<String>),
+    /// The request claims to be multipart but its Content-Type or body cannot be parsed.
+    Malformed(anyhow::Error),
+    /// A text field, i.e. a part without filename, is larger than allowed.
+    FieldTooLarge {
+        name: String,
+        length: usize,
+        limit: usize,
+    },
+}
+
+/// Default size limit of a multipart text field: 64 KiB.
+pub const DEFAULT_MAX_FIELD_SIZE: usize = 64 * 1024;
+
+/// Limits applied when parsing a multipart body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MultipartConfig {
+    /// Maximum size of a text field, file parts are only bounded by the request body size.
+    pub max_field_size: usize,
+}
+
+impl Default for MultipartConfig {
+    fn default() -> Self {
+        Self {
+            max_field_size: DEFAULT_MAX_FIELD_SIZE,
+        }
+    }
+}
+
+impl MultipartError {
+    /// Status code of the response to send back for this error.
+    pub fn status_code(&self) -> HttpStatusCode {
+        match self {
+            MultipartError::NotMultipart(_) => HttpStatusCode::UnsupportedMediaType,
+            MultipartError::Malformed(_) => HttpStatusCode::BadRequest,
+            MultipartError::FieldTooLarge { .. } => HttpStatusCode::ContentTooLarge,
+        }
+    }
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::NotMultipart(Some(content_type)) => {
+                write!(
+                    f,
+                    "expected a multipart/form-data body but got: {content_type}"
+                )
+            }
+            MultipartError::NotMultipart(None) => {
+                write!(
+                    f,
+                    "cannot process multipart body because Content-Type header is missing"
+                )
+            }
+            MultipartError::Malformed(error) => write!(f, "malformed multipart body: {error}"),
+            MultipartError::FieldTooLarge {
+                name,
+                length,
+                limit,
+            } => write!(
+                f,
+                "multipart field '{name}' is {length} bytes, more than the {limit} bytes allowed"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MultipartError {}
+
+impl From<anyhow::Error> for MultipartError {
+    fn from(error: anyhow::Error) -> Self {
+        MultipartError::Malformed(error)
+    }
+}
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct MultipartBody {
@@ -17,64 +99,105 @@ pub struct MultipartBodyPart {
 
 impl MultipartBody {
     pub fn from_bytes(boundary: &str, bytes: &[u8]) -> Result<MultipartBody> {
-        warn!("for now, only single part multipart body is supported... I know, that does not make sense");
-        let cursor = Cursor::new(bytes);
-        let mut reader = BufReader::new(cursor);
+        Ok(Self::from_bytes_with_config(
+            boundary,
+            bytes,
+            &MultipartConfig::default(),
+        )?)
+    }
 
-        let actual_boundary = format!("--{}", boundary);
+    pub fn from_bytes_with_config(
+        boundary: &str,
+        bytes: &[u8],
+        config: &MultipartConfig,
+    ) -> Result<MultipartBody, MultipartError> {
+        // the CRLF preceding a boundary is part of the delimiter, not of the part data
+        let delimiter = format!("\r\n--{}", boundary);
+        let delimiter = delimiter.as_bytes();
+        let dash_boundary = &delimiter[2..];
 
-        let mut read_boundary = String::new();
-        let bytes_read = reader.read_line(&mut read_boundary)?;
-        trace!("read boundary: {read_boundary:?} ({bytes_read} bytes)");
-        if !read_boundary.trim().eq(&actual_boundary) {
-            bail!(
-                "boundaries do not match: expected '{actual_boundary}' but got '{read_boundary}'"
-            );
-        }
+        let mut position = if bytes.starts_with(dash_boundary) {
+            0
+        } else {
+            find_bytes(bytes, delimiter, 0).with_context(|| {
+                format!("boundaries do not match: expected '--{boundary}' in multipart body")
+            })? + 2
+        };
 
-        let mut content_disposition = String::new();
-        let bytes_read = reader.read_line(&mut content_disposition)?;
-        trace!("read content disposition: {content_disposition} ({bytes_read} bytes)");
-        let content_disposition = ContentDispositionHeader::from_line(&content_disposition)?;
-        trace!("parse content_disposition: {content_disposition:?}");
+        let mut parts = vec![];
+        loop {
+            let after_boundary = position + dash_boundary.len();
+            if bytes[after_boundary..].starts_with(b"--") {
+                trace!("read closing boundary, parsed {} parts", parts.len());
+                break;
+            }
 
-        let mut content_type = String::new();
-        let bytes_read = reader.read_line(&mut content_type)?;
-        trace!("read content type: {content_type} ({bytes_read} bytes)");
-        let content_type = content_type
-            .strip_prefix("Content-Type:")
-            .context("expected Content-Type prefix")?
-            .replace('"', "")
-            .trim()
-            .to_owned();
-
-        let mut empty_line = String::new();
-        let bytes_read = reader.read_line(&mut empty_line)?;
-        trace!("read empty line: {bytes_read} bytes");
-        if !empty_line.trim().is_empty() {
-            bail!("expected to read an empty line but got: {empty_line}");
-        }
+            let part_start = find_bytes(bytes, b"\r\n", after_boundary)
+                .context("expected CRLF after boundary")?
+                + 2;
+            let part_end = find_bytes(bytes, delimiter, part_start)
+                .context("multipart body is missing its closing boundary")?;
 
-        let mut buffer = Vec::new();
-        let bytes_read = reader.read_to_end(&mut buffer)?;
-        trace!("read to end: {bytes_read} bytes");
+            let part = MultipartBodyPart::from_bytes(&bytes[part_start..part_end])?;
+            if part.filename.is_none() && part.data.len() > config.max_field_size {
+                return Err(MultipartError::FieldTooLarge {
+                    name: part.name,
+                    length: part.data.len(),
+                    limit: config.max_field_size,
+                });
+            }
 
-        let end_boundary = format!("{}--", actual_boundary);
-        buffer.truncate(buffer.len() - end_boundary.len());
+            parts.push(part);
+            position = part_end + 2;
+        }
 
-        let res = MultipartBody {
-            parts: vec![MultipartBodyPart {
-                name: content_disposition.form_name,
-                filename: content_disposition.filename,
-                content_type,
-                data: buffer,
-            }],
+        Ok(MultipartBody { parts })
+    }
+}
+
+impl MultipartBodyPart {
+    fn from_bytes(bytes: &[u8]) -> Result<MultipartBodyPart> {
+        let (head, data) = match bytes.strip_prefix(b"\r\n") {
+            Some(data) => (&[][..], data),
+            None => {
+                let head_end = find_bytes(bytes, b"\r\n\r\n", 0)
+                    .context("expected an empty line between part headers and data")?;
+                (&bytes[..head_end], &bytes[head_end + 4..])
+            }
         };
 
-        Ok(res)
+        let mut content_disposition = None;
+        let mut content_type = None;
+        for line in std::str::from_utf8(head)?.split("\r\n") {
+            trace!("read part header: {line}");
+            if line.starts_with("Content-Disposition:") {
+                content_disposition = Some(ContentDispositionHeader::from_line(line)?);
+            } else if let Some(value) = line.strip_prefix("Content-Type:") {
+                content_type = Some(value.replace('"', "").trim().to_owned());
+            }
+        }
+
+        let content_disposition =
+            content_disposition.context("expected Content-Disposition header in part")?;
+        trace!("parse content_disposition: {content_disposition:?}");
+
+        Ok(MultipartBodyPart {
+            name: content_disposition.form_name,
+            filename: content_disposition.filename,
+            content_type: content_type.unwrap_or("text/plain".to_owned()),
+            data: data.to_vec(),
+        })
     }
 }
 
+fn find_bytes(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|idx| idx + from)
+}
+
 #[derive(Debug)]
 pub struct ContentDispositionHeader {
     pub form_name: String,
@@ -135,21 +258,21 @@ mod tests {
     #[test]
     fn test_multipart_body_single_text_part_ok() {
         let boundary = "ExampleBoundaryString";
-        let body = "--ExampleBoundaryString
-Content-Disposition: form-data; name=\"description\"
-Content-Type: text/html
-
-This is a description
+        let body = "--ExampleBoundaryString\r\n\
+Content-Disposition: form-data; name=\"description\"\r\n\
+Content-Type: text/html\r\n\
+\r\n\
+This is a description\r\n\
 --ExampleBoundaryString--"
             .as_bytes();
 
-        let actual = MultipartBody::from_bytes(boundary, &body).unwrap();
+        let actual = MultipartBody::from_bytes(boundary, body).unwrap();
         let expected = MultipartBody {
             parts: vec![MultipartBodyPart {
                 name: "description".to_owned(),
                 filename: None,
                 content_type: "text/html".to_owned(),
-                data: "This is a description\n".as_bytes().to_vec(),
+                data: "This is a description".as_bytes().to_vec(),
             }],
         };
 
@@ -157,22 +280,114 @@ This is a description
     }
 
     #[test]
-    // TODO: This test checks for err because Multipart support right now is only with single part
-    // In normal situation, the body in this function would denote a valid Multipart body
-    fn test_mutlipart_body_multiple_parts_is_err() {
-        let boundary = "--delimiter123";
-        let body = "
---delimiter123
-Content-Disposition: form-data; name=\"field1\"
-
-value1
---delimiter123
-Content-Disposition: form-data; name=\"field2\"; filename=\"example.txt\"
-
-value2
---delimiter123--"
+    fn test_multipart_body_multiple_parts_ok() {
+        let boundary = "delimiter123";
+        let body = "preamble to ignore\r\n\
+--delimiter123\r\n\
+Content-Disposition: form-data; name=\"field1\"\r\n\
+\r\n\
+value1\r\n\
+--delimiter123\r\n\
+Content-Disposition: form-data; name=\"field2\"; filename=\"example.txt\"\r\n\
+Content-Type: text/markdown\r\n\
+\r\n\
+value2\r\n\r\n\
+--delimiter123--\r\n"
+            .as_bytes();
+
+        let actual = MultipartBody::from_bytes(boundary, body).unwrap();
+        let expected = MultipartBody {
+            parts: vec![
+                MultipartBodyPart {
+                    name: "field1".to_owned(),
+                    filename: None,
+                    content_type: "text/plain".to_owned(),
+                    data: "value1".as_bytes().to_vec(),
+                },
+                MultipartBodyPart {
+                    name: "field2".to_owned(),
+                    filename: Some("example.txt".to_owned()),
+                    content_type: "text/markdown".to_owned(),
+                    data: "value2\r\n".as_bytes().to_vec(),
+                },
+            ],
+        };
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_multipart_body_binary_round_trip() {
+        let boundary = "BinaryBoundary";
+        let png_like: Vec<u8> = vec![
+            0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', 0x00, 0xff, b'-', b'-', b'\r',
+            b'\n', b'\r', b'\n', 0xfe,
+        ];
+
+        let mut body = "--BinaryBoundary\r\n\
+Content-Disposition: form-data; name=\"image\"; filename=\"dot.png\"\r\n\
+Content-Type: image/png\r\n\r\n"
+            .as_bytes()
+            .to_vec();
+        body.extend_from_slice(&png_like);
+        body.extend_from_slice(b"\r\n--BinaryBoundary--\r\n");
+
+        let actual = MultipartBody::from_bytes(boundary, &body).unwrap();
+        assert_eq!(1, actual.parts.len());
+        assert_eq!("image/png", actual.parts[0].content_type);
+        assert_eq!(png_like, actual.parts[0].data);
+    }
+
+    #[test]
+    fn test_multipart_body_text_field_too_large_err() {
+        let config = MultipartConfig { max_field_size: 8 };
+        let body = "--foo\r\n\
+Content-Disposition: form-data; name=\"short\"\r\n\
+\r\n\
+12345678\r\n\
+--foo\r\n\
+Content-Disposition: form-data; name=\"upload\"; filename=\"a.txt\"\r\n\
+\r\n\
+file parts are not limited\r\n\
+--foo--"
+            .as_bytes();
+        let actual = MultipartBody::from_bytes_with_config("foo", body, &config).unwrap();
+        assert_eq!(2, actual.parts.len());
+
+        let body = "--foo\r\n\
+Content-Disposition: form-data; name=\"long\"\r\n\
+\r\n\
+123456789\r\n\
+--foo--"
+            .as_bytes();
+        let error = MultipartBody::from_bytes_with_config("foo", body, &config).unwrap_err();
+        assert!(matches!(
+            &error,
+            MultipartError::FieldTooLarge { name, length: 9, limit: 8 } if name == "long"
+        ));
+        assert_eq!(HttpStatusCode::ContentTooLarge, error.status_code());
+    }
+
+    #[test]
+    fn test_multipart_body_wrong_boundary_err() {
+        let body = "--foo\r\n\
+Content-Disposition: form-data; name=\"field1\"\r\n\
+\r\n\
+value1\r\n\
+--foo--"
+            .as_bytes();
+
+        assert!(MultipartBody::from_bytes("bar", body).is_err());
+    }
+
+    #[test]
+    fn test_multipart_body_missing_closing_boundary_err() {
+        let body = "--foo\r\n\
+Content-Disposition: form-data; name=\"field1\"\r\n\
+\r\n\
+value1"
             .as_bytes();
 
-        assert!(MultipartBody::from_bytes(boundary, &body).is_err());
+        assert!(MultipartBody::from_bytes("foo", body).is_err());
     }
 }
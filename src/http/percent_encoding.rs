@@ -0,0 +1,115 @@
+use anyhow::{bail, Result};
+
+/// Decodes `%XX` sequences, as used in the path of a request target.
+pub fn decode(input: &str) -> Result<String> {
+    decode_impl(input, false)
+}
+
+/// Decodes `%XX` sequences and `+` as a space, as used in a query string.
+pub fn decode_query_component(input: &str) -> Result<String> {
+    decode_impl(input, true)
+}
+
+/// Encodes everything but unreserved characters, so that the result can be used as a single
+/// path segment.
+pub fn encode_path_segment(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+
+    encoded
+}
+
+fn decode_impl(input: &str, plus_as_space: bool) -> Result<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut idx = 0;
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'%' => {
+                let high = bytes.get(idx + 1).and_then(|&b| hex_value(b));
+                let low = bytes.get(idx + 2).and_then(|&b| hex_value(b));
+                match (high, low) {
+                    (Some(high), Some(low)) => decoded.push(high << 4 | low),
+                    _ => bail!("malformed percent-encoding at position {idx} in: {input}"),
+                }
+                idx += 3;
+            }
+            b'+' if plus_as_space => {
+                decoded.push(b' ');
+                idx += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                idx += 1;
+            }
+        }
+    }
+
+    match String::from_utf8(decoded) {
+        Ok(value) => Ok(value),
+        Err(error) => bail!("percent-decoded value is not valid UTF-8 ({error}): {input}"),
+    }
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|value| value as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_plain() {
+        assert_eq!("/api/weather", decode("/api/weather").unwrap());
+    }
+
+    #[test]
+    fn test_decode_escapes() {
+        assert_eq!("hello world/é", decode("hello%20world%2F%C3%A9").unwrap());
+    }
+
+    #[test]
+    fn test_decode_keeps_plus_in_path() {
+        assert_eq!("a+b", decode("a+b").unwrap());
+    }
+
+    #[test]
+    fn test_decode_query_component_plus_as_space() {
+        assert_eq!(
+            "This is a+query",
+            decode_query_component("This+is+a%2Bquery").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_decode_malformed_err() {
+        assert!(decode("%ZZ").is_err());
+        assert!(decode("abc%2").is_err());
+        assert!(decode("%+1").is_err());
+    }
+
+    #[test]
+    fn test_decode_invalid_utf8_err() {
+        assert!(decode("%FF").is_err());
+    }
+
+    #[test]
+    fn test_encode_path_segment() {
+        assert_eq!(
+            "report-2024_v1.pdf",
+            encode_path_segment("report-2024_v1.pdf")
+        );
+        assert_eq!("my%20file%2Fa%3Fb", encode_path_segment("my file/a?b"));
+        assert_eq!("%C3%A9t%C3%A9", encode_path_segment("été"));
+        assert_eq!("été", decode(&encode_path_segment("été")).unwrap());
+    }
+}
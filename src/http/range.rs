@@ -0,0 +1,294 @@
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+
+/// Most ranges accepted in a `Range` header, requests with more are answered with the whole
+/// resource.
+pub const MAX_RANGES: usize = 16;
+
+/// Parsed value of a `Range` request header, e.g. `bytes=0-99,200-299`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RangeSpec {
+    ranges: Vec<ByteRangeSpec>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum ByteRangeSpec {
+    FromTo(u64, u64),
+    From(u64),
+    Suffix(u64),
+}
+
+/// Satisfiable byte range, both bounds are inclusive.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn content_range(&self, total_len: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total_len)
+    }
+
+    /// Number of bytes covered, both ends being inclusive.
+    pub fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn slice<'a>(&self, bytes: &'a [u8]) -> &'a [u8] {
+        &bytes[self.start as usize..=self.end as usize]
+    }
+}
+
+impl RangeSpec {
+    pub fn parse(header_value: &str) -> Result<RangeSpec> {
+        let ranges = header_value
+            .trim()
+            .strip_prefix("bytes=")
+            .context("only `bytes` ranges are supported")?;
+
+        let ranges = ranges
+            .split(',')
+            .map(|range| Self::parse_range(range.trim()))
+            .collect::<Result<Vec<_>>>()?;
+        if ranges.len() > MAX_RANGES {
+            bail!("{} ranges exceed the limit of {MAX_RANGES}", ranges.len());
+        }
+
+        Ok(RangeSpec { ranges })
+    }
+
+    fn parse_range(range: &str) -> Result<ByteRangeSpec> {
+        let (start, end) = range
+            .split_once('-')
+            .with_context(|| format!("range should have format `start-end`: {range}"))?;
+
+        Ok(match (start.trim(), end.trim()) {
+            ("", "") => bail!("range should have a start or an end: {range}"),
+            ("", suffix) => ByteRangeSpec::Suffix(suffix.parse()?),
+            (start, "") => ByteRangeSpec::From(start.parse()?),
+            (start, end) => {
+                let (start, end) = (start.parse()?, end.parse()?);
+                if start > end {
+                    bail!("range start is after its end: {range}");
+                }
+                ByteRangeSpec::FromTo(start, end)
+            }
+        })
+    }
+
+    /// Resolves the ranges against a resource of `total_len` bytes, dropping unsatisfiable ones.
+    /// Overlapping and adjacent ranges are merged, so no byte is sent twice, and the result is
+    /// sorted.
+    ///
+    /// An empty result means the whole header is unsatisfiable (416).
+    pub fn resolve(&self, total_len: u64) -> Vec<ByteRange> {
+        let mut ranges: Vec<_> = self
+            .ranges
+            .iter()
+            .filter_map(|range| match *range {
+                ByteRangeSpec::FromTo(start, end) if start < total_len => Some(ByteRange {
+                    start,
+                    end: end.min(total_len - 1),
+                }),
+                ByteRangeSpec::From(start) if start < total_len => Some(ByteRange {
+                    start,
+                    end: total_len - 1,
+                }),
+                ByteRangeSpec::Suffix(len) if len > 0 && total_len > 0 => Some(ByteRange {
+                    start: total_len.saturating_sub(len),
+                    end: total_len - 1,
+                }),
+                _ => None,
+            })
+            .collect();
+        ranges.sort_by_key(|range| range.start);
+
+        let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end.saturating_add(1) => {
+                    last.end = last.end.max(range.end);
+                }
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+}
+
+/// Layout of a `multipart/byteranges` body, so that it can be sent without holding the ranges
+/// in memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultipartByteranges {
+    pub boundary: String,
+    /// Head of each part, written before the bytes of its range.
+    pub parts: Vec<(String, ByteRange)>,
+}
+
+impl MultipartByteranges {
+    pub fn new(ranges: &[ByteRange], content_type: &str, total_len: u64) -> Self {
+        let boundary = format!(
+            "rtfw_byteranges_{:x}",
+            Utc::now().timestamp_nanos_opt().unwrap_or(0)
+        );
+
+        let parts = ranges
+            .iter()
+            .map(|range| {
+                let part_head = format!(
+                    "--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                    boundary,
+                    content_type,
+                    range.content_range(total_len)
+                );
+                (part_head, *range)
+            })
+            .collect();
+
+        Self { boundary, parts }
+    }
+
+    /// Delimiter ending the body, after the last part.
+    pub fn closing(&self) -> String {
+        format!("--{}--\r\n", self.boundary)
+    }
+
+    /// Size of the whole body, each range being followed by a CRLF.
+    pub fn content_length(&self) -> u64 {
+        let parts: u64 = self
+            .parts
+            .iter()
+            .map(|(part_head, range)| part_head.len() as u64 + range.length() + 2)
+            .sum();
+        parts + self.closing().len() as u64
+    }
+
+    /// Builds the body from the whole resource held in `bytes`.
+    pub fn to_bytes(&self, bytes: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (part_head, range) in &self.parts {
+            body.extend_from_slice(part_head.as_bytes());
+            body.extend_from_slice(range.slice(bytes));
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(self.closing().as_bytes());
+        body
+    }
+}
+
+/// Builds a `multipart/byteranges` body, returns the boundary used along with the body.
+pub fn to_multipart_byteranges(
+    bytes: &[u8],
+    ranges: &[ByteRange],
+    content_type: &str,
+) -> (String, Vec<u8>) {
+    let multipart = MultipartByteranges::new(ranges, content_type, bytes.len() as u64);
+    let body = multipart.to_bytes(bytes);
+    (multipart.boundary, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_single_range() {
+        let expected = RangeSpec {
+            ranges: vec![ByteRangeSpec::FromTo(0, 99)],
+        };
+        assert_eq!(expected, RangeSpec::parse("bytes=0-99").unwrap());
+    }
+
+    #[test]
+    fn test_parse_multiple_ranges() {
+        let expected = RangeSpec {
+            ranges: vec![
+                ByteRangeSpec::FromTo(0, 99),
+                ByteRangeSpec::From(200),
+                ByteRangeSpec::Suffix(50),
+            ],
+        };
+        assert_eq!(expected, RangeSpec::parse("bytes=0-99, 200-,-50").unwrap());
+    }
+
+    #[test]
+    fn test_parse_invalid_err() {
+        assert!(RangeSpec::parse("items=0-99").is_err());
+        assert!(RangeSpec::parse("bytes=99-0").is_err());
+        assert!(RangeSpec::parse("bytes=-").is_err());
+        assert!(RangeSpec::parse("bytes=abc-def").is_err());
+    }
+
+    #[test]
+    fn test_resolve_empty_resource_unsatisfiable() {
+        for header in ["bytes=0-0", "bytes=0-", "bytes=-5", "bytes=-0"] {
+            let spec = RangeSpec::parse(header).unwrap();
+            assert!(spec.resolve(0).is_empty(), "{header}");
+        }
+    }
+
+    #[test]
+    fn test_resolve_clamps_and_drops_unsatisfiable() {
+        let spec = RangeSpec::parse("bytes=0-99,900-2000,-100,5000-").unwrap();
+        let expected = vec![
+            ByteRange { start: 0, end: 99 },
+            ByteRange {
+                start: 900,
+                end: 999,
+            },
+        ];
+
+        assert_eq!(expected, spec.resolve(1000));
+    }
+
+    #[test]
+    fn test_resolve_merges_overlapping_and_adjacent() {
+        let spec = RangeSpec::parse("bytes=500-599,0-,0-,100-199,200-299").unwrap();
+        assert_eq!(vec![ByteRange { start: 0, end: 999 }], spec.resolve(1000));
+
+        let spec = RangeSpec::parse("bytes=200-299,0-99,100-149,90-120,400-").unwrap();
+        let expected = vec![
+            ByteRange { start: 0, end: 149 },
+            ByteRange {
+                start: 200,
+                end: 299,
+            },
+            ByteRange {
+                start: 400,
+                end: 999,
+            },
+        ];
+        assert_eq!(expected, spec.resolve(1000));
+    }
+
+    #[test]
+    fn test_parse_too_many_ranges_err() {
+        let header = format!("bytes={}", vec!["0-"; MAX_RANGES].join(","));
+        assert!(RangeSpec::parse(&header).is_ok());
+
+        let header = format!("bytes={}", vec!["0-"; MAX_RANGES + 1].join(","));
+        assert!(RangeSpec::parse(&header).is_err());
+    }
+
+    #[test]
+    fn test_to_multipart_byteranges() {
+        let bytes = "0123456789".as_bytes();
+        let ranges = [
+            ByteRange { start: 0, end: 1 },
+            ByteRange { start: 8, end: 9 },
+        ];
+
+        let (boundary, body) = to_multipart_byteranges(bytes, &ranges, "text/plain");
+        let expected = format!(
+            "--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\r\n\
+--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\r\n\
+--{boundary}--\r\n"
+        );
+
+        assert_eq!(expected.as_bytes(), body);
+
+        let multipart = MultipartByteranges::new(&ranges, "text/plain", 10);
+        assert_eq!(body.len() as u64, multipart.content_length());
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::{bail, Result};
+use log::trace;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    fmt,
+    io::{self, BufRead, Read},
+    sync::{Arc, Mutex, OnceLock, PoisonError},
+};
+
+/// Connection reader shared between the server and the body of the request being handled.
+pub type SharedReader = Arc<Mutex<dyn BufRead + Send>>;
+
+/// Body of a request, read from the connection the first time it is accessed.
+#[derive(Default)]
+pub struct RequestBody {
+    bytes: OnceLock<Vec<u8>>,
+    pending: Mutex<Option<PendingBody>>,
+}
+
+/// Body bytes still waiting on the connection.
+struct PendingBody {
+    reader: SharedReader,
+    length: usize,
+}
+
+impl RequestBody {
+    /// Creates a body that has already been read.
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes: OnceLock::from(bytes),
+            pending: Mutex::new(None),
+        }
+    }
+
+    /// Creates a body of `length` bytes left to be read from `reader`.
+    pub fn lazy(reader: SharedReader, length: usize) -> Self {
+        Self {
+            bytes: OnceLock::new(),
+            pending: Mutex::new(Some(PendingBody { reader, length })),
+        }
+    }
+
+    /// Returns the body, reading it from the connection if needed.
+    pub fn bytes(&self) -> Result<&[u8]> {
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(bytes) = self.bytes.get() {
+            return Ok(bytes);
+        }
+
+        let bytes = match pending.take() {
+            Some(pending_body) => pending_body.read()?,
+            None => Vec::new(),
+        };
+        Ok(self.bytes.get_or_init(|| bytes))
+    }
+
+    /// Returns the body only if it has already been read.
+    pub fn loaded(&self) -> Option<&[u8]> {
+        self.bytes.get().map(Vec::as_slice)
+    }
+
+    /// Discards the body if it has not been read, so that the connection is positioned at the
+    /// start of the next request.
+    pub fn drain(&self) -> Result<()> {
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(pending_body) = pending.take() {
+            pending_body.discard()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl PendingBody {
+    fn read(self) -> Result<Vec<u8>> {
+        trace!("read body ({} bytes)", self.length);
+        let mut reader = self.reader.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut body = vec![0; self.length];
+        reader.read_exact(&mut body)?;
+        Ok(body)
+    }
+
+    fn discard(self) -> Result<()> {
+        trace!("discard unread body ({} bytes)", self.length);
+        let mut reader = self.reader.lock().unwrap_or_else(PoisonError::into_inner);
+        let discarded = io::copy(
+            &mut (&mut *reader).take(self.length as u64),
+            &mut io::sink(),
+        )?;
+        if discarded != self.length as u64 {
+            bail!(
+                "connection closed after {discarded} of {} body bytes",
+                self.length
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl From<Vec<u8>> for RequestBody {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::new(bytes)
+    }
+}
+
+impl fmt::Debug for RequestBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.loaded() {
+            Some(bytes) => f.debug_tuple("RequestBody").field(&bytes).finish(),
+            None => f.write_str("RequestBody(<unread>)"),
+        }
+    }
+}
+
+impl PartialEq for RequestBody {
+    fn eq(&self, other: &Self) -> bool {
+        self.loaded() == other.loaded()
+    }
+}
+
+impl Eq for RequestBody {}
+
+impl Serialize for RequestBody {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes = self.bytes().map_err(serde::ser::Error::custom)?;
+        bytes.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RequestBody {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self::new(Vec::deserialize(deserializer)?))
+    }
+}
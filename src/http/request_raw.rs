@@ -1,73 +1,875 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use log::trace;
 use std::{
-    io::{BufRead, BufReader, Read},
+    fmt,
+    io::{self, BufRead, BufReader, Read},
     net::{IpAddr, TcpStream},
+    str::FromStr,
+    time::{Duration, Instant},
 };
 
-use super::HttpHeader;
+use super::{response_status_codes::HttpStatusCode, HttpHeader, HttpMethod};
+
+/// Default maximum size of a request body: 2 MiB.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+
+/// Default capacity of the buffer reading from the connection: 8 KiB, same as `BufReader`.
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Default maximum total size of the `Cookie` headers of a request: 8 KiB.
+pub const DEFAULT_MAX_COOKIE_HEADER_SIZE: usize = 8 * 1024;
+
+/// Default maximum number of cookies in a request.
+pub const DEFAULT_MAX_COOKIE_COUNT: usize = 100;
+
+/// Default maximum size of the request line and headers together: 32 KiB.
+pub const DEFAULT_MAX_HEAD_SIZE: usize = 32 * 1024;
+
+/// Default time the client has to send the request line and headers once it started.
+pub const DEFAULT_HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest chunk size line accepted in a chunked body, extensions included.
+pub const MAX_CHUNK_SIZE_LINE: usize = 1024;
+
+/// Methods whose request body is read by default, the others have theirs discarded.
+pub const DEFAULT_BODY_METHODS: [HttpMethod; 4] = [
+    HttpMethod::POST,
+    HttpMethod::PUT,
+    HttpMethod::PATCH,
+    HttpMethod::DELETE,
+];
+
+/// Limits applied while reading a request from the client.
+#[derive(Debug, Clone)]
+pub struct RequestReadConfig {
+    /// Size of the request line and headers together, also applied to the trailers of chunked
+    /// bodies.
+    pub max_head_size: usize,
+    /// Time allowed to read the request line and headers, checked each time data arrives so
+    /// it can be exceeded by up to the read timeout of the connection.
+    pub head_timeout: Option<Duration>,
+    pub max_body_size: usize,
+    /// Total size of all the `Cookie` header values.
+    pub max_cookie_header_size: usize,
+    pub max_cookie_count: usize,
+    /// Capacity of the buffer reading from the connection, at least 1 byte is used.
+    pub read_buffer_size: usize,
+    /// Methods whose body is kept, the body of other requests is read off the connection and
+    /// thrown away.
+    pub body_methods: Vec<HttpMethod>,
+}
+
+impl Default for RequestReadConfig {
+    fn default() -> Self {
+        Self {
+            max_head_size: DEFAULT_MAX_HEAD_SIZE,
+            head_timeout: Some(DEFAULT_HEAD_TIMEOUT),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_cookie_header_size: DEFAULT_MAX_COOKIE_HEADER_SIZE,
+            max_cookie_count: DEFAULT_MAX_COOKIE_COUNT,
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+            body_methods: DEFAULT_BODY_METHODS.to_vec(),
+        }
+    }
+}
+
+impl RequestReadConfig {
+    /// Wraps `stream` in a buffered reader of the configured capacity.
+    pub fn buf_reader<R: Read>(&self, stream: R) -> BufReader<R> {
+        BufReader::with_capacity(self.read_buffer_size.max(1), stream)
+    }
+
+    /// Whether the body of the request starting with `request_line` is kept. Unknown methods
+    /// are left for the request parser to reject.
+    fn keeps_body(&self, request_line: &str) -> bool {
+        let method = request_line.split(' ').next().unwrap_or_default();
+        HttpMethod::from_str(method).map_or(true, |method| self.body_methods.contains(&method))
+    }
+}
+
+/// Errors raised when a request is rejected before being fully read.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RequestReadError {
+    HeadTooLarge {
+        limit: usize,
+    },
+    HeadTimeout {
+        timeout: Duration,
+    },
+    InvalidContentLength {
+        value: String,
+    },
+    /// `Transfer-Encoding` does not end with `chunked`, so the end of the body is unknown.
+    InvalidTransferEncoding {
+        value: String,
+    },
+    /// Both `Transfer-Encoding` and `Content-Length` are sent, which parsers could disagree on.
+    ConflictingFraming,
+    ChunkSizeLineTooLong {
+        limit: usize,
+    },
+    /// The request could not be parsed, e.g. a malformed request line or chunk.
+    BadRequest {
+        reason: String,
+    },
+    PayloadTooLarge {
+        length: usize,
+        limit: usize,
+    },
+    CookieHeaderTooLarge {
+        length: usize,
+        limit: usize,
+    },
+    TooManyCookies {
+        count: usize,
+        limit: usize,
+    },
+}
+
+impl RequestReadError {
+    /// Status code of the response sent back to the client for this error.
+    pub fn status_code(&self) -> HttpStatusCode {
+        match self {
+            RequestReadError::HeadTooLarge { .. } => HttpStatusCode::RequestHeaderFieldsTooLarge,
+            RequestReadError::HeadTimeout { .. } => HttpStatusCode::RequestTimeout,
+            RequestReadError::InvalidContentLength { .. }
+            | RequestReadError::InvalidTransferEncoding { .. }
+            | RequestReadError::ConflictingFraming
+            | RequestReadError::ChunkSizeLineTooLong { .. }
+            | RequestReadError::BadRequest { .. } => HttpStatusCode::BadRequest,
+            RequestReadError::PayloadTooLarge { .. } => HttpStatusCode::ContentTooLarge,
+            RequestReadError::CookieHeaderTooLarge { .. }
+            | RequestReadError::TooManyCookies { .. } => {
+                HttpStatusCode::RequestHeaderFieldsTooLarge
+            }
+        }
+    }
+}
+
+impl fmt::Display for RequestReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestReadError::HeadTooLarge { limit } => {
+                write!(
+                    f,
+                    "request line and headers exceed the limit of {limit} bytes"
+                )
+            }
+            RequestReadError::HeadTimeout { timeout } => write!(
+                f,
+                "request line and headers not received within {}ms",
+                timeout.as_millis()
+            ),
+            RequestReadError::InvalidContentLength { value } => {
+                write!(f, "invalid Content-Length: {value:?}")
+            }
+            RequestReadError::InvalidTransferEncoding { value } => {
+                write!(f, "Transfer-Encoding does not end with chunked: {value:?}")
+            }
+            RequestReadError::ConflictingFraming => {
+                write!(f, "both Transfer-Encoding and Content-Length are sent")
+            }
+            RequestReadError::ChunkSizeLineTooLong { limit } => {
+                write!(f, "chunk size line exceeds the limit of {limit} bytes")
+            }
+            RequestReadError::BadRequest { reason } => write!(f, "malformed request: {reason}"),
+            RequestReadError::PayloadTooLarge { length, limit } => write!(
+                f,
+                "request body of {length} bytes exceeds the limit of {limit} bytes"
+            ),
+            RequestReadError::CookieHeaderTooLarge { length, limit } => write!(
+                f,
+                "cookie headers of {length} bytes exceed the limit of {limit} bytes"
+            ),
+            RequestReadError::TooManyCookies { count, limit } => {
+                write!(f, "{count} cookies exceed the limit of {limit} cookies")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RequestReadError {}
+
+/// Parses a `Content-Length` value, which must be a plain decimal number once the surrounding
+/// whitespace is removed.
+///
+/// Comma-separated lists (`10, 10`) are rejected rather than guessed at, like signs or any other
+/// character that could make two parsers disagree on where the body ends.
+pub fn parse_content_length(value: &str) -> Result<usize, RequestReadError> {
+    let invalid = || RequestReadError::InvalidContentLength {
+        value: value.to_owned(),
+    };
+
+    let digits = value.trim();
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(invalid());
+    }
+
+    digits.parse().map_err(|_| invalid())
+}
 
 pub struct HttpRequestRaw {
     pub request_line: String,
     pub headers: Vec<HttpHeader>,
     pub body: Vec<u8>,
+    /// Trailer headers sent after a chunked body.
+    pub trailers: Vec<HttpHeader>,
     pub peer_ip: IpAddr,
     pub local_ip: IpAddr,
 }
 
 impl HttpRequestRaw {
-    pub fn from_tcp(stream: &TcpStream) -> Result<HttpRequestRaw> {
+    pub fn from_tcp(stream: &TcpStream, config: &RequestReadConfig) -> Result<HttpRequestRaw> {
         trace!("trying to convert TCP message into HTTP request");
-        let mut buf_reader = BufReader::new(stream);
-
         let peer_ip = stream.peer_addr()?.ip();
         let local_ip = stream.local_addr()?.ip();
 
+        Self::from_reader(config.buf_reader(stream), peer_ip, local_ip, config)
+    }
+
+    pub fn from_reader<R: BufRead>(
+        mut buf_reader: R,
+        peer_ip: IpAddr,
+        local_ip: IpAddr,
+        config: &RequestReadConfig,
+    ) -> Result<HttpRequestRaw> {
+        let (mut raw_request, pending_length) =
+            Self::read_head(&mut buf_reader, peer_ip, local_ip, config)?;
+
+        if pending_length > 0 {
+            trace!("read body ({} bytes)", pending_length);
+            raw_request.body = vec![0; pending_length];
+            buf_reader.read_exact(&mut raw_request.body)?;
+        }
+
+        trace!("finish processing TCP stream");
+        Ok(raw_request)
+    }
+
+    /// Reads the request line and headers, leaving a `Content-Length` body on the reader.
+    ///
+    /// Returns the request along with the length of the body still to be read. Chunked bodies
+    /// are read right away, along with their trailers.
+    pub fn read_head<R: BufRead + ?Sized>(
+        buf_reader: &mut R,
+        peer_ip: IpAddr,
+        local_ip: IpAddr,
+        config: &RequestReadConfig,
+    ) -> Result<(HttpRequestRaw, usize)> {
         let mut request_line = String::new();
-        let mut headers = Vec::new();
         let mut body = Vec::new();
+        let mut trailers = Vec::new();
+        let mut pending_length = 0;
 
-        trace!("read request line");
-        buf_reader.read_line(&mut request_line)?;
+        trace!("read request line and headers");
+        let mut head_reader = HeadReader::new(buf_reader, config);
+        let head = head_reader
+            .read_line(&mut request_line)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| read_header_lines(&mut head_reader));
+        // report the limit rather than the read error it caused
+        if let Some(error) = head_reader.exceeded {
+            return Err(error.into());
+        }
+        let headers = head?;
 
-        let mut line = String::new();
-        trace!("proceed to read read headers");
-        while buf_reader.read_line(&mut line)? > 0 {
-            if line.trim().is_empty() {
-                break;
+        let transfer_encoding = header_values(&headers, "Transfer-Encoding");
+        let has_content_length = !header_values(&headers, "Content-Length").is_empty();
+        if !transfer_encoding.is_empty() {
+            // a body without a known end would be left on the connection as the next request
+            let transfer_encoding = transfer_encoding.join(", ");
+            let is_chunked = transfer_encoding
+                .rsplit(',')
+                .next()
+                .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"));
+            if !is_chunked {
+                return Err(RequestReadError::InvalidTransferEncoding {
+                    value: transfer_encoding,
+                }
+                .into());
             }
 
-            if let Some((key, value)) = line.trim_end().split_once(':') {
-                let header = HttpHeader {
-                    name: key.trim().to_owned(),
-                    value: value.trim().to_owned(),
-                };
-                headers.push(header);
+            if has_content_length {
+                return Err(RequestReadError::ConflictingFraming.into());
             }
 
-            line.clear();
-        }
-
-        if let Some(content_len) = headers
+            trace!("found chunked Transfer-Encoding, decoding body chunks");
+            (body, trailers) = read_chunked_body(buf_reader, config)?;
+        } else if let Some(content_len) = headers
             .iter()
-            .find(|header| header.name == "Content-Length")
+            .find(|header| header.name.eq_ignore_ascii_case("Content-Length"))
         {
             trace!("found Content-Length header, using value to read body");
-            let content_len: usize = content_len.value.parse()?;
-            if content_len > 0 {
-                trace!("read body ({} bytes)", content_len);
-                body = vec![0; content_len];
-                buf_reader.read_exact(&mut body)?;
+            let content_len = parse_content_length(&content_len.value)?;
+            let conflicting = headers.iter().any(|header| {
+                header.name.eq_ignore_ascii_case("Content-Length")
+                    && parse_content_length(&header.value) != Ok(content_len)
+            });
+            if conflicting {
+                return Err(RequestReadError::InvalidContentLength {
+                    value: header_values(&headers, "Content-Length").join(", "),
+                }
+                .into());
+            }
+
+            if content_len > config.max_body_size {
+                return Err(RequestReadError::PayloadTooLarge {
+                    length: content_len,
+                    limit: config.max_body_size,
+                }
+                .into());
             }
+
+            pending_length = content_len;
         }
 
-        trace!("finish processing TCP stream");
-        Ok(HttpRequestRaw {
+        if pending_length > 0 && !config.keeps_body(&request_line) {
+            trace!("discard body of {pending_length} bytes, not kept for this method");
+            let discarded = io::copy(&mut buf_reader.take(pending_length as u64), &mut io::sink())?;
+            if discarded != pending_length as u64 {
+                bail!("connection closed after {discarded} of {pending_length} body bytes");
+            }
+            pending_length = 0;
+        } else if !body.is_empty() && !config.keeps_body(&request_line) {
+            trace!("discard chunked body, not kept for this method");
+            body.clear();
+        }
+
+        let raw_request = HttpRequestRaw {
             request_line,
             headers,
             body,
+            trailers,
             peer_ip,
             local_ip,
-        })
+        };
+        Ok((raw_request, pending_length))
+    }
+}
+
+/// Reads the request head off the connection, failing once it goes over the size or time limit
+/// of the config.
+struct HeadReader<'a, R: ?Sized> {
+    inner: &'a mut R,
+    remaining: usize,
+    max_head_size: usize,
+    timeout: Option<Duration>,
+    started: Instant,
+    /// Limit that made the last read fail.
+    exceeded: Option<RequestReadError>,
+}
+
+impl<'a, R: BufRead + ?Sized> HeadReader<'a, R> {
+    fn new(inner: &'a mut R, config: &RequestReadConfig) -> Self {
+        Self {
+            inner,
+            remaining: config.max_head_size,
+            max_head_size: config.max_head_size,
+            timeout: config.head_timeout,
+            started: Instant::now(),
+            exceeded: None,
+        }
+    }
+
+    fn exceed(&mut self, error: RequestReadError) -> io::Error {
+        let io_error = io::Error::new(io::ErrorKind::InvalidData, error.to_string());
+        self.exceeded = Some(error);
+        io_error
+    }
+}
+
+impl<R: BufRead + ?Sized> Read for HeadReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead + ?Sized> BufRead for HeadReader<'_, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if let Some(timeout) = self.timeout {
+            if self.started.elapsed() > timeout {
+                return Err(self.exceed(RequestReadError::HeadTimeout { timeout }));
+            }
+        }
+        if self.remaining == 0 {
+            let limit = self.max_head_size;
+            return Err(self.exceed(RequestReadError::HeadTooLarge { limit }));
+        }
+
+        let available = self.inner.fill_buf()?;
+        let len = available.len().min(self.remaining);
+        Ok(&available[..len])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.remaining -= amt;
+        self.inner.consume(amt);
+    }
+}
+
+/// Values of the headers named `name`, header names are case-insensitive.
+fn header_values(headers: &[HttpHeader], name: &str) -> Vec<String> {
+    headers
+        .iter()
+        .filter(|header| header.name.eq_ignore_ascii_case(name))
+        .map(|header| header.value.to_owned())
+        .collect()
+}
+
+/// Reads header lines until the empty line ending the section.
+fn read_header_lines<R: BufRead + ?Sized>(buf_reader: &mut R) -> Result<Vec<HttpHeader>> {
+    let mut headers = Vec::new();
+    let mut line = String::new();
+    while buf_reader.read_line(&mut line)? > 0 {
+        if line.trim().is_empty() {
+            break;
+        }
+
+        if let Some((key, value)) = line.trim_end().split_once(':') {
+            let header = HttpHeader {
+                name: key.trim().to_owned(),
+                value: value.trim().to_owned(),
+            };
+            headers.push(header);
+        }
+
+        line.clear();
+    }
+
+    Ok(headers)
+}
+
+/// Decodes a `Transfer-Encoding: chunked` body.
+///
+/// Returns the body along with the trailer headers, which are kept apart: they come after the
+/// header section was checked, e.g. by a proxy, so they must not pass for regular headers.
+fn read_chunked_body<R: BufRead + ?Sized>(
+    buf_reader: &mut R,
+    config: &RequestReadConfig,
+) -> Result<(Vec<u8>, Vec<HttpHeader>)> {
+    let mut body = Vec::new();
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        let read = (&mut *buf_reader)
+            .take(MAX_CHUNK_SIZE_LINE as u64)
+            .read_line(&mut line)?;
+        if read == 0 {
+            bail!("unexpected end of stream while reading chunk size");
+        }
+        if !line.ends_with('\n') && read == MAX_CHUNK_SIZE_LINE {
+            return Err(RequestReadError::ChunkSizeLineTooLong {
+                limit: MAX_CHUNK_SIZE_LINE,
+            }
+            .into());
+        }
+
+        // chunk extensions after `;` are ignored
+        let size = line.trim_end().split(';').next().unwrap_or_default().trim();
+        // `from_str_radix` alone would accept a leading `+`
+        if size.is_empty() || !size.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            bail!("malformed chunk size: {size:?}");
+        }
+        let size = usize::from_str_radix(size, 16)
+            .with_context(|| format!("malformed chunk size: {size:?}"))?;
+        trace!("read chunk ({size} bytes)");
+
+        if size == 0 {
+            break;
+        }
+
+        let length = body.len().saturating_add(size);
+        if length > config.max_body_size {
+            return Err(RequestReadError::PayloadTooLarge {
+                length,
+                limit: config.max_body_size,
+            }
+            .into());
+        }
+
+        let start = body.len();
+        body.resize(length, 0);
+        buf_reader.read_exact(&mut body[start..])?;
+
+        let mut chunk_end = [0; 2];
+        buf_reader.read_exact(&mut chunk_end)?;
+        if &chunk_end != b"\r\n" {
+            bail!("expected CRLF after chunk data");
+        }
+    }
+
+    // the trailers count towards the head size limit, without its deadline
+    let mut trailer_reader = HeadReader::new(buf_reader, config);
+    trailer_reader.timeout = None;
+    let trailers = read_header_lines(&mut trailer_reader);
+    if let Some(error) = trailer_reader.exceeded {
+        return Err(error.into());
+    }
+    let trailers = trailers?;
+    trace!("read {} trailer headers", trailers.len());
+    Ok((body, trailers))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, net::Ipv4Addr};
+
+    use super::*;
+
+    fn read_request(raw: &str, config: &RequestReadConfig) -> Result<HttpRequestRaw> {
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        HttpRequestRaw::from_reader(Cursor::new(raw.as_bytes()), ip, ip, config)
+    }
+
+    #[test]
+    fn test_body_within_limit_ok() {
+        let config = RequestReadConfig {
+            max_body_size: 5,
+            ..Default::default()
+        };
+        let raw = read_request(
+            "POST /upload HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello",
+            &config,
+        )
+        .unwrap();
+
+        assert_eq!(b"hello".to_vec(), raw.body);
+    }
+
+    #[test]
+    fn test_body_over_limit_rejected_before_allocation() {
+        // the body is never sent: the huge length must be rejected from the header alone
+        let raw = read_request(
+            "POST /upload HTTP/1.1\r\nContent-Length: 4000000000\r\n\r\n",
+            &RequestReadConfig::default(),
+        );
+
+        let error = raw.err().unwrap();
+        assert_eq!(
+            Some(&RequestReadError::PayloadTooLarge {
+                length: 4_000_000_000,
+                limit: DEFAULT_MAX_BODY_SIZE,
+            }),
+            error.downcast_ref::<RequestReadError>()
+        );
+    }
+
+    #[test]
+    fn test_get_body_skipped() {
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let config = RequestReadConfig::default();
+        let mut reader = Cursor::new(
+            "GET /search HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello\
+GET /next HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n\
+GET /last HTTP/1.1\r\n\r\n"
+                .as_bytes(),
+        );
+
+        let (raw, pending_length) =
+            HttpRequestRaw::read_head(&mut reader, ip, ip, &config).unwrap();
+        assert_eq!(0, pending_length);
+        assert!(raw.body.is_empty());
+
+        let raw = HttpRequestRaw::from_reader(&mut reader, ip, ip, &config).unwrap();
+        assert_eq!("GET /next HTTP/1.1\r\n", raw.request_line);
+        assert!(raw.body.is_empty());
+
+        let raw = HttpRequestRaw::from_reader(&mut reader, ip, ip, &config).unwrap();
+        assert_eq!("GET /last HTTP/1.1\r\n", raw.request_line);
+    }
+
+    #[test]
+    fn test_body_methods_configurable() {
+        let request = "POST /upload HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+        let raw = read_request(request, &RequestReadConfig::default()).unwrap();
+        assert_eq!(b"hello".to_vec(), raw.body);
+
+        let config = RequestReadConfig {
+            body_methods: vec![HttpMethod::PUT],
+            ..Default::default()
+        };
+        let raw = read_request(request, &config).unwrap();
+        assert!(raw.body.is_empty());
+    }
+
+    #[test]
+    fn test_head_over_size_limit_rejected() {
+        let config = RequestReadConfig {
+            max_head_size: 64,
+            ..Default::default()
+        };
+        let request = format!("GET / HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "a".repeat(64));
+
+        let error = read_request(&request, &config).err().unwrap();
+        let error = error.downcast_ref::<RequestReadError>().unwrap();
+        assert_eq!(&RequestReadError::HeadTooLarge { limit: 64 }, error);
+        assert_eq!(
+            HttpStatusCode::RequestHeaderFieldsTooLarge,
+            error.status_code()
+        );
+
+        // the body does not count towards the head
+        let request = format!(
+            "POST / HTTP/1.1\r\nContent-Length: 100\r\n\r\n{}",
+            "a".repeat(100)
+        );
+        assert!(read_request(&request, &config).is_ok());
+    }
+
+    /// Sends one byte at a time, waiting before each of them.
+    struct SlowReader<'a>(&'a [u8]);
+
+    impl Read for SlowReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            std::thread::sleep(Duration::from_millis(10));
+            let Some((&byte, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+            buf[0] = byte;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn test_head_over_time_limit_rejected() {
+        let config = RequestReadConfig {
+            head_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let reader = BufReader::new(SlowReader(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let error = HttpRequestRaw::from_reader(reader, ip, ip, &config)
+            .err()
+            .unwrap();
+        let error = error.downcast_ref::<RequestReadError>().unwrap();
+        assert_eq!(
+            &RequestReadError::HeadTimeout {
+                timeout: Duration::from_millis(50)
+            },
+            error
+        );
+        assert_eq!(HttpStatusCode::RequestTimeout, error.status_code());
+    }
+
+    #[test]
+    fn test_parse_content_length() {
+        assert_eq!(Ok(10), parse_content_length("10"));
+        assert_eq!(Ok(10), parse_content_length(" \t10  "));
+        assert_eq!(Ok(0), parse_content_length("0"));
+
+        for value in ["10, 10", "10,10", "", "+10", "-1", "1 0", "0x10"] {
+            assert_eq!(
+                Err(RequestReadError::InvalidContentLength {
+                    value: value.to_owned()
+                }),
+                parse_content_length(value)
+            );
+        }
+    }
+
+    #[test]
+    fn test_comma_listed_content_length_rejected() {
+        let error = read_request(
+            "POST /upload HTTP/1.1\r\nContent-Length: 5, 5\r\n\r\nhello",
+            &RequestReadConfig::default(),
+        )
+        .err()
+        .unwrap();
+
+        let error = error.downcast_ref::<RequestReadError>().unwrap();
+        assert_eq!(HttpStatusCode::BadRequest, error.status_code());
+    }
+
+    #[test]
+    fn test_conflicting_content_length_headers_rejected() {
+        let raw = read_request(
+            "POST /upload HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\nhello",
+            &RequestReadConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(b"hello".to_vec(), raw.body);
+
+        let error = read_request(
+            "POST /upload HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 3\r\n\r\nhello",
+            &RequestReadConfig::default(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            Some(&RequestReadError::InvalidContentLength {
+                value: "5, 3".to_owned()
+            }),
+            error.downcast_ref::<RequestReadError>()
+        );
+    }
+
+    #[test]
+    fn test_chunked_body_two_chunks() {
+        let raw = read_request(
+            "POST /upload HTTP/1.1\r\n\
+Transfer-Encoding: chunked\r\n\
+\r\n\
+5\r\nhello\r\n\
+7;name=value\r\n, world\r\n\
+0\r\n\
+\r\n",
+            &RequestReadConfig::default(),
+        )
+        .unwrap();
+
+        assert_eq!(b"hello, world".to_vec(), raw.body);
+        assert_eq!(1, raw.headers.len());
+    }
+
+    #[test]
+    fn test_chunked_body_trailers_kept_apart() {
+        let raw = read_request(
+            "POST /upload HTTP/1.1\r\n\
+Transfer-Encoding: chunked\r\n\
+Trailer: Authorization\r\n\
+\r\n\
+A\r\n0123456789\r\n\
+0\r\n\
+Authorization: Bearer injected\r\n\
+\r\n",
+            &RequestReadConfig::default(),
+        )
+        .unwrap();
+
+        assert_eq!(b"0123456789".to_vec(), raw.body);
+        assert_eq!(2, raw.headers.len());
+        assert!(header_values(&raw.headers, "Authorization").is_empty());
+        assert_eq!(
+            vec![HttpHeader::new("Authorization", "Bearer injected")],
+            raw.trailers
+        );
+    }
+
+    #[test]
+    fn test_transfer_encoding_not_ending_with_chunked_rejected() {
+        for transfer_encoding in ["gzip", "chunked, gzip"] {
+            let request = format!(
+                "POST /upload HTTP/1.1\r\nTransfer-Encoding: {transfer_encoding}\r\n\
+Content-Length: 5\r\n\r\nhello"
+            );
+
+            let error = read_request(&request, &RequestReadConfig::default())
+                .err()
+                .unwrap();
+            let error = error.downcast_ref::<RequestReadError>().unwrap();
+            assert_eq!(
+                &RequestReadError::InvalidTransferEncoding {
+                    value: transfer_encoding.to_owned()
+                },
+                error
+            );
+            assert_eq!(HttpStatusCode::BadRequest, error.status_code());
+        }
+    }
+
+    #[test]
+    fn test_chunked_with_content_length_rejected() {
+        let error = read_request(
+            "POST /upload HTTP/1.1\r\n\
+transfer-encoding: chunked\r\n\
+Content-Length: 5\r\n\
+\r\n\
+5\r\nhello\r\n\
+0\r\n\r\n",
+            &RequestReadConfig::default(),
+        )
+        .err()
+        .unwrap();
+
+        assert_eq!(
+            Some(&RequestReadError::ConflictingFraming),
+            error.downcast_ref::<RequestReadError>()
+        );
+    }
+
+    #[test]
+    fn test_chunked_body_malformed_size_err() {
+        let raw = read_request(
+            "POST /upload HTTP/1.1\r\n\
+Transfer-Encoding: chunked\r\n\
+\r\n\
+zz\r\nhello\r\n\
+0\r\n\r\n",
+            &RequestReadConfig::default(),
+        );
+
+        assert!(raw.is_err());
+    }
+
+    #[test]
+    fn test_chunked_body_signed_size_err() {
+        let raw = read_request(
+            "POST /upload HTTP/1.1\r\n\
+Transfer-Encoding: chunked\r\n\
+\r\n\
++5\r\nhello\r\n\
+0\r\n\r\n",
+            &RequestReadConfig::default(),
+        );
+
+        assert!(raw.is_err());
+    }
+
+    #[test]
+    fn test_chunked_body_long_lines_rejected() {
+        let request = format!(
+            "POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;{}\r\nhello\r\n0\r\n\r\n",
+            "a".repeat(MAX_CHUNK_SIZE_LINE)
+        );
+        let error = read_request(&request, &RequestReadConfig::default())
+            .err()
+            .unwrap();
+        assert_eq!(
+            Some(&RequestReadError::ChunkSizeLineTooLong {
+                limit: MAX_CHUNK_SIZE_LINE
+            }),
+            error.downcast_ref::<RequestReadError>()
+        );
+
+        let config = RequestReadConfig {
+            max_head_size: 64,
+            ..Default::default()
+        };
+        let request = format!(
+            "POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nX-Padding: {}\r\n\r\n",
+            "a".repeat(64)
+        );
+        let error = read_request(&request, &config).err().unwrap();
+        assert_eq!(
+            Some(&RequestReadError::HeadTooLarge { limit: 64 }),
+            error.downcast_ref::<RequestReadError>()
+        );
+    }
+
+    #[test]
+    fn test_chunked_body_over_limit_rejected() {
+        let config = RequestReadConfig {
+            max_body_size: 8,
+            ..Default::default()
+        };
+        let raw = read_request(
+            "POST /upload HTTP/1.1\r\n\
+Transfer-Encoding: chunked\r\n\
+\r\n\
+5\r\nhello\r\n\
+5\r\nworld\r\n\
+0\r\n\r\n",
+            &config,
+        );
+
+        let error = raw.err().unwrap();
+        assert!(error.downcast_ref::<RequestReadError>().is_some());
     }
 }
@@ -1,16 +1,127 @@
 use anyhow::{bail, Result};
+use flate2::{write::GzEncoder, Compression};
 use log::trace;
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
 
-use super::{HttpCookie, HttpHeader, HttpVersion};
+use super::{
+    range::MultipartByteranges, sse::EventStream, websocket::WebSocketCallback, HttpCookie,
+    HttpHeader, HttpVersion,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct HttpResponse {
     pub version: HttpVersion,
+    /// Status line after the version, e.g. `404 Not Found`, the reason can be custom.
     pub status: String,
+    /// Numeric code of `status`, `0` when it does not start with one.
+    pub status_code: u16,
     pub headers: BTreeMap<String, HttpHeader>,
     pub cookies: BTreeMap<String, HttpCookie>,
     pub body: Vec<u8>,
+    /// Body read while the response is written, sent instead of `body` when set.
+    pub stream: Option<BodyStream>,
+    /// Compress the body with gzip while it is written, using chunked encoding.
+    pub gzip: bool,
+    /// Takes over the connection once this `101 Switching Protocols` response is sent.
+    pub upgrade: Option<WebSocketCallback>,
+}
+
+/// Size of the pieces of body fed to the writer, each ends up in at most one chunk.
+pub const STREAM_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Body that is not held in memory but copied to the connection piece by piece.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BodyStream {
+    /// `len` bytes of the file at `path`, starting at offset `start`.
+    File { path: PathBuf, start: u64, len: u64 },
+    /// `multipart/byteranges` body whose parts are read from the file at `path`.
+    FileRanges {
+        path: PathBuf,
+        multipart: MultipartByteranges,
+    },
+    /// Server-Sent Events written as they are produced, the length is not known in advance.
+    Events(EventStream),
+}
+
+impl BodyStream {
+    /// Streams the whole file, its length is taken from the file metadata.
+    pub fn from_file(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let len = path.metadata()?.len();
+        Ok(Self::File {
+            path,
+            start: 0,
+            len,
+        })
+    }
+
+    /// Number of bytes the stream writes, `None` when it is only known once the stream ends.
+    pub fn content_length(&self) -> Option<u64> {
+        match self {
+            Self::File { len, .. } => Some(*len),
+            Self::FileRanges { multipart, .. } => Some(multipart.content_length()),
+            Self::Events(_) => None,
+        }
+    }
+
+    /// Copies the stream to `writer` in pieces of at most [`STREAM_CHUNK_SIZE`] bytes.
+    pub fn copy_to<W: Write + ?Sized>(&self, writer: &mut W) -> Result<()> {
+        match self {
+            Self::File { path, start, len } => {
+                copy_file_range(&mut File::open(path)?, path, *start, *len, writer)?;
+            }
+            Self::FileRanges { path, multipart } => {
+                let mut file = File::open(path)?;
+                for (part_head, range) in &multipart.parts {
+                    writer.write_all(part_head.as_bytes())?;
+                    copy_file_range(&mut file, path, range.start, range.length(), writer)?;
+                    writer.write_all(b"\r\n")?;
+                }
+                writer.write_all(multipart.closing().as_bytes())?;
+            }
+            Self::Events(events) => events.copy_to(writer)?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Copies `len` bytes of `file` from offset `start`, failing if the file got shorter.
+fn copy_file_range<W: Write + ?Sized>(
+    file: &mut File,
+    path: &Path,
+    start: u64,
+    len: u64,
+    writer: &mut W,
+) -> Result<()> {
+    file.seek(SeekFrom::Start(start))?;
+    let mut file = file.take(len);
+
+    let mut buffer = vec![0; STREAM_CHUNK_SIZE];
+    let mut copied = 0;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+
+        writer.write_all(&buffer[..read])?;
+        copied += read as u64;
+    }
+
+    if copied != len {
+        bail!(
+            "{} shrank while being sent: {copied} of {len} bytes written",
+            path.display()
+        );
+    }
+
+    Ok(())
 }
 
 impl Default for HttpResponse {
@@ -24,17 +135,108 @@ impl HttpResponse {
         HttpResponse {
             version: HttpVersion::HTTP1_1,
             status: "200 OK".to_owned(),
+            status_code: 200,
             headers: BTreeMap::new(),
             cookies: BTreeMap::new(),
             body: Vec::new(),
+            stream: None,
+            gzip: false,
+            upgrade: None,
+        }
+    }
+
+    /// Replaces the body, dropping any stream, and updates `Content-Length` to match.
+    pub fn set_body(&mut self, body: Vec<u8>) {
+        self.headers.insert(
+            "Content-Length".to_owned(),
+            HttpHeader::new("Content-Length", &body.len().to_string()),
+        );
+        self.body = body;
+        self.stream = None;
+    }
+
+    /// Length of the body that will be sent, before compression. Streams of unknown length
+    /// count as empty.
+    pub fn body_len(&self) -> u64 {
+        match &self.stream {
+            Some(stream) => stream.content_length().unwrap_or_default(),
+            None => self.body.len() as u64,
+        }
+    }
+
+    /// Returns the body, reading the stream into memory if there is one.
+    pub fn read_body(&self) -> Result<Vec<u8>> {
+        match &self.stream {
+            Some(stream) => {
+                let mut body = Vec::new();
+                stream.copy_to(&mut body)?;
+                Ok(body)
+            }
+            None => Ok(self.body.clone()),
         }
     }
 
+    /// Sets the status line, keeping `status_code` in sync with it.
+    pub fn set_status(&mut self, status: &str) {
+        self.status = status.to_owned();
+        self.status_code = status
+            .split(' ')
+            .next()
+            .and_then(|code| code.parse().ok())
+            .unwrap_or_default();
+    }
+
+    /// Returns the header set with this name, which is matched regardless of case.
+    pub fn get_header(&self, name: &str) -> Option<&HttpHeader> {
+        self.headers.get(name).or_else(|| {
+            self.headers
+                .values()
+                .find(|header| header.name.eq_ignore_ascii_case(name))
+        })
+    }
+
+    /// Sets a header, replacing the ones set with the same name in any case.
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.headers
+            .retain(|_, header| !header.name.eq_ignore_ascii_case(name));
+        self.headers
+            .insert(name.to_owned(), HttpHeader::new(name, value));
+    }
+
     pub fn start_line(&self) -> String {
         format!("{} {}", self.version, self.status)
     }
 
     pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut response = Vec::new();
+        self.write_to(&mut response)?;
+        Ok(response)
+    }
+
+    /// Writes the response to `writer`, compressing the body on the fly when `gzip` is set.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        self.write_head(writer)?;
+
+        if !self.gzip {
+            self.write_body(writer)?;
+            return Ok(writer.flush()?);
+        }
+
+        let mut encoder = GzEncoder::new(ChunkedWriter { inner: writer }, Compression::default());
+        self.write_body(&mut encoder)?;
+        encoder.finish()?.finish()?;
+
+        Ok(())
+    }
+
+    /// Writes the status line and headers only, as they would be for the full response, e.g.
+    /// to answer a `HEAD` request.
+    pub fn write_head_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        self.write_head(writer)?;
+        Ok(writer.flush()?)
+    }
+
+    fn write_head<W: Write>(&self, writer: &mut W) -> Result<()> {
         if self.status.is_empty() {
             bail!("status must be set on response");
         }
@@ -43,10 +245,25 @@ impl HttpResponse {
         trace!("{:?}", head);
 
         for (_, header) in self.headers.iter() {
+            // the length of the compressed body is not known ahead of time
+            if self.gzip && header.name == "Content-Length" {
+                continue;
+            }
+
             let header = format!("{}: {}\r\n", header.name, header.value);
             head.push_str(&header);
         }
 
+        if self.needs_content_length() {
+            trace!("adding missing Content-Length: {}", self.body.len());
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+
+        if self.gzip {
+            head.push_str("Content-Encoding: gzip\r\n");
+            head.push_str("Transfer-Encoding: chunked\r\n");
+        }
+
         for (_, cookie) in self.cookies.iter() {
             let cookie = cookie.to_str()?;
             let header = format!("Set-Cookie: {}\r\n", cookie);
@@ -54,11 +271,256 @@ impl HttpResponse {
         }
 
         head.push_str("\r\n");
+        Ok(writer.write_all(head.as_bytes())?)
+    }
 
-        let response_head = head.as_bytes();
-        let body = &self.body;
-        let response: Vec<u8> = [response_head, body].concat();
+    /// Whether the client can find the end of the body without waiting for the connection to
+    /// close, from its length, its chunks or a status that never has a body.
+    pub fn is_delimited(&self) -> bool {
+        let no_body_status = matches!(self.status_code, 100..=199 | 204 | 304);
 
-        Ok(response)
+        no_body_status
+            || self.gzip
+            || self.needs_content_length()
+            || self.headers.values().any(|header| {
+                header.name.eq_ignore_ascii_case("Content-Length")
+                    || (header.name.eq_ignore_ascii_case("Transfer-Encoding")
+                        && header.value.to_ascii_lowercase().contains("chunked"))
+            })
+    }
+
+    /// Whether the in-memory body was set without a matching `Content-Length` header.
+    fn needs_content_length(&self) -> bool {
+        !self.gzip
+            && self.stream.is_none()
+            && !self.body.is_empty()
+            && !self
+                .headers
+                .values()
+                .any(|header| header.name.eq_ignore_ascii_case("Content-Length"))
+    }
+
+    fn write_body<W: Write>(&self, writer: &mut W) -> Result<()> {
+        match &self.stream {
+            Some(stream) => stream.copy_to(writer),
+            None => {
+                for piece in self.body.chunks(STREAM_CHUNK_SIZE) {
+                    writer.write_all(piece)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Writes everything it receives as chunks of a `Transfer-Encoding: chunked` body.
+struct ChunkedWriter<'a, W: Write> {
+    inner: &'a mut W,
+}
+
+impl<W: Write> ChunkedWriter<'_, W> {
+    /// Writes the last, empty, chunk.
+    fn finish(self) -> std::io::Result<()> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        write!(self.inner, "{:x}\r\n", buf.len())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use crate::http::{response_status_codes::HttpStatusCode, HttpResponseBuilder};
+
+    use super::*;
+
+    fn find_bytes(haystack: &[u8], needle: &[u8]) -> usize {
+        haystack
+            .windows(needle.len())
+            .position(|window| window == needle)
+            .unwrap()
+    }
+
+    /// Splits a chunked body into the data of each of its chunks.
+    fn decode_chunks(mut body: &[u8]) -> Vec<Vec<u8>> {
+        let mut chunks = vec![];
+        loop {
+            let line_end = find_bytes(body, b"\r\n");
+            let size = std::str::from_utf8(&body[..line_end]).unwrap();
+            let size = usize::from_str_radix(size, 16).unwrap();
+            body = &body[line_end + 2..];
+            if size == 0 {
+                assert_eq!(b"\r\n", body);
+                return chunks;
+            }
+
+            chunks.push(body[..size].to_vec());
+            assert_eq!(b"\r\n", &body[size..size + 2]);
+            body = &body[size + 2..];
+        }
+    }
+
+    #[test]
+    fn test_clone_to_bytes_equal() {
+        let response = HttpResponseBuilder::new()
+            .set_status(HttpStatusCode::Created)
+            .set_html_body("<p>Cached</p>")
+            .set_cookie(HttpCookie::new("foo", "bar").set_http_only(true))
+            .build()
+            .unwrap();
+
+        let cloned = response.clone();
+        assert_eq!(response.to_bytes().unwrap(), cloned.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_set_body_updates_content_length() {
+        let mut response = HttpResponseBuilder::new()
+            .set_html_body("<body></body>")
+            .build()
+            .unwrap();
+
+        let body = String::from_utf8(response.body.clone()).unwrap();
+        let body = body.replace("</body>", "<script src=\"/reload.js\"></script></body>");
+        response.set_body(body.clone().into_bytes());
+
+        let actual = String::from_utf8(response.to_bytes().unwrap()).unwrap();
+        assert!(actual.contains(&format!("Content-Length: {}\r\n", body.len())));
+        assert!(actual.ends_with(&body));
+    }
+
+    #[test]
+    fn test_headers_matched_regardless_of_case() {
+        let mut response = HttpResponseBuilder::new()
+            .set_header("connection", "close")
+            .build()
+            .unwrap();
+        assert_eq!("close", response.get_header("Connection").unwrap().value);
+
+        response.set_header("Connection", "keep-alive");
+        let connection: Vec<_> = response
+            .headers
+            .values()
+            .filter(|header| header.name.eq_ignore_ascii_case("Connection"))
+            .collect();
+        assert_eq!(
+            vec![&HttpHeader::new("Connection", "keep-alive")],
+            connection
+        );
+    }
+
+    #[test]
+    fn test_to_bytes_adds_missing_content_length() {
+        let mut response = HttpResponse::new();
+        response.body = b"no length given".to_vec();
+
+        let actual = String::from_utf8(response.to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            "HTTP/1.1 200 OK\r\nContent-Length: 15\r\n\r\nno length given",
+            actual
+        );
+
+        response.headers.insert(
+            "Content-Length".to_owned(),
+            HttpHeader::new("Content-Length", "15"),
+        );
+        let actual = String::from_utf8(response.to_bytes().unwrap()).unwrap();
+        assert_eq!(1, actual.matches("Content-Length").count());
+    }
+
+    #[test]
+    fn test_write_to_streams_gzip_chunks() {
+        let body: Vec<u8> = (0..1_000_000u32)
+            .flat_map(|i| (i.wrapping_mul(2_654_435_761) >> 24).to_le_bytes())
+            .collect();
+
+        let response = HttpResponseBuilder::new()
+            .set_raw_body(body.clone())
+            .set_gzip_encoding(true)
+            .build()
+            .unwrap();
+
+        let mut written = vec![];
+        response.write_to(&mut written).unwrap();
+
+        let head_end = find_bytes(&written, b"\r\n\r\n") + 4;
+        let head = std::str::from_utf8(&written[..head_end]).unwrap();
+        assert!(head.contains("Content-Encoding: gzip\r\n"));
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!head.contains("Content-Length"));
+
+        let chunks = decode_chunks(&written[head_end..]);
+        assert!(chunks.len() > 1);
+
+        let mut decompressed = vec![];
+        GzDecoder::new(chunks.concat().as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(body, decompressed);
+    }
+
+    #[test]
+    fn test_write_to_streams_file_body() {
+        let content: Vec<u8> = (0..STREAM_CHUNK_SIZE * 3 + 123)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&content).unwrap();
+
+        let response = HttpResponseBuilder::new()
+            .set_body_stream(BodyStream::from_file(file.path()).unwrap())
+            .build()
+            .unwrap();
+        assert!(response.body.is_empty());
+        assert_eq!(content.len() as u64, response.body_len());
+
+        let mut written = vec![];
+        response.write_to(&mut written).unwrap();
+
+        let head_end = find_bytes(&written, b"\r\n\r\n") + 4;
+        let head = std::str::from_utf8(&written[..head_end]).unwrap();
+        assert!(head.contains(&format!("Content-Length: {}\r\n", content.len())));
+        assert_eq!(content, &written[head_end..]);
+    }
+
+    #[test]
+    fn test_file_body_stream_range() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"0123456789").unwrap();
+
+        let stream = BodyStream::File {
+            path: file.path().to_owned(),
+            start: 2,
+            len: 5,
+        };
+        let mut written = vec![];
+        stream.copy_to(&mut written).unwrap();
+        assert_eq!(b"23456".to_vec(), written);
+
+        let stream = BodyStream::File {
+            path: file.path().to_owned(),
+            start: 8,
+            len: 5,
+        };
+        assert!(stream.copy_to(&mut vec![]).is_err());
     }
 }
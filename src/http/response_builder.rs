@@ -1,14 +1,22 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use chrono::{DateTime, Utc};
 use log::trace;
 use serde::Serialize;
+use std::path::Path;
 
 use super::{
-    response_status_codes::HttpStatusCode, HttpCookie, HttpHeader, HttpResponse, HttpVersion,
+    digest, http_date,
+    range::{self, RangeSpec},
+    response::BodyStream,
+    response_status_codes::HttpStatusCode,
+    CachePolicy, EventStream, HttpCookie, HttpHeader, HttpMethod, HttpRequest, HttpResponse,
+    HttpVersion,
 };
 
 pub struct HttpResponseBuilder {
     response: HttpResponse,
+    empty_content_length: bool,
+    error_text_body: bool,
 }
 
 impl Default for HttpResponseBuilder {
@@ -21,6 +29,8 @@ impl HttpResponseBuilder {
     pub fn new() -> Self {
         HttpResponseBuilder {
             response: HttpResponse::new(),
+            empty_content_length: false,
+            error_text_body: false,
         }
         .set_date(Utc::now())
     }
@@ -29,15 +39,54 @@ impl HttpResponseBuilder {
         Self::new().set_date(Utc::now()).set_version(version)
     }
 
-    pub fn build(self) -> Result<HttpResponse> {
-        if self.response.status.is_empty() {
-            bail!("status must be set on response");
+    pub fn build(mut self) -> Result<HttpResponse> {
+        check_status(&self.response.status)?;
+
+        if self.error_text_body && self.needs_error_text_body() {
+            let status = self.response.status.clone();
+            self = self.set_text_body(&status);
+        }
+
+        if self.empty_content_length && self.needs_empty_content_length() {
+            self = self.set_header("Content-Length", "0");
         }
 
         trace!("{:?}", self.response);
         Ok(self.response)
     }
 
+    /// When enabled, `build` adds `Content-Length: 0` to responses without a body and length.
+    ///
+    /// `204 No Content` and `304 Not Modified` responses are left untouched.
+    pub fn set_empty_content_length(mut self, enabled: bool) -> Self {
+        self.empty_content_length = enabled;
+        self
+    }
+
+    /// When enabled, `build` gives `4xx` and `5xx` responses without a body a `text/plain` one
+    /// made of the status line, e.g. `404 Not Found`.
+    pub fn set_error_text_body(mut self, enabled: bool) -> Self {
+        self.error_text_body = enabled;
+        self
+    }
+
+    fn needs_error_text_body(&self) -> bool {
+        let is_error = matches!(self.response.status_code, 400..=599);
+
+        is_error && self.response.body.is_empty() && self.response.stream.is_none()
+    }
+
+    fn needs_empty_content_length(&self) -> bool {
+        let no_body_status = [HttpStatusCode::NoContent, HttpStatusCode::NotModified]
+            .iter()
+            .any(|status| status.code() == self.response.status_code);
+
+        self.response.body.is_empty()
+            && self.response.stream.is_none()
+            && !self.response.headers.contains_key("Content-Length")
+            && !no_body_status
+    }
+
     pub fn set_version(mut self, version: HttpVersion) -> Self {
         self.response.version = version;
         self
@@ -47,8 +96,10 @@ impl HttpResponseBuilder {
         self.set_raw_status(&status.to_string())
     }
 
+    /// Sets a status line that may have a custom reason, e.g. `299 All Good`. `build` fails
+    /// unless it is a 3-digit code, optionally followed by a space and the reason.
     pub fn set_raw_status(mut self, status: &str) -> Self {
-        self.response.status = status.to_owned();
+        self.response.set_status(status);
         self
     }
 
@@ -59,14 +110,50 @@ impl HttpResponseBuilder {
         self
     }
 
+    /// Sets the `Allow` header listing `methods` once each, in declaration order of
+    /// [`HttpMethod`] (`GET, HEAD, POST, ...`).
+    pub fn set_allow(self, methods: &[HttpMethod]) -> Self {
+        let mut methods = methods.to_vec();
+        methods.sort();
+        methods.dedup();
+
+        let allow: Vec<_> = methods.iter().map(HttpMethod::to_string).collect();
+        self.set_header("Allow", &allow.join(", "))
+    }
+
+    /// Adds a `<url>; rel="rel"` entry to the `Link` header, e.g. to point at the `next` page
+    /// of a paginated resource. Entries added before are kept, separated by commas.
+    pub fn add_link(self, url: &str, rel: &str) -> Self {
+        let link = format!("<{url}>; rel=\"{rel}\"");
+        let links = match self.response.headers.get("Link") {
+            Some(header) => format!("{}, {link}", header.value),
+            None => link,
+        };
+
+        self.set_header("Link", &links)
+    }
+
     pub fn set_cookie(mut self, cookie: HttpCookie) -> Self {
         self.response.cookies.insert(cookie.name.to_owned(), cookie);
         self
     }
 
+    /// Asks the client to delete the cookie `name` set for `path`, see [`HttpCookie::expired`].
+    pub fn clear_cookie(self, name: &str, path: Option<&str>) -> Self {
+        self.set_cookie(HttpCookie::expired(name).set_path(path))
+    }
+
+    /// Sets `cookie` with its value signed with `secret`, see [`HttpCookie::signed`].
+    pub fn set_signed_cookie(self, cookie: HttpCookie, secret: &[u8]) -> Self {
+        self.set_cookie(cookie.signed(secret))
+    }
+
     pub fn set_date(self, date: DateTime<Utc>) -> Self {
-        let date = date.format("%a, %d %b %Y %H:%M:%S UTC").to_string();
-        self.set_header("Date", &date)
+        self.set_header("Date", &http_date::format(date))
+    }
+
+    pub fn set_cache_control(self, policy: CachePolicy) -> Self {
+        self.set_header("Cache-Control", &policy.to_string())
     }
 
     pub fn set_content_type(self, content_type: &str) -> Self {
@@ -78,33 +165,175 @@ impl HttpResponseBuilder {
         let length = body.len().to_string();
 
         self.response.body = body.into_bytes();
+        self.response.stream = None;
         self.set_content_type("text/html")
             .set_header("Content-Length", &length)
     }
 
+    pub fn set_text_body(mut self, body: &str) -> Self {
+        let length = body.len().to_string();
+
+        self.response.body = body.as_bytes().to_vec();
+        self.response.stream = None;
+        self.set_content_type("text/plain; charset=utf-8")
+            .set_header("Content-Length", &length)
+    }
+
+    /// Sends the file at `path`, with a Content-Type guessed from its extension.
+    ///
+    /// The file is streamed when the response is written, it must still exist at that point.
+    pub fn set_file_body(self, path: &Path) -> Result<Self> {
+        if !path.is_file() {
+            bail!("cannot send {} as body: not a file", path.display());
+        }
+
+        let stream = BodyStream::from_file(path)
+            .with_context(|| format!("cannot send {} as body", path.display()))?;
+        let mime_type = mime_guess::from_path(path).first_or_octet_stream();
+
+        Ok(self
+            .set_body_stream(stream)
+            .set_content_type(mime_type.as_ref()))
+    }
+
     pub fn set_json_body<T: Serialize>(mut self, body: &T) -> Result<Self> {
         let body = serde_json::to_string(&body)?.to_string();
         let body = format!("{}\r\n", body);
         let length = body.len().to_string();
 
         self.response.body = body.into_bytes();
+        self.response.stream = None;
         Ok(self
             .set_content_type("application/json")
             .set_header("Content-Length", &length))
     }
 
+    /// Adds a `Digest: sha-256=...` header computed over the body set so far, so it must be
+    /// called after the body setters. Streamed bodies are read once to hash them.
+    pub fn set_digest(self) -> Result<Self> {
+        let body = self.response.read_body()?;
+        Ok(self.set_header("Digest", &digest::sha256_header_value(&body)))
+    }
+
+    /// Compresses the body with gzip as it is sent, using chunked encoding instead of a
+    /// `Content-Length`.
+    pub fn set_gzip_encoding(mut self, enabled: bool) -> Self {
+        self.response.gzip = enabled;
+        self
+    }
+
     pub fn set_raw_body(mut self, body: Vec<u8>) -> Self {
         let length = body.len().to_string();
 
         self.response.body = body;
+        self.response.stream = None;
         self.set_content_type("application/octet-stream")
             .set_header("Content-Length", &length)
     }
+
+    /// Answers the `Range` header of `request` from the body set so far, so it must be called
+    /// after the body setters. Only in-memory bodies of `200 OK` responses are sliced, invalid
+    /// `Range` headers and those with more than [`MAX_RANGES`](super::range::MAX_RANGES) ranges
+    /// are ignored.
+    pub fn with_range_support(mut self, request: &HttpRequest) -> Result<Self> {
+        if self.response.status_code != 200 || self.response.stream.is_some() {
+            return Ok(self);
+        }
+
+        self = self.set_header("Accept-Ranges", "bytes");
+        let Some(header) = request.get_header("Range") else {
+            return Ok(self);
+        };
+        let range_spec = match RangeSpec::parse(&header.value) {
+            Ok(range_spec) => range_spec,
+            Err(e) => {
+                trace!("ignoring invalid Range header: {e}");
+                return Ok(self);
+            }
+        };
+
+        let body = std::mem::take(&mut self.response.body);
+        let total_len = body.len() as u64;
+        let content_type = self
+            .response
+            .headers
+            .get("Content-Type")
+            .map_or("application/octet-stream".to_owned(), |header| {
+                header.value.clone()
+            });
+
+        Ok(match range_spec.resolve(total_len).as_slice() {
+            [] => self
+                .set_status(HttpStatusCode::RangeNotSatisfiable)
+                .set_header("Content-Range", &format!("bytes */{total_len}"))
+                .set_header("Content-Length", "0"),
+            [range] => self
+                .set_status(HttpStatusCode::PartialContent)
+                .set_raw_body(range.slice(&body).to_vec())
+                .set_content_type(&content_type)
+                .set_header("Content-Range", &range.content_range(total_len)),
+            ranges => {
+                let (boundary, body) = range::to_multipart_byteranges(&body, ranges, &content_type);
+
+                self.set_status(HttpStatusCode::PartialContent)
+                    .set_raw_body(body)
+                    .set_content_type(&format!("multipart/byteranges; boundary={boundary}"))
+            }
+        })
+    }
+
+    /// Sends the body from `stream` as the response is written, without loading it in memory.
+    pub fn set_body_stream(mut self, stream: BodyStream) -> Self {
+        self.response.body.clear();
+        self.response.headers.remove("Content-Length");
+        let length = stream.content_length();
+        self.response.stream = Some(stream);
+
+        let builder = self.set_content_type("application/octet-stream");
+        match length {
+            Some(length) => builder.set_header("Content-Length", &length.to_string()),
+            None => builder,
+        }
+    }
+
+    /// Sends the events of `events` as they are produced, see [`sse::channel`](super::sse::channel).
+    ///
+    /// The end of the body is signaled by closing the connection.
+    pub fn set_event_stream(self, events: EventStream) -> Self {
+        self.set_body_stream(BodyStream::Events(events))
+            .set_content_type("text/event-stream")
+            .set_cache_control(CachePolicy::NoCache)
+            .set_header("Connection", "close")
+    }
+}
+
+/// Checks the status is a 3-digit code, optionally followed by a space and a reason phrase.
+fn check_status(status: &str) -> Result<()> {
+    if status.is_empty() {
+        bail!("status must be set on response");
+    }
+
+    let bytes = status.as_bytes();
+    let valid_code = bytes.len() >= 3 && bytes[..3].iter().all(u8::is_ascii_digit);
+    let valid_reason = match bytes.get(3..) {
+        Some([]) => true,
+        Some([b' ', reason @ ..]) => reason
+            .iter()
+            .all(|&byte| byte == b'\t' || !byte.is_ascii_control()),
+        _ => false,
+    };
+    if !valid_code || !valid_reason {
+        bail!("invalid status {status:?}, expected a 3-digit code and an optional reason");
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::http::cookie::SameSitePolicy;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use crate::http::{cookie::SameSitePolicy, HttpRequestRaw};
 
     use super::*;
 
@@ -113,7 +342,7 @@ mod tests {
         let expected = "HTTP/1.1 200 OK\r\n\
 Content-Length: 20\r\n\
 Content-Type: text/html\r\n\
-Date: Tue, 29 Oct 2024 16:56:32 UTC\r\n\
+Date: Tue, 29 Oct 2024 16:56:32 GMT\r\n\
 Set-Cookie: User=jhondoe; SameSite=Lax; Secure\r\n\
 Set-Cookie: foo=bar; HttpOnly; Path=/some/path\r\n\r\n<p>Hello World</p>\r\n"
             .as_bytes();
@@ -143,4 +372,309 @@ Set-Cookie: foo=bar; HttpOnly; Path=/some/path\r\n\r\n<p>Hello World</p>\r\n"
 
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn test_set_text_body() {
+        let response = HttpResponseBuilder::new()
+            .set_text_body("plain text")
+            .build()
+            .unwrap();
+
+        assert_eq!(b"plain text".to_vec(), response.body);
+        assert_eq!(
+            "text/plain; charset=utf-8",
+            response.headers["Content-Type"].value
+        );
+        assert_eq!("10", response.headers["Content-Length"].value);
+    }
+
+    #[test]
+    fn test_set_file_body() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("style.css");
+        std::fs::write(&path, "body { color: red; }").unwrap();
+
+        let response = HttpResponseBuilder::new()
+            .set_file_body(&path)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!("text/css", response.headers["Content-Type"].value);
+        assert_eq!("20", response.headers["Content-Length"].value);
+        assert_eq!(
+            b"body { color: red; }".to_vec(),
+            response.read_body().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_set_file_body_missing_file_err() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let error = HttpResponseBuilder::new()
+            .set_file_body(&dir.path().join("missing.css"))
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("missing.css"));
+        assert!(HttpResponseBuilder::new()
+            .set_file_body(dir.path())
+            .is_err());
+    }
+
+    #[test]
+    fn test_set_digest() {
+        let response = HttpResponseBuilder::new()
+            .set_raw_body(b"{\"hello\": \"world\"}".to_vec())
+            .set_digest()
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            "sha-256=X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=",
+            response.headers["Digest"].value
+        );
+        assert!(digest::verify(&response.headers["Digest"].value, &response.body).is_ok());
+    }
+
+    #[test]
+    fn test_set_allow_canonical_order() {
+        let response = HttpResponseBuilder::new()
+            .set_allow(&[
+                HttpMethod::OPTIONS,
+                HttpMethod::POST,
+                HttpMethod::GET,
+                HttpMethod::HEAD,
+                HttpMethod::GET,
+            ])
+            .build()
+            .unwrap();
+
+        assert_eq!("GET, HEAD, POST, OPTIONS", response.headers["Allow"].value);
+    }
+
+    #[test]
+    fn test_error_text_body() {
+        let actual = HttpResponseBuilder::new()
+            .set_status(HttpStatusCode::NotFound)
+            .set_error_text_body(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(b"404 Not Found".to_vec(), actual.body);
+        assert_eq!(
+            "text/plain; charset=utf-8",
+            actual.headers["Content-Type"].value
+        );
+        assert_eq!("13", actual.headers["Content-Length"].value);
+    }
+
+    #[test]
+    fn test_error_text_body_keeps_bodies_and_success() {
+        let actual = HttpResponseBuilder::new()
+            .set_status(HttpStatusCode::NotFound)
+            .set_error_text_body(false)
+            .build()
+            .unwrap();
+        assert!(actual.body.is_empty());
+        assert!(!actual.headers.contains_key("Content-Type"));
+
+        let actual = HttpResponseBuilder::new()
+            .set_status(HttpStatusCode::InternalServerError)
+            .set_html_body("<p>Oops</p>")
+            .set_error_text_body(true)
+            .build()
+            .unwrap();
+        assert_eq!(b"<p>Oops</p>\r\n".to_vec(), actual.body);
+
+        let actual = HttpResponseBuilder::new()
+            .set_status(HttpStatusCode::NoContent)
+            .set_error_text_body(true)
+            .build()
+            .unwrap();
+        assert!(actual.body.is_empty());
+    }
+
+    #[test]
+    fn test_empty_content_length() {
+        let actual = HttpResponseBuilder::new()
+            .set_status(HttpStatusCode::OK)
+            .set_empty_content_length(true)
+            .build()
+            .unwrap();
+
+        assert_eq!("0", actual.headers.get("Content-Length").unwrap().value);
+        assert!(String::from_utf8(actual.to_bytes().unwrap())
+            .unwrap()
+            .contains("Content-Length: 0\r\n"));
+    }
+
+    #[test]
+    fn test_empty_content_length_disabled() {
+        let actual = HttpResponseBuilder::new()
+            .set_status(HttpStatusCode::OK)
+            .build()
+            .unwrap();
+
+        assert!(!actual.headers.contains_key("Content-Length"));
+    }
+
+    #[test]
+    fn test_empty_content_length_skips_no_content() {
+        let actual = HttpResponseBuilder::new()
+            .set_status(HttpStatusCode::NoContent)
+            .set_empty_content_length(true)
+            .build()
+            .unwrap();
+
+        assert!(!actual.headers.contains_key("Content-Length"));
+    }
+
+    #[test]
+    fn test_status_code_matches_status() {
+        let actual = HttpResponseBuilder::new()
+            .set_status(HttpStatusCode::NotFound)
+            .build()
+            .unwrap();
+        assert_eq!(404, actual.status_code);
+        assert_eq!("404 Not Found", actual.status);
+        assert_eq!(
+            HttpStatusCode::from_u16(actual.status_code)
+                .unwrap()
+                .to_string(),
+            actual.status
+        );
+
+        let actual = HttpResponseBuilder::new()
+            .set_raw_status("299 Custom Reason")
+            .build()
+            .unwrap();
+        assert_eq!(299, actual.status_code);
+        assert!(String::from_utf8(actual.to_bytes().unwrap())
+            .unwrap()
+            .starts_with("HTTP/1.1 299 Custom Reason\r\n"));
+    }
+
+    #[test]
+    fn test_raw_status_validated() {
+        for status in ["404 Not Found", "299", "599 Custom reason"] {
+            assert!(HttpResponseBuilder::new()
+                .set_raw_status(status)
+                .build()
+                .is_ok());
+        }
+
+        for status in [
+            "abc",
+            "",
+            "20 OK",
+            "2000 OK",
+            "200OK",
+            "200 OK\r\nX-Injected: 1",
+        ] {
+            assert!(HttpResponseBuilder::new()
+                .set_raw_status(status)
+                .build()
+                .is_err());
+        }
+    }
+
+    fn get_ranged_request(range: &str) -> HttpRequest {
+        HttpRequest::from_raw_request(HttpRequestRaw {
+            request_line: "GET /report HTTP/1.1".to_owned(),
+            headers: vec![HttpHeader::new("Range", range)],
+            body: vec![],
+            trailers: vec![],
+            peer_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            local_ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_raw_body_range_support() {
+        let body = b"0123456789".to_vec();
+        let ranged_response = |range: &str| {
+            HttpResponseBuilder::new()
+                .set_raw_body(body.clone())
+                .set_content_type("text/plain")
+                .with_range_support(&get_ranged_request(range))
+                .unwrap()
+                .build()
+                .unwrap()
+        };
+
+        let actual = ranged_response("bytes=2-5");
+        assert_eq!(206, actual.status_code);
+        assert_eq!(b"2345".to_vec(), actual.body);
+        assert_eq!("bytes 2-5/10", actual.headers["Content-Range"].value);
+        assert_eq!("4", actual.headers["Content-Length"].value);
+        assert_eq!("text/plain", actual.headers["Content-Type"].value);
+        assert_eq!("bytes", actual.headers["Accept-Ranges"].value);
+
+        let actual = ranged_response("bytes=0-0,-2");
+        assert_eq!(206, actual.status_code);
+        assert!(actual.headers["Content-Type"]
+            .value
+            .starts_with("multipart/byteranges; boundary="));
+
+        let actual = ranged_response("bytes=20-");
+        assert_eq!(416, actual.status_code);
+        assert_eq!("bytes */10", actual.headers["Content-Range"].value);
+        assert!(actual.body.is_empty());
+
+        let actual = ranged_response("lines=1-2");
+        assert_eq!(200, actual.status_code);
+        assert_eq!(body, actual.body);
+
+        let actual = ranged_response(&format!("bytes={}", vec!["0-0"; 17].join(",")));
+        assert_eq!(200, actual.status_code);
+        assert_eq!(body, actual.body);
+
+        let actual = ranged_response("bytes=0-,0-,0-");
+        assert_eq!(206, actual.status_code);
+        assert_eq!(body, actual.body);
+    }
+
+    #[test]
+    fn test_add_link() {
+        let actual = HttpResponseBuilder::new()
+            .add_link("/api/users?page=3", "next")
+            .add_link("/api/users?page=1", "prev")
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            "</api/users?page=3>; rel=\"next\", </api/users?page=1>; rel=\"prev\"",
+            actual.headers["Link"].value
+        );
+    }
+
+    fn get_cache_control(policy: CachePolicy) -> String {
+        let response = HttpResponseBuilder::new()
+            .set_cache_control(policy)
+            .build()
+            .unwrap();
+
+        response.headers.get("Cache-Control").unwrap().value.clone()
+    }
+
+    #[test]
+    fn test_cache_control_presets() {
+        assert_eq!("no-store", get_cache_control(CachePolicy::NoStore));
+        assert_eq!("no-cache", get_cache_control(CachePolicy::NoCache));
+        assert_eq!(
+            "public, max-age=3600",
+            get_cache_control(CachePolicy::Public { max_age: 3600 })
+        );
+        assert_eq!(
+            "private, max-age=60",
+            get_cache_control(CachePolicy::Private { max_age: 60 })
+        );
+        assert_eq!(
+            "public, max-age=31536000, immutable",
+            get_cache_control(CachePolicy::Immutable { max_age: 31536000 })
+        );
+    }
 }
@@ -1,6 +1,7 @@
+use anyhow::{bail, Result};
 use std::fmt::Display;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HttpStatusCode {
     // 1XX
     Continue = 100,
@@ -72,79 +73,199 @@ pub enum HttpStatusCode {
     NetworkAuthenticationRequired = 511,
 }
 
-impl Display for HttpStatusCode {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let value = match self {
+impl HttpStatusCode {
+    /// Gets the status matching a numeric code, e.g. `404` gives [`HttpStatusCode::NotFound`].
+    pub fn from_u16(code: u16) -> Result<Self> {
+        let status = match code {
             // 1XX
-            HttpStatusCode::Continue => "100 Continue",
-            HttpStatusCode::SwitchingProtocols => "101 Switching Protocols",
-            HttpStatusCode::ProcessingDeprecated => "102 Processing Deprecated",
-            HttpStatusCode::EarlyHints => "103 EarlyHints",
+            100 => HttpStatusCode::Continue,
+            101 => HttpStatusCode::SwitchingProtocols,
+            102 => HttpStatusCode::ProcessingDeprecated,
+            103 => HttpStatusCode::EarlyHints,
             // 2XX
-            HttpStatusCode::OK => "200 OK",
-            HttpStatusCode::Created => "201 Created",
-            HttpStatusCode::Accepted => "202 Accepted",
-            HttpStatusCode::NonAuthoritativeInformation => "203 Non Authoritative Information",
-            HttpStatusCode::NoContent => "204 No Content",
-            HttpStatusCode::ResetContent => "205 Reset Content",
-            HttpStatusCode::PartialContent => "206 Partial Content",
-            HttpStatusCode::MultiStatus => "207 Multi Status",
-            HttpStatusCode::AlreadyReported => "208 Already Reported",
-            HttpStatusCode::IMUsed => "226 IM Used",
+            200 => HttpStatusCode::OK,
+            201 => HttpStatusCode::Created,
+            202 => HttpStatusCode::Accepted,
+            203 => HttpStatusCode::NonAuthoritativeInformation,
+            204 => HttpStatusCode::NoContent,
+            205 => HttpStatusCode::ResetContent,
+            206 => HttpStatusCode::PartialContent,
+            207 => HttpStatusCode::MultiStatus,
+            208 => HttpStatusCode::AlreadyReported,
+            226 => HttpStatusCode::IMUsed,
             // 3XX
-            HttpStatusCode::MultipleChoices => "300 Multiple Choices",
-            HttpStatusCode::MovedPermanently => "301 Moved Permanently",
-            HttpStatusCode::Found => "302 Found",
-            HttpStatusCode::SeeOther => "303 See Other",
-            HttpStatusCode::NotModified => "304 Not Modified",
-            HttpStatusCode::UseProxyDeprecated => "305 Use Proxy Deprecated",
-            HttpStatusCode::Unused => "306 Unused",
-            HttpStatusCode::TemporaryRedirect => "307 Temporary Redirect",
-            HttpStatusCode::PermanentRedirect => "308 Permanent Redirect",
+            300 => HttpStatusCode::MultipleChoices,
+            301 => HttpStatusCode::MovedPermanently,
+            302 => HttpStatusCode::Found,
+            303 => HttpStatusCode::SeeOther,
+            304 => HttpStatusCode::NotModified,
+            305 => HttpStatusCode::UseProxyDeprecated,
+            306 => HttpStatusCode::Unused,
+            307 => HttpStatusCode::TemporaryRedirect,
+            308 => HttpStatusCode::PermanentRedirect,
             // 4XX
-            HttpStatusCode::BadRequest => "400 Bad Request",
-            HttpStatusCode::Unauthorized => "401 Unauthorized",
-            HttpStatusCode::PaymentRequired => "402 Payment Required",
-            HttpStatusCode::Forbidden => "403 Forbidden",
-            HttpStatusCode::NotFound => "404 Not Found",
-            HttpStatusCode::MethodNotAllowed => "405 Method Not Allowed",
-            HttpStatusCode::NotAcceptable => "406 Not Acceptable",
-            HttpStatusCode::ProxyAuthenticationRequired => "407 Proxy Authentication Required",
-            HttpStatusCode::RequestTimeout => "408 Request Timeout",
-            HttpStatusCode::Conflict => "409 Conflict",
-            HttpStatusCode::Gone => "410 Gone",
-            HttpStatusCode::LengthRequired => "411 Length Required",
-            HttpStatusCode::PreconditionFailed => "412 Precondition Failed",
-            HttpStatusCode::ContentTooLarge => "413 Content Too Large",
-            HttpStatusCode::URITooLong => "414 URI Too Long",
-            HttpStatusCode::UnsupportedMediaType => "415 Unsupported Media Type",
-            HttpStatusCode::RangeNotSatisfiable => "416 Range Not Satisfiable",
-            HttpStatusCode::ExpectationFailed => "417 Expectation Failed",
-            HttpStatusCode::ImATeapot => "418 I'm a Teapot",
-            HttpStatusCode::MisdirectedRequest => "421 Misdirected Request",
-            HttpStatusCode::UnprocessableContent => "422 Unprocessable Content",
-            HttpStatusCode::Locked => "423 Locked",
-            HttpStatusCode::FailedDependency => "424 Failed Dependency",
-            HttpStatusCode::TooEarlyExperimental => "425 Too Early Experimental",
-            HttpStatusCode::UpgradeRequired => "426 Upgrade Required",
-            HttpStatusCode::PreconditionRequired => "428 Precondition Required",
-            HttpStatusCode::TooManyRequests => "429 Too Many Requests",
-            HttpStatusCode::RequestHeaderFieldsTooLarge => "431 Request Header Fields Too Large",
-            HttpStatusCode::UnavailableForLegalReasons => "451 Unavailable For Legal Reasons",
+            400 => HttpStatusCode::BadRequest,
+            401 => HttpStatusCode::Unauthorized,
+            402 => HttpStatusCode::PaymentRequired,
+            403 => HttpStatusCode::Forbidden,
+            404 => HttpStatusCode::NotFound,
+            405 => HttpStatusCode::MethodNotAllowed,
+            406 => HttpStatusCode::NotAcceptable,
+            407 => HttpStatusCode::ProxyAuthenticationRequired,
+            408 => HttpStatusCode::RequestTimeout,
+            409 => HttpStatusCode::Conflict,
+            410 => HttpStatusCode::Gone,
+            411 => HttpStatusCode::LengthRequired,
+            412 => HttpStatusCode::PreconditionFailed,
+            413 => HttpStatusCode::ContentTooLarge,
+            414 => HttpStatusCode::URITooLong,
+            415 => HttpStatusCode::UnsupportedMediaType,
+            416 => HttpStatusCode::RangeNotSatisfiable,
+            417 => HttpStatusCode::ExpectationFailed,
+            418 => HttpStatusCode::ImATeapot,
+            421 => HttpStatusCode::MisdirectedRequest,
+            422 => HttpStatusCode::UnprocessableContent,
+            423 => HttpStatusCode::Locked,
+            424 => HttpStatusCode::FailedDependency,
+            425 => HttpStatusCode::TooEarlyExperimental,
+            426 => HttpStatusCode::UpgradeRequired,
+            428 => HttpStatusCode::PreconditionRequired,
+            429 => HttpStatusCode::TooManyRequests,
+            431 => HttpStatusCode::RequestHeaderFieldsTooLarge,
+            451 => HttpStatusCode::UnavailableForLegalReasons,
             // 5XX
-            HttpStatusCode::InternalServerError => "500 Internal Server Error",
-            HttpStatusCode::NotImplemented => "501 Not Implemented",
-            HttpStatusCode::BadGateway => "502 Bad Gateway",
-            HttpStatusCode::ServiceUnavailable => "503 Service Unavailable",
-            HttpStatusCode::GatewayTimeout => "504 Gateway Timeout",
-            HttpStatusCode::HTTPVersionNotSupported => "505 HTTP Version Not Supported",
-            HttpStatusCode::VariantAlsoNegotiates => "506 Variant Also Negotiates",
-            HttpStatusCode::InsufficientStorage => "507 Insufficient Storage",
-            HttpStatusCode::LoopDetected => "508 Loop Detected",
-            HttpStatusCode::NotExtended => "510 Not Extended",
-            HttpStatusCode::NetworkAuthenticationRequired => "511 Network Authentication Required",
+            500 => HttpStatusCode::InternalServerError,
+            501 => HttpStatusCode::NotImplemented,
+            502 => HttpStatusCode::BadGateway,
+            503 => HttpStatusCode::ServiceUnavailable,
+            504 => HttpStatusCode::GatewayTimeout,
+            505 => HttpStatusCode::HTTPVersionNotSupported,
+            506 => HttpStatusCode::VariantAlsoNegotiates,
+            507 => HttpStatusCode::InsufficientStorage,
+            508 => HttpStatusCode::LoopDetected,
+            510 => HttpStatusCode::NotExtended,
+            511 => HttpStatusCode::NetworkAuthenticationRequired,
+            _ => bail!("unknown HTTP status code: {code}"),
         };
 
-        write!(f, "{}", value)
+        Ok(status)
+    }
+
+    /// Numeric code of the status, e.g. `404`.
+    pub fn code(&self) -> u16 {
+        *self as u16
+    }
+
+    /// Text following the code in the status line, e.g. `Not Found`.
+    pub fn reason_phrase(&self) -> &'static str {
+        match self {
+            // 1XX
+            HttpStatusCode::Continue => "Continue",
+            HttpStatusCode::SwitchingProtocols => "Switching Protocols",
+            HttpStatusCode::ProcessingDeprecated => "Processing Deprecated",
+            HttpStatusCode::EarlyHints => "EarlyHints",
+            // 2XX
+            HttpStatusCode::OK => "OK",
+            HttpStatusCode::Created => "Created",
+            HttpStatusCode::Accepted => "Accepted",
+            HttpStatusCode::NonAuthoritativeInformation => "Non Authoritative Information",
+            HttpStatusCode::NoContent => "No Content",
+            HttpStatusCode::ResetContent => "Reset Content",
+            HttpStatusCode::PartialContent => "Partial Content",
+            HttpStatusCode::MultiStatus => "Multi Status",
+            HttpStatusCode::AlreadyReported => "Already Reported",
+            HttpStatusCode::IMUsed => "IM Used",
+            // 3XX
+            HttpStatusCode::MultipleChoices => "Multiple Choices",
+            HttpStatusCode::MovedPermanently => "Moved Permanently",
+            HttpStatusCode::Found => "Found",
+            HttpStatusCode::SeeOther => "See Other",
+            HttpStatusCode::NotModified => "Not Modified",
+            HttpStatusCode::UseProxyDeprecated => "Use Proxy Deprecated",
+            HttpStatusCode::Unused => "Unused",
+            HttpStatusCode::TemporaryRedirect => "Temporary Redirect",
+            HttpStatusCode::PermanentRedirect => "Permanent Redirect",
+            // 4XX
+            HttpStatusCode::BadRequest => "Bad Request",
+            HttpStatusCode::Unauthorized => "Unauthorized",
+            HttpStatusCode::PaymentRequired => "Payment Required",
+            HttpStatusCode::Forbidden => "Forbidden",
+            HttpStatusCode::NotFound => "Not Found",
+            HttpStatusCode::MethodNotAllowed => "Method Not Allowed",
+            HttpStatusCode::NotAcceptable => "Not Acceptable",
+            HttpStatusCode::ProxyAuthenticationRequired => "Proxy Authentication Required",
+            HttpStatusCode::RequestTimeout => "Request Timeout",
+            HttpStatusCode::Conflict => "Conflict",
+            HttpStatusCode::Gone => "Gone",
+            HttpStatusCode::LengthRequired => "Length Required",
+            HttpStatusCode::PreconditionFailed => "Precondition Failed",
+            HttpStatusCode::ContentTooLarge => "Content Too Large",
+            HttpStatusCode::URITooLong => "URI Too Long",
+            HttpStatusCode::UnsupportedMediaType => "Unsupported Media Type",
+            HttpStatusCode::RangeNotSatisfiable => "Range Not Satisfiable",
+            HttpStatusCode::ExpectationFailed => "Expectation Failed",
+            HttpStatusCode::ImATeapot => "I'm a Teapot",
+            HttpStatusCode::MisdirectedRequest => "Misdirected Request",
+            HttpStatusCode::UnprocessableContent => "Unprocessable Content",
+            HttpStatusCode::Locked => "Locked",
+            HttpStatusCode::FailedDependency => "Failed Dependency",
+            HttpStatusCode::TooEarlyExperimental => "Too Early Experimental",
+            HttpStatusCode::UpgradeRequired => "Upgrade Required",
+            HttpStatusCode::PreconditionRequired => "Precondition Required",
+            HttpStatusCode::TooManyRequests => "Too Many Requests",
+            HttpStatusCode::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            HttpStatusCode::UnavailableForLegalReasons => "Unavailable For Legal Reasons",
+            // 5XX
+            HttpStatusCode::InternalServerError => "Internal Server Error",
+            HttpStatusCode::NotImplemented => "Not Implemented",
+            HttpStatusCode::BadGateway => "Bad Gateway",
+            HttpStatusCode::ServiceUnavailable => "Service Unavailable",
+            HttpStatusCode::GatewayTimeout => "Gateway Timeout",
+            HttpStatusCode::HTTPVersionNotSupported => "HTTP Version Not Supported",
+            HttpStatusCode::VariantAlsoNegotiates => "Variant Also Negotiates",
+            HttpStatusCode::InsufficientStorage => "Insufficient Storage",
+            HttpStatusCode::LoopDetected => "Loop Detected",
+            HttpStatusCode::NotExtended => "Not Extended",
+            HttpStatusCode::NetworkAuthenticationRequired => "Network Authentication Required",
+        }
+    }
+}
+
+impl Display for HttpStatusCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.code(), self.reason_phrase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_u16_round_trip() {
+        for (code, status, phrase) in [
+            (200, HttpStatusCode::OK, "OK"),
+            (301, HttpStatusCode::MovedPermanently, "Moved Permanently"),
+            (404, HttpStatusCode::NotFound, "Not Found"),
+            (418, HttpStatusCode::ImATeapot, "I'm a Teapot"),
+            (
+                503,
+                HttpStatusCode::ServiceUnavailable,
+                "Service Unavailable",
+            ),
+        ] {
+            let parsed = HttpStatusCode::from_u16(code).unwrap();
+            assert_eq!(status, parsed);
+            assert_eq!(code, parsed.code());
+            assert_eq!(phrase, parsed.reason_phrase());
+            assert_eq!(format!("{code} {phrase}"), parsed.to_string());
+        }
+    }
+
+    #[test]
+    fn test_from_u16_unknown_err() {
+        assert!(HttpStatusCode::from_u16(299).is_err());
+        assert!(HttpStatusCode::from_u16(0).is_err());
+        assert!(HttpStatusCode::from_u16(1000).is_err());
     }
 }
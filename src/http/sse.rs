@@ -0,0 +1,169 @@
+use anyhow::{anyhow, bail, Result};
+use std::{
+    fmt::{self, Debug},
+    io::Write,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, PoisonError,
+    },
+};
+
+/// A single Server-Sent Event, written as `event:`, `id:` and `data:` lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    event: Option<String>,
+    id: Option<String>,
+    data: String,
+}
+
+impl SseEvent {
+    /// Event carrying `data`, each of its lines is sent as a `data:` field.
+    pub fn new(data: &str) -> Self {
+        Self {
+            event: None,
+            id: None,
+            data: data.to_owned(),
+        }
+    }
+
+    /// Sets the event type, dispatched to the matching `addEventListener` in browsers.
+    pub fn event(mut self, name: &str) -> Self {
+        self.event = Some(name.to_owned());
+        self
+    }
+
+    /// Sets the id browsers send back in `Last-Event-ID` when reconnecting.
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(id.to_owned());
+        self
+    }
+
+    /// Formats the event as it is sent on the wire, ending with a blank line.
+    pub fn to_frame(&self) -> String {
+        let mut frame = String::new();
+        if let Some(event) = &self.event {
+            frame.push_str(&format!("event: {event}\n"));
+        }
+        if let Some(id) = &self.id {
+            frame.push_str(&format!("id: {id}\n"));
+        }
+        for line in self.data.split('\n') {
+            frame.push_str(&format!("data: {}\n", line.trim_end_matches('\r')));
+        }
+
+        frame.push('\n');
+        frame
+    }
+}
+
+/// Creates a stream of events, the events sent with the [`EventSender`] are written to the
+/// client as they come until every sender is dropped.
+pub fn channel() -> (EventSender, EventStream) {
+    let (sender, receiver) = mpsc::channel();
+    let stream = EventStream {
+        receiver: Arc::new(Mutex::new(Some(receiver))),
+    };
+
+    (EventSender(sender), stream)
+}
+
+/// Sending half of an event stream, can be moved to another thread to produce the events.
+#[derive(Debug, Clone)]
+pub struct EventSender(Sender<SseEvent>);
+
+impl EventSender {
+    /// Queues `event` to be written, fails once the client is gone.
+    pub fn send(&self, event: SseEvent) -> Result<()> {
+        self.0
+            .send(event)
+            .map_err(|_| anyhow!("event stream closed by the client"))
+    }
+}
+
+/// Body of a `text/event-stream` response, see [`channel`].
+///
+/// Clones share the same events, which can only be written once.
+#[derive(Clone)]
+pub struct EventStream {
+    receiver: Arc<Mutex<Option<Receiver<SseEvent>>>>,
+}
+
+impl Debug for EventStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventStream").finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for EventStream {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.receiver, &other.receiver)
+    }
+}
+
+impl Eq for EventStream {}
+
+impl EventStream {
+    /// Writes the events to `writer` as they are sent, flushing after each of them. Returns
+    /// once all the senders are dropped.
+    pub fn copy_to<W: Write + ?Sized>(&self, writer: &mut W) -> Result<()> {
+        let receiver = self
+            .receiver
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        let Some(receiver) = receiver else {
+            bail!("event stream was already written");
+        };
+
+        // the client should get the headers even if the first event takes a while
+        writer.flush()?;
+        for event in receiver {
+            writer.write_all(event.to_frame().as_bytes())?;
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_event_to_frame() {
+        assert_eq!("data: hello\n\n", SseEvent::new("hello").to_frame());
+        assert_eq!(
+            "event: update\nid: 42\ndata: first\ndata: second\n\n",
+            SseEvent::new("first\r\nsecond")
+                .event("update")
+                .id("42")
+                .to_frame()
+        );
+    }
+
+    #[test]
+    fn test_event_stream_copy_until_senders_dropped() {
+        let (sender, stream) = channel();
+        let producer = thread::spawn(move || {
+            sender.send(SseEvent::new("one")).unwrap();
+            sender.send(SseEvent::new("two")).unwrap();
+        });
+
+        let mut written = vec![];
+        stream.copy_to(&mut written).unwrap();
+        producer.join().unwrap();
+
+        assert_eq!(b"data: one\n\ndata: two\n\n".to_vec(), written);
+        assert!(stream.copy_to(&mut vec![]).is_err());
+    }
+
+    #[test]
+    fn test_send_after_stream_dropped_err() {
+        let (sender, stream) = channel();
+        drop(stream);
+        assert!(sender.send(SseEvent::new("lost")).is_err());
+    }
+}
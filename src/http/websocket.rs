@@ -0,0 +1,431 @@
+use anyhow::{bail, Context, Result};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use log::{debug, trace};
+use sha1::{Digest, Sha1};
+use std::{
+    io::{Read, Write},
+    sync::PoisonError,
+};
+
+use super::{
+    response_status_codes::HttpStatusCode, HttpMethod, HttpRequest, HttpResponse,
+    HttpResponseBuilder, SharedReader,
+};
+
+/// Appended to `Sec-WebSocket-Key` before hashing it into `Sec-WebSocket-Accept`.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Largest message accepted from a client, fragments included.
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Signature of the function taking over the connection once the upgrade response is sent.
+pub type WebSocketCallback = fn(&HttpRequest, &mut WebSocket) -> Result<()>;
+
+/// Computes the `Sec-WebSocket-Accept` value answering a `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.trim().as_bytes());
+    hasher.update(WEBSOCKET_GUID.as_bytes());
+    BASE64_STANDARD.encode(hasher.finalize())
+}
+
+/// Whether the request asks to upgrade the connection to a WebSocket (RFC 6455).
+pub fn is_upgrade_request(request: &HttpRequest) -> bool {
+    let has_token = |name: &str, token: &str| {
+        request
+            .header_values(name)
+            .iter()
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    };
+
+    request.method == HttpMethod::GET
+        && has_token("Upgrade", "websocket")
+        && has_token("Connection", "upgrade")
+        && request.get_header("Sec-WebSocket-Key").is_some()
+}
+
+/// Answers a WebSocket handshake with `101 Switching Protocols`, `callback` then gets the
+/// connection once the response is sent.
+///
+/// Requests that are not a valid handshake get `426 Upgrade Required`.
+pub fn upgrade_response(
+    request: &HttpRequest,
+    callback: WebSocketCallback,
+) -> Result<HttpResponse> {
+    if !is_upgrade_request(request) {
+        return HttpResponseBuilder::new()
+            .set_status(HttpStatusCode::UpgradeRequired)
+            .set_header("Upgrade", "websocket")
+            .set_header("Connection", "Upgrade")
+            .set_empty_content_length(true)
+            .build();
+    }
+
+    let version = request
+        .get_header("Sec-WebSocket-Version")
+        .map(|header| header.value.trim());
+    if version != Some("13") {
+        return HttpResponseBuilder::new()
+            .set_status(HttpStatusCode::UpgradeRequired)
+            .set_header("Sec-WebSocket-Version", "13")
+            .set_empty_content_length(true)
+            .build();
+    }
+
+    let key = &request
+        .get_header("Sec-WebSocket-Key")
+        .context("missing Sec-WebSocket-Key")?
+        .value;
+    let mut response = HttpResponseBuilder::new()
+        .set_status(HttpStatusCode::SwitchingProtocols)
+        .set_header("Upgrade", "websocket")
+        .set_header("Connection", "Upgrade")
+        .set_header("Sec-WebSocket-Accept", &accept_key(key))
+        .build()?;
+    response.upgrade = Some(callback);
+    Ok(response)
+}
+
+/// Kind of a WebSocket frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(value: u8) -> Result<Self> {
+        let opcode = match value {
+            0x0 => Opcode::Continuation,
+            0x1 => Opcode::Text,
+            0x2 => Opcode::Binary,
+            0x8 => Opcode::Close,
+            0x9 => Opcode::Ping,
+            0xA => Opcode::Pong,
+            _ => bail!("unknown WebSocket opcode: {value:#x}"),
+        };
+
+        Ok(opcode)
+    }
+
+    fn as_u8(&self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    fn is_control(&self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+/// A single WebSocket frame, with its payload unmasked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Unfragmented frame.
+    pub fn new(opcode: Opcode, payload: Vec<u8>) -> Self {
+        Self {
+            fin: true,
+            opcode,
+            payload,
+        }
+    }
+
+    /// Reads a frame sent by a client, which must be masked.
+    pub fn read_from<R: Read + ?Sized>(reader: &mut R) -> Result<Self> {
+        let mut head = [0; 2];
+        reader.read_exact(&mut head)?;
+
+        let fin = head[0] & 0x80 != 0;
+        if head[0] & 0x70 != 0 {
+            bail!("WebSocket frame uses reserved bits");
+        }
+        let opcode = Opcode::from_u8(head[0] & 0x0F)?;
+        if head[1] & 0x80 == 0 {
+            bail!("WebSocket frame from the client is not masked");
+        }
+
+        let length = match head[1] & 0x7F {
+            126 => {
+                let mut length = [0; 2];
+                reader.read_exact(&mut length)?;
+                u16::from_be_bytes(length) as u64
+            }
+            127 => {
+                let mut length = [0; 8];
+                reader.read_exact(&mut length)?;
+                u64::from_be_bytes(length)
+            }
+            length => length as u64,
+        };
+        if opcode.is_control() && (!fin || length > 125) {
+            bail!("WebSocket control frame is fragmented or too long");
+        }
+        if length > MAX_MESSAGE_SIZE as u64 {
+            bail!("WebSocket frame of {length} bytes exceeds the limit of {MAX_MESSAGE_SIZE}");
+        }
+
+        let mut mask = [0; 4];
+        reader.read_exact(&mut mask)?;
+        let mut payload = vec![0; length as usize];
+        reader.read_exact(&mut payload)?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+
+        Ok(Self {
+            fin,
+            opcode,
+            payload,
+        })
+    }
+
+    /// Writes the frame unmasked, as servers must.
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> Result<()> {
+        let mut head = vec![(self.fin as u8) << 7 | self.opcode.as_u8()];
+        match self.payload.len() {
+            length @ 0..=125 => head.push(length as u8),
+            length @ 126..=0xFFFF => {
+                head.push(126);
+                head.extend((length as u16).to_be_bytes());
+            }
+            length => {
+                head.push(127);
+                head.extend((length as u64).to_be_bytes());
+            }
+        }
+
+        writer.write_all(&head)?;
+        writer.write_all(&self.payload)?;
+        Ok(writer.flush()?)
+    }
+}
+
+/// Complete message received on a WebSocket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// The client closed the connection, the close frame has already been answered.
+    Close,
+}
+
+/// Server side of an upgraded connection, reassembles fragmented messages and answers pings.
+pub struct WebSocket {
+    reader: SharedReader,
+    writer: Box<dyn Write + Send>,
+}
+
+impl WebSocket {
+    /// Wraps the connection, `reader` may already hold frames sent right after the handshake.
+    pub fn new(reader: SharedReader, writer: impl Write + Send + 'static) -> Self {
+        Self {
+            reader,
+            writer: Box::new(writer),
+        }
+    }
+
+    /// Reads the next frame as is, without answering control frames.
+    pub fn read_frame(&mut self) -> Result<Frame> {
+        let mut reader = self.reader.lock().unwrap_or_else(PoisonError::into_inner);
+        Frame::read_from(&mut *reader)
+    }
+
+    pub fn write_frame(&mut self, frame: &Frame) -> Result<()> {
+        trace!("sending WebSocket frame: {:?}", frame.opcode);
+        frame.write_to(&mut self.writer)
+    }
+
+    /// Reads the next text or binary message, answering the pings and close frames received
+    /// in the meantime.
+    pub fn read_message(&mut self) -> Result<Message> {
+        let mut message: Option<(Opcode, Vec<u8>)> = None;
+        loop {
+            let frame = self.read_frame()?;
+            match frame.opcode {
+                Opcode::Ping => {
+                    self.write_frame(&Frame::new(Opcode::Pong, frame.payload))?;
+                    continue;
+                }
+                Opcode::Pong => continue,
+                Opcode::Close => {
+                    debug!("WebSocket closed by the client");
+                    // echoing the status code is enough to complete the closing handshake
+                    let status = frame.payload.get(..2).unwrap_or_default().to_vec();
+                    self.write_frame(&Frame::new(Opcode::Close, status))?;
+                    return Ok(Message::Close);
+                }
+                Opcode::Text | Opcode::Binary if message.is_none() => {
+                    message = Some((frame.opcode, frame.payload));
+                }
+                Opcode::Continuation if message.is_some() => {
+                    if let Some((_, payload)) = message.as_mut() {
+                        if payload.len() + frame.payload.len() > MAX_MESSAGE_SIZE {
+                            bail!("WebSocket message exceeds the limit of {MAX_MESSAGE_SIZE}");
+                        }
+                        payload.extend(frame.payload);
+                    }
+                }
+                opcode => bail!("unexpected WebSocket frame: {opcode:?}"),
+            }
+
+            if !frame.fin {
+                continue;
+            }
+
+            return match message.take() {
+                Some((Opcode::Text, payload)) => Ok(Message::Text(
+                    String::from_utf8(payload).context("WebSocket text message is not UTF-8")?,
+                )),
+                Some((_, payload)) => Ok(Message::Binary(payload)),
+                None => bail!("WebSocket message has no data frame"),
+            };
+        }
+    }
+
+    pub fn send_text(&mut self, text: &str) -> Result<()> {
+        self.write_frame(&Frame::new(Opcode::Text, text.as_bytes().to_vec()))
+    }
+
+    pub fn send_binary(&mut self, bytes: &[u8]) -> Result<()> {
+        self.write_frame(&Frame::new(Opcode::Binary, bytes.to_vec()))
+    }
+
+    /// Starts the closing handshake with a normal closure status.
+    pub fn close(&mut self) -> Result<()> {
+        self.write_frame(&Frame::new(Opcode::Close, 1000u16.to_be_bytes().to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufReader, Cursor},
+        net::IpAddr,
+        str::FromStr,
+        sync::{Arc, Mutex},
+    };
+
+    use crate::http::{HttpHeader, HttpRequestRaw};
+
+    use super::*;
+
+    /// Frame as a client would send it.
+    fn masked_frame(first_byte: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![first_byte, 0x80 | payload.len() as u8];
+        frame.extend(mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        frame
+    }
+
+    fn get_websocket(client_bytes: Vec<u8>) -> WebSocket {
+        let reader: SharedReader = Arc::new(Mutex::new(BufReader::new(Cursor::new(client_bytes))));
+        WebSocket::new(reader, Vec::new())
+    }
+
+    fn get_request(headers: &[(&str, &str)]) -> HttpRequest {
+        HttpRequest::from_raw_request(HttpRequestRaw {
+            request_line: "GET /chat HTTP/1.1".to_owned(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| HttpHeader::new(name, value))
+                .collect(),
+            body: vec![],
+            trailers: vec![],
+            peer_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+            local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+        })
+        .unwrap()
+    }
+
+    fn echo(_request: &HttpRequest, _websocket: &mut WebSocket) -> Result<()> {
+        Ok(())
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // example from RFC 6455
+        assert_eq!(
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=",
+            accept_key("dGhlIHNhbXBsZSBub25jZQ==")
+        );
+    }
+
+    #[test]
+    fn test_upgrade_response() {
+        let request = get_request(&[
+            ("Upgrade", "websocket"),
+            ("Connection", "keep-alive, Upgrade"),
+            ("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="),
+            ("Sec-WebSocket-Version", "13"),
+        ]);
+        let response = upgrade_response(&request, echo).unwrap();
+        assert_eq!("101 Switching Protocols", response.status);
+        assert_eq!(
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=",
+            response.headers["Sec-WebSocket-Accept"].value
+        );
+        assert!(response.upgrade.is_some());
+
+        let response = upgrade_response(&get_request(&[]), echo).unwrap();
+        assert_eq!("426 Upgrade Required", response.status);
+        assert!(response.upgrade.is_none());
+    }
+
+    #[test]
+    fn test_read_message_reassembles_fragments_and_answers_ping() {
+        let mut client_bytes = masked_frame(0x01, b"hel");
+        client_bytes.extend(masked_frame(0x89, b"ping"));
+        client_bytes.extend(masked_frame(0x80, b"lo"));
+        let mut websocket = get_websocket(client_bytes);
+
+        assert_eq!(
+            Message::Text("hello".to_owned()),
+            websocket.read_message().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_read_frame_unmasked_err() {
+        let mut websocket = get_websocket(vec![0x81, 0x02, b'h', b'i']);
+        assert!(websocket.read_frame().is_err());
+    }
+
+    #[test]
+    fn test_write_frame_lengths() {
+        let mut written = vec![];
+        Frame::new(Opcode::Text, b"hi".to_vec())
+            .write_to(&mut written)
+            .unwrap();
+        assert_eq!(vec![0x81, 0x02, b'h', b'i'], written);
+
+        let mut written = vec![];
+        Frame::new(Opcode::Binary, vec![0; 300])
+            .write_to(&mut written)
+            .unwrap();
+        assert_eq!(vec![0x82, 126, 0x01, 0x2C], written[..4]);
+        assert_eq!(304, written.len());
+    }
+}
@@ -1,5 +1,10 @@
+pub mod access_log;
+pub mod compression;
+pub mod cors;
 pub mod file_server;
 pub mod http;
+pub mod middleware;
 pub mod router;
+pub mod routes;
 pub mod thread_pool;
 pub mod web_server;
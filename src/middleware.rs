@@ -0,0 +1,206 @@
+use anyhow::Result;
+use std::fmt::Debug;
+
+use crate::http::{HttpRequest, HttpResponse};
+
+/// Signature of a middleware that only needs to run before the handlers.
+pub type BeforeCallback = fn(&HttpRequest) -> Result<Option<HttpResponse>>;
+
+/// Behavior shared by all the requests going through a [`Router`](crate::router::Router).
+///
+/// Middleware run in the order they were registered before the request is routed, then in
+/// reverse order on the way out. An error from either step is answered like a failing handler.
+pub trait Middleware: Debug + Send + Sync {
+    /// Runs before the request is routed, returning a response skips the handler and the
+    /// remaining middleware.
+    fn before(&self, _request: &HttpRequest) -> Result<Option<HttpResponse>> {
+        Ok(None)
+    }
+
+    /// Runs on the response before it is sent back.
+    fn after(&self, _request: &HttpRequest, _response: &mut HttpResponse) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Middleware for BeforeCallback {
+    fn before(&self, request: &HttpRequest) -> Result<Option<HttpResponse>> {
+        self(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, str::FromStr};
+
+    use crate::{
+        file_server::FileServer,
+        http::{
+            response_status_codes::HttpStatusCode, HttpHeader, HttpMethod, HttpRequestRaw,
+            HttpResponseBuilder,
+        },
+        router::{Router, RoutingData},
+    };
+
+    use super::*;
+
+    fn require_token(request: &HttpRequest) -> Result<Option<HttpResponse>> {
+        if request.get_header("X-Token").is_some() {
+            return Ok(None);
+        }
+
+        let response = HttpResponseBuilder::new()
+            .set_status(HttpStatusCode::Unauthorized)
+            .build()?;
+        Ok(Some(response))
+    }
+
+    /// Appends its name to the `X-Trace` header of every response.
+    #[derive(Debug)]
+    struct Trace(&'static str);
+
+    impl Middleware for Trace {
+        fn after(&self, _request: &HttpRequest, response: &mut HttpResponse) -> Result<()> {
+            let trace = match response.headers.get("X-Trace") {
+                Some(header) => format!("{},{}", header.value, self.0),
+                None => self.0.to_owned(),
+            };
+            response
+                .headers
+                .insert("X-Trace".to_owned(), HttpHeader::new("X-Trace", &trace));
+            Ok(())
+        }
+    }
+
+    fn failing_before(_request: &HttpRequest) -> Result<Option<HttpResponse>> {
+        anyhow::bail!("session store unreachable")
+    }
+
+    #[derive(Debug)]
+    struct FailingAfter;
+
+    impl Middleware for FailingAfter {
+        fn after(&self, _request: &HttpRequest, _response: &mut HttpResponse) -> Result<()> {
+            anyhow::bail!("response signing failed")
+        }
+    }
+
+    fn get_hello(_request: &HttpRequest, _routing_data: &RoutingData) -> Result<HttpResponse> {
+        HttpResponseBuilder::new().set_html_body("hello").build()
+    }
+
+    fn get_protected_router() -> Router {
+        Router::new()
+            .use_middleware(Trace("outer"))
+            .use_middleware(require_token as BeforeCallback)
+            .use_middleware(Trace("inner"))
+            .get("/hello", get_hello)
+            .unwrap()
+    }
+
+    fn get_request(request_line: &str, headers: Vec<HttpHeader>) -> HttpRequest {
+        HttpRequest::from_raw_request(HttpRequestRaw {
+            request_line: request_line.to_owned(),
+            headers,
+            body: vec![],
+            trailers: vec![],
+            peer_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+            local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_auth_middleware_blocks_without_header() {
+        let request = get_request("GET /hello HTTP/1.1", vec![]);
+        let response = get_protected_router().handle_request(&request).unwrap();
+
+        assert_eq!(HttpStatusCode::Unauthorized.to_string(), response.status);
+        assert!(response.body.is_empty());
+        // only the middleware that ran before the short-circuit post-process the response
+        assert_eq!("outer", response.headers.get("X-Trace").unwrap().value);
+    }
+
+    #[test]
+    fn test_auth_middleware_passes_with_header() {
+        let request = get_request(
+            "GET /hello HTTP/1.1",
+            vec![HttpHeader::new("X-Token", "secret")],
+        );
+        let response = get_protected_router().handle_request(&request).unwrap();
+
+        assert_eq!("200 OK", response.status);
+        assert_eq!(b"hello\r\n".to_vec(), response.body);
+        assert_eq!(
+            "inner,outer",
+            response.headers.get("X-Trace").unwrap().value
+        );
+    }
+
+    #[test]
+    fn test_middleware_runs_for_file_server_and_catcher() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "notes").unwrap();
+        let file_server = FileServer::new()
+            .map_dir("/files", dir.path().to_str().unwrap())
+            .unwrap();
+
+        let router = get_protected_router()
+            .set_file_server(file_server)
+            .catch_all(HttpMethod::GET, get_hello)
+            .unwrap();
+
+        let request = get_request("GET /files/notes.txt HTTP/1.1", vec![]);
+        let response = router.handle_request(&request).unwrap();
+        assert_eq!(HttpStatusCode::Unauthorized.to_string(), response.status);
+
+        let request = get_request("GET /unknown HTTP/1.1", vec![]);
+        let response = router.handle_request(&request).unwrap();
+        assert_eq!(HttpStatusCode::Unauthorized.to_string(), response.status);
+
+        let token = vec![HttpHeader::new("X-Token", "secret")];
+        let request = get_request("GET /files/notes.txt HTTP/1.1", token.clone());
+        let response = router.handle_request(&request).unwrap();
+        assert_eq!(b"notes".to_vec(), response.read_body().unwrap());
+        assert_eq!(
+            "inner,outer",
+            response.headers.get("X-Trace").unwrap().value
+        );
+
+        let request = get_request("GET /unknown HTTP/1.1", token);
+        let response = router.handle_request(&request).unwrap();
+        assert_eq!(
+            "inner,outer",
+            response.headers.get("X-Trace").unwrap().value
+        );
+    }
+
+    #[test]
+    fn test_failing_middleware_answers_500() {
+        let request = get_request("GET /hello HTTP/1.1", vec![]);
+
+        let router = Router::new()
+            .use_middleware(Trace("outer"))
+            .use_middleware(failing_before as BeforeCallback)
+            .get("/hello", get_hello)
+            .unwrap();
+        let response = router.handle_request(&request).unwrap();
+        assert_eq!(
+            HttpStatusCode::InternalServerError.to_string(),
+            response.status
+        );
+        assert_eq!("outer", response.headers.get("X-Trace").unwrap().value);
+
+        let router = Router::new()
+            .use_middleware(Trace("outer"))
+            .use_middleware(FailingAfter)
+            .get("/hello", get_hello)
+            .unwrap();
+        let response = router.handle_request(&request).unwrap();
+        assert_eq!(
+            HttpStatusCode::InternalServerError.to_string(),
+            response.status
+        );
+        assert_eq!("outer", response.headers.get("X-Trace").unwrap().value);
+    }
+}
@@ -1,20 +1,43 @@
 use anyhow::{bail, Context, Result};
-use log::{debug, trace};
-use std::{collections::HashMap, fs, str::FromStr};
+use log::{debug, error, trace};
+use serde::Serialize;
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt::{self, Debug},
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+};
 
 use crate::{
+    cors::CorsConfig,
     file_server::FileServer,
     http::{
-        response_status_codes::HttpStatusCode, HttpMethod, HttpRequest, HttpResponse,
-        HttpResponseBuilder,
+        percent_encoding,
+        range::{MultipartByteranges, RangeSpec},
+        response_status_codes::HttpStatusCode,
+        websocket::{self, WebSocketCallback},
+        BodyStream, HttpMethod, HttpRequest, HttpResponse, HttpResponseBuilder,
     },
+    middleware::Middleware,
 };
 
 #[derive(Debug)]
 pub struct Router {
-    pub routes: HashMap<StoredRoute, RoutingCallback>,
+    /// Registered through [`Router::add_route`] and the like only, so that `route_tree` which
+    /// the requests are matched against stays in sync.
+    routes: HashMap<StoredRoute, Box<dyn Handler>>,
     pub catcher_routes: HashMap<HttpMethod, RoutingCallback>,
     pub file_server: Option<FileServer>,
+    route_tree: RouteNode,
+    auto_options: bool,
+    auto_head: bool,
+    file_server_first: bool,
+    middlewares: Vec<Box<dyn Middleware>>,
+    state: Option<Arc<dyn Any + Send + Sync>>,
+    path_rewriter: Option<PathRewriter>,
+    error_handler: Option<ErrorHandler>,
 }
 
 impl Default for Router {
@@ -29,62 +52,136 @@ impl Router {
             routes: HashMap::new(),
             catcher_routes: HashMap::new(),
             file_server: None,
+            route_tree: RouteNode::default(),
+            auto_options: false,
+            auto_head: false,
+            file_server_first: false,
+            middlewares: Vec::new(),
+            state: None,
+            path_rewriter: None,
+            error_handler: None,
         }
     }
 
+    /// When enabled, `OPTIONS` requests without an explicit handler are answered with
+    /// `204 No Content` and an `Allow` header listing the methods registered for the path.
+    pub fn auto_options(mut self, enabled: bool) -> Self {
+        self.auto_options = enabled;
+        self
+    }
+
+    /// When enabled, `HEAD` requests without an explicit handler are answered by the `GET` route
+    /// of the path, the server then only sends the headers of its response.
+    pub fn auto_head(mut self, enabled: bool) -> Self {
+        self.auto_head = enabled;
+        self
+    }
+
+    /// Registers a rewrite of the request path run before it is matched against the routes,
+    /// returning `None` keeps the path as is.
+    ///
+    /// The rewriter gets the path as sent by the client, still percent-encoded and without the
+    /// query. Handlers, middleware and the file server still see the original request.
+    pub fn path_rewriter(mut self, rewriter: PathRewriter) -> Self {
+        self.path_rewriter = Some(rewriter);
+        self
+    }
+
+    /// Registers the handler building the response sent when a route handler returns an error.
+    ///
+    /// Without one, the error is logged and the client gets a bare `500 Internal Server Error`
+    /// so its details are not leaked.
+    pub fn error_handler(mut self, handler: ErrorHandler) -> Self {
+        self.error_handler = Some(handler);
+        self
+    }
+
+    /// When enabled, the file server is checked before routes with dynamic or wildcard parts,
+    /// routes made only of static parts still come first.
+    ///
+    /// By default, every declared route is checked before the file server.
+    pub fn file_server_first(mut self, enabled: bool) -> Self {
+        self.file_server_first = enabled;
+        self
+    }
+
+    /// Stores application state shared by all the handlers, e.g. a database pool or a cache.
+    ///
+    /// Handlers get it back with [`RoutingData::state`], use atomics or locks inside `state` for
+    /// anything that needs to be modified:
+    ///
+    /// ```
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    ///
+    /// use anyhow::Result;
+    /// use rtfw_http::http::{HttpRequest, HttpResponse, HttpResponseBuilder};
+    /// use rtfw_http::router::{Router, RoutingData};
+    ///
+    /// struct AppState {
+    ///     visits: AtomicUsize,
+    /// }
+    ///
+    /// fn get_visits(_request: &HttpRequest, routing_data: &RoutingData) -> Result<HttpResponse> {
+    ///     let state = routing_data.state::<AppState>()?;
+    ///     let visits = state.visits.fetch_add(1, Ordering::SeqCst) + 1;
+    ///     HttpResponseBuilder::new().set_html_body(&visits.to_string()).build()
+    /// }
+    ///
+    /// let router = Router::new()
+    ///     .with_state(AppState { visits: AtomicUsize::new(0) })
+    ///     .get("/visits", get_visits)
+    ///     .unwrap();
+    /// ```
+    pub fn with_state<T: Send + Sync + 'static>(mut self, state: T) -> Self {
+        self.state = Some(Arc::new(state));
+        self
+    }
+
+    /// Registers a middleware run for every request, including the ones served by the file
+    /// server and catchers.
+    pub fn use_middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+
+    /// Answers CORS preflight requests and adds the CORS headers to every response.
+    pub fn enable_cors(self, cors: CorsConfig) -> Self {
+        self.use_middleware(cors)
+    }
+
+    /// Registered routes along with their handler.
+    pub fn routes(&self) -> &HashMap<StoredRoute, Box<dyn Handler>> {
+        &self.routes
+    }
+
     pub fn set_file_server(mut self, file_server: FileServer) -> Self {
         self.file_server = Some(file_server);
         self
     }
 
     fn find_matching_route(&self, request_route: &RequestRoute) -> Result<Option<&StoredRoute>> {
-        let mut excluded: Vec<&StoredRoute> = vec![];
-        let request_route_parts = request_route.path.split('/');
-        trace!("trying to match request parts: {:?}", request_route_parts);
-
-        let matching_candidates: Vec<_> = self
-            .routes
-            .keys()
-            .filter(|route| route.method == request_route.method)
-            .collect();
-
-        for (idx, part) in request_route_parts.enumerate() {
-            for match_candidate in matching_candidates.iter() {
-                if excluded.contains(match_candidate) {
-                    continue;
-                };
-
-                if let Some(match_part) = match_candidate.parts.get(idx) {
-                    if !match_part.is_dynamic && !match_part.name.eq(part) {
-                        trace!(
-                            "excluding server route from search because part differ and not dynamic: {:?}",
-                            match_candidate
-                        );
-                        excluded.push(match_candidate);
-                    }
-                } else {
-                    trace!(
-                        "excluding server route from search because too small: {:?}",
-                        match_candidate
-                    );
-                    excluded.push(match_candidate);
-                };
-            }
-        }
+        let segments = request_route.segments()?;
+        let segments: Vec<_> = segments.iter().map(String::as_str).collect();
+        trace!("trying to match request parts: {:?}", segments);
 
-        let selected_routes: Vec<_> = matching_candidates
-            .iter()
-            .filter(|route| !excluded.contains(route))
-            .collect();
+        let mut selected_routes = vec![];
+        self.route_tree.visit(&segments, &mut |node| {
+            selected_routes = node
+                .routes
+                .iter()
+                .filter(|route| route.method == request_route.method)
+                .collect();
+            !selected_routes.is_empty()
+        });
 
         trace!(
             "selected routes (should only have 1 or 0): {:?}",
             selected_routes
         );
 
-        match selected_routes.len() {
-            0 => Ok(None),
-            1 => Ok(Some(selected_routes.first().unwrap())),
+        match selected_routes.as_slice() {
+            [] => Ok(None),
+            [route] => Ok(Some(route)),
             _ => bail!(
                 "multiple selected routes even though that should not happen: {:?}",
                 selected_routes
@@ -92,47 +189,218 @@ impl Router {
         }
     }
 
+    /// Methods of all the routes whose path matches, regardless of the request method.
+    fn find_allowed_methods(&self, request_route: &RequestRoute) -> Result<Vec<HttpMethod>> {
+        let segments = request_route.segments()?;
+        let segments: Vec<_> = segments.iter().map(String::as_str).collect();
+
+        let mut methods = vec![];
+        self.route_tree.visit(&segments, &mut |node| {
+            methods.extend(node.routes.iter().map(|route| route.method.clone()));
+            false
+        });
+
+        if self.auto_head && methods.contains(&HttpMethod::GET) {
+            methods.push(HttpMethod::HEAD);
+        }
+
+        methods.sort();
+        methods.dedup();
+        Ok(methods)
+    }
+
     pub fn handle_request(&self, request: &HttpRequest) -> Result<HttpResponse> {
-        let route_def = format!("{} {}", request.method, request.url);
-        let route = RequestRoute::from_str(&route_def)?;
-        debug!("trying to match route: {route_def}");
+        let mut early_response = None;
+        let mut entered = 0;
+        for middleware in &self.middlewares {
+            match middleware.before(request) {
+                Ok(None) => entered += 1,
+                Ok(Some(response)) => {
+                    debug!("middleware answered the request: {:?}", middleware);
+                    early_response = Some(response);
+                    break;
+                }
+                Err(e) => {
+                    early_response = Some(self.error_response(request, &e)?);
+                    break;
+                }
+            }
+        }
+
+        let mut response = match early_response {
+            Some(response) => response,
+            None => match self.route_request(request) {
+                Ok(response) => response,
+                Err(e) => self.error_response(request, &e)?,
+            },
+        };
+
+        for middleware in self.middlewares[..entered].iter().rev() {
+            if let Err(e) = middleware.after(request, &mut response) {
+                response = self.error_response(request, &e)?;
+            }
+        }
+
+        Ok(response)
+    }
+
+    fn error_response(&self, request: &HttpRequest, e: &anyhow::Error) -> Result<HttpResponse> {
+        error!(
+            "handler failed on {} {}: {e:#}",
+            request.method, request.resource_path
+        );
+
+        if let Some(error_handler) = self.error_handler {
+            match error_handler(request, e) {
+                Ok(response) => return Ok(response),
+                Err(e) => error!("error handler failed: {e:#}"),
+            }
+        }
+
+        HttpResponseBuilder::new()
+            .set_status(HttpStatusCode::InternalServerError)
+            .set_error_text_body(true)
+            .build()
+    }
+
+    fn try_file_server(&self, request: &HttpRequest) -> Result<Option<HttpResponse>> {
+        let Some(file_server) = &self.file_server else {
+            return Ok(None);
+        };
+
+        debug!("attempting with file server");
+        let file_path = match file_server.handle_file_access(&request.url) {
+            Ok(file_path) => file_path,
+            Err(e) => {
+                debug!("no match with file server: {e}");
+                return Ok(None);
+            }
+        };
+
+        if file_server.needs_trailing_slash(&request.url) {
+            // keeps the path as sent by the client, it is already encoded
+            let target = request.request_target();
+            let location = match target.split_once('?') {
+                Some((path, query)) => format!("{path}/?{query}"),
+                None => format!("{target}/"),
+            };
+            debug!("redirecting directory request to: {location}");
+            let response = HttpResponseBuilder::new()
+                .set_status(HttpStatusCode::MovedPermanently)
+                .set_header("Location", &location)
+                .build()?;
+            return Ok(Some(response));
+        }
+
+        if !file_server.is_access_allowed(request, &file_path) {
+            debug!("file server denied access to: {}", file_path.display());
+            let response = HttpResponseBuilder::new()
+                .set_status(HttpStatusCode::Forbidden)
+                .build()?;
+            return Ok(Some(response));
+        }
+
+        if file_path.is_dir() {
+            debug!("rendering directory listing: {}", file_path.display());
+            let response = file_server.render_directory_listing(&request.url, &file_path)?;
+            return Ok(Some(response));
+        }
+
+        let mime_type = file_server.mime_type(&file_path);
+        Ok(Some(build_file_response(request, &file_path, &mime_type)?))
+    }
+
+    fn route_request(&self, request: &HttpRequest) -> Result<HttpResponse> {
+        // a static site has nothing to match, straight to the files and the catchers
+        if self.routes.is_empty() {
+            if let Some(response) = self.try_file_server(request)? {
+                return Ok(response);
+            }
+
+            return self.catcher_response(request);
+        }
+
+        // match on the raw path so that encoded `/` stay inside their segment
+        let raw_path = request.resource_path.split('?').next().unwrap_or_default();
+        let rewritten_path = self.path_rewriter.and_then(|rewriter| rewriter(raw_path));
+        if let Some(rewritten_path) = &rewritten_path {
+            debug!("rewrote request path: {raw_path} -> {rewritten_path}");
+        }
+        let raw_path = rewritten_path.as_deref().unwrap_or(raw_path);
+        let route = RequestRoute::new(request.method.clone(), raw_path);
+        debug!("trying to match route: {} {}", route.method, route.path);
 
         // test against declared routes
-        let matching_result = self.find_matching_route(&route)?;
+        let mut matching_result = self.find_matching_route(&route)?;
+        if matching_result.is_none() && self.auto_head && route.method == HttpMethod::HEAD {
+            let get_route = RequestRoute::new(HttpMethod::GET, raw_path);
+            matching_result = self.find_matching_route(&get_route)?;
+        }
+        let check_files_first = self.file_server_first
+            && matching_result.is_some_and(|matching_route| !matching_route.is_static());
+        if check_files_first {
+            if let Some(response) = self.try_file_server(request)? {
+                return Ok(response);
+            }
+        }
+
         if let Some(matching_route) = matching_result {
             debug!("found matching server route: {:?}", matching_route);
-            let routing_data = matching_route.extract_routing_data(&request.url)?;
-            let callback = self
+            let mut routing_data = matching_route.extract_routing_data(&route.path)?;
+            routing_data.state = self.state.clone();
+            routing_data.query = request.query.clone();
+            let handler = self
                 .routes
                 .get(matching_route)
-                .context("failed to get callback, even though route should be a valid key")?;
+                .context("failed to get handler, even though route should be a valid key")?;
 
-            return callback(request, &routing_data);
+            return handler.handle(request, &routing_data);
         }
 
         debug!("no matching server route, trying other options...");
 
         // test against file server static mappings
-        if let Some(file_server) = &self.file_server {
-            debug!("attempting with file server");
-            match file_server.handle_file_access(&route.path) {
-                Ok(file_path) => {
-                    let mime_type = mime_guess::from_path(&file_path).first_or_octet_stream();
-                    let content = fs::read(file_path)?;
-
-                    return HttpResponseBuilder::new()
-                        .set_raw_body(content)
-                        .set_content_type(mime_type.as_ref())
-                        .build();
-                }
-                Err(e) => debug!("no match with file server: {e}"),
+        if !check_files_first {
+            if let Some(response) = self.try_file_server(request)? {
+                return Ok(response);
+            }
+        }
+
+        // test against routes registered for other methods
+        let mut allowed_methods = self.find_allowed_methods(&route)?;
+        if !allowed_methods.is_empty() {
+            if self.auto_options && request.method == HttpMethod::OPTIONS {
+                allowed_methods.push(HttpMethod::OPTIONS);
+                allowed_methods.sort();
+                allowed_methods.dedup();
+
+                debug!("answering OPTIONS automatically");
+                return HttpResponseBuilder::new()
+                    .set_status(HttpStatusCode::NoContent)
+                    .set_allow(&allowed_methods)
+                    .build();
             }
+
+            debug!("path is only registered for: {allowed_methods:?}, return 405");
+            return HttpResponseBuilder::new()
+                .set_status(HttpStatusCode::MethodNotAllowed)
+                .set_allow(&allowed_methods)
+                .build();
         }
 
-        // test against catcher routes
+        self.catcher_response(request)
+    }
+
+    /// Answers a request no route nor file matched, with the catcher of its method or a 404.
+    fn catcher_response(&self, request: &HttpRequest) -> Result<HttpResponse> {
         if let Some(catcher) = self.catcher_routes.get(&request.method) {
             debug!("defaulting to catcher for {}", request.method.to_string());
-            return catcher(request, &RoutingData::default());
+            let routing_data = RoutingData {
+                state: self.state.clone(),
+                query: request.query.clone(),
+                ..Default::default()
+            };
+            return catcher(request, &routing_data);
         }
 
         debug!("no default catcher, return 404");
@@ -162,20 +430,57 @@ impl Router {
         method: HttpMethod,
         path: &str,
         callback: RoutingCallback,
+    ) -> Result<()> {
+        self.add_handler(method, path, Box::new(callback))
+    }
+
+    fn add_handler(
+        &mut self,
+        method: HttpMethod,
+        path: &str,
+        handler: Box<dyn Handler>,
     ) -> Result<()> {
         let route = StoredRoute::new(method, path)?;
 
-        if self.routes.contains_key(&route) {
+        if let Some(existing) = self
+            .routes
+            .keys()
+            .find(|other| other.conflicts_with(&route))
+        {
             bail!(
-                "cannot register route {:?} because a similar route already exists",
-                route
+                "cannot register route {} /{} because it matches the same requests as {} /{}",
+                route.method,
+                route.path,
+                existing.method,
+                existing.path
             );
         }
 
-        self.routes.insert(route, callback);
+        self.route_tree.insert(route.clone());
+        self.routes.insert(route, handler);
         Ok(())
     }
 
+    /// Registers a handler whose returned value is sent as a `200 OK` JSON response.
+    ///
+    /// An error returned by the handler is answered like for any other route, see
+    /// [`Router::error_handler`].
+    pub fn json_route<T: Serialize + 'static>(
+        mut self,
+        method: HttpMethod,
+        path: &str,
+        callback: JsonCallback<T>,
+    ) -> Result<Self> {
+        self.add_handler(method, path, Box::new(JsonHandler(callback)))?;
+        Ok(self)
+    }
+
+    /// Accepts WebSocket handshakes on `GET path`, `callback` then takes over the connection.
+    pub fn websocket(mut self, path: &str, callback: WebSocketCallback) -> Result<Self> {
+        self.add_handler(HttpMethod::GET, path, Box::new(callback))?;
+        Ok(self)
+    }
+
     pub fn get(mut self, path: &str, callback: RoutingCallback) -> Result<Self> {
         self.add_route(HttpMethod::GET, path, callback)?;
         Ok(self)
@@ -227,6 +532,69 @@ impl Router {
     }
 }
 
+fn build_file_response(
+    request: &HttpRequest,
+    file_path: &Path,
+    mime_type: &str,
+) -> Result<HttpResponse> {
+    let total_len = file_path.metadata()?.len();
+
+    let range_spec = match request.get_header("Range") {
+        Some(header) => match RangeSpec::parse(&header.value) {
+            Ok(range_spec) => Some(range_spec),
+            Err(e) => {
+                debug!("ignoring invalid Range header: {e}");
+                None
+            }
+        },
+        None => None,
+    };
+
+    let Some(range_spec) = range_spec else {
+        return HttpResponseBuilder::new()
+            .set_body_stream(BodyStream::File {
+                path: file_path.to_owned(),
+                start: 0,
+                len: total_len,
+            })
+            .set_content_type(mime_type)
+            .set_header("Accept-Ranges", "bytes")
+            .build();
+    };
+
+    match range_spec.resolve(total_len).as_slice() {
+        [] => HttpResponseBuilder::new()
+            .set_status(HttpStatusCode::RangeNotSatisfiable)
+            .set_header("Content-Range", &format!("bytes */{total_len}"))
+            .build(),
+        [range] => HttpResponseBuilder::new()
+            .set_status(HttpStatusCode::PartialContent)
+            .set_body_stream(BodyStream::File {
+                path: file_path.to_owned(),
+                start: range.start,
+                len: range.length(),
+            })
+            .set_content_type(mime_type)
+            .set_header("Accept-Ranges", "bytes")
+            .set_header("Content-Range", &range.content_range(total_len))
+            .build(),
+        ranges => {
+            let multipart = MultipartByteranges::new(ranges, mime_type, total_len);
+            let content_type = format!("multipart/byteranges; boundary={}", multipart.boundary);
+
+            HttpResponseBuilder::new()
+                .set_status(HttpStatusCode::PartialContent)
+                .set_body_stream(BodyStream::FileRanges {
+                    path: file_path.to_owned(),
+                    multipart,
+                })
+                .set_content_type(&content_type)
+                .set_header("Accept-Ranges", "bytes")
+                .build()
+        }
+    }
+}
+
 #[derive(Debug, Hash, Eq, PartialEq, Clone)]
 pub struct StoredRoute {
     pub method: HttpMethod,
@@ -237,56 +605,214 @@ pub struct StoredRoute {
 impl StoredRoute {
     pub fn new(method: HttpMethod, path: &str) -> Result<Self> {
         let path = path.trim_matches('/').to_owned();
+        let parts = Self::parse_parts(&path)?;
+
+        Ok(Self {
+            method,
+            path,
+            parts,
+        })
+    }
+
+    /// Checks that `path` is a valid route pattern, with the same rules as [`StoredRoute::new`].
+    pub fn validate_pattern(path: &str) -> Result<()> {
+        Self::parse_parts(path.trim_matches('/'))?;
+        Ok(())
+    }
 
+    fn parse_parts(path: &str) -> Result<Vec<RoutePart>> {
+        let segments: Vec<_> = path.split('/').collect();
         let mut parts = vec![];
-        for part in path.split('/') {
+        for (idx, part) in segments.iter().enumerate() {
+            let is_last = idx == segments.len() - 1;
             let is_dynamic = part.starts_with(':');
-            let value = if is_dynamic {
-                part[1..].to_string()
-            } else {
-                part.to_string()
-            };
+            let is_wildcard = part.ends_with('*');
+            let value = part.trim_start_matches(':').trim_end_matches('*');
 
             if value.contains(':') {
                 bail!("nested `:` is not allowed in dynamic route part");
             }
 
+            if value.contains('*') || (is_wildcard && !is_dynamic && !value.is_empty()) {
+                bail!("`*` must be a whole route part or the end of a dynamic part: {part}");
+            }
+
+            if is_wildcard && is_dynamic && !is_last {
+                bail!("named wildcard `{part}` is only allowed as the last route part");
+            }
+
             parts.push(RoutePart {
                 is_dynamic,
-                name: value,
+                is_wildcard,
+                name: value.to_owned(),
             });
         }
 
-        Ok(Self {
-            method,
-            path,
-            parts,
-        })
+        Ok(parts)
+    }
+
+    /// Whether the route only has static parts, i.e. matches a single exact path.
+    pub fn is_static(&self) -> bool {
+        self.parts
+            .iter()
+            .all(|part| !part.is_dynamic && !part.is_wildcard)
+    }
+
+    /// Whether both routes have the same method and path shape, e.g. `/users/:id` and
+    /// `/users/:name`, so that no request could tell them apart.
+    pub fn conflicts_with(&self, other: &StoredRoute) -> bool {
+        self.method == other.method
+            && self.parts.len() == other.parts.len()
+            && self
+                .parts
+                .iter()
+                .zip(&other.parts)
+                .enumerate()
+                .all(|(idx, (a, b))| {
+                    match (
+                        Some(idx) == self.tail_index(),
+                        Some(idx) == other.tail_index(),
+                    ) {
+                        (true, true) => true,
+                        (false, false) => {
+                            a.is_dynamic == b.is_dynamic
+                                && a.is_wildcard == b.is_wildcard
+                                && (a.is_dynamic || a.is_wildcard || a.name == b.name)
+                        }
+                        _ => false,
+                    }
+                })
+    }
+
+    /// Index of the trailing wildcard part that swallows all remaining segments, if any.
+    fn tail_index(&self) -> Option<usize> {
+        match self.parts.last() {
+            Some(part) if part.is_wildcard => Some(self.parts.len() - 1),
+            _ => None,
+        }
     }
 
+    /// Extracts the dynamic and wildcard values from a raw (percent-encoded) request path.
+    ///
+    /// Empty segments are kept, like when the route is matched, so that each value lines up
+    /// with the part that matched it.
     pub fn extract_routing_data(&self, request_url: &str) -> Result<RoutingData> {
-        let request_parts: Vec<_> = request_url.split('/').filter(|p| !p.is_empty()).collect();
+        // the root has no segments rather than a single empty one
+        let path = request_url.trim_matches('/');
+        let request_parts = match path.is_empty() {
+            true => vec![],
+            false => path
+                .split('/')
+                .map(percent_encoding::decode)
+                .collect::<Result<Vec<_>>>()?,
+        };
 
         let mut params: HashMap<String, Option<String>> = HashMap::new();
+        let mut tail = None;
         for (idx, part) in self.parts.iter().enumerate() {
+            if Some(idx) == self.tail_index() {
+                let segments = request_parts.get(idx..).unwrap_or_default().to_vec();
+                if part.is_dynamic {
+                    params.insert(part.name.to_owned(), Some(segments.join("/")));
+                }
+
+                tail = Some(segments);
+                continue;
+            }
+
             if !part.is_dynamic {
                 continue;
             }
 
-            let value = request_parts.get(idx).map(|&value| value.to_owned());
+            let value = request_parts.get(idx).map(|value| value.to_owned());
             params.insert(part.name.to_owned(), value);
         }
 
-        Ok(RoutingData { params })
+        Ok(RoutingData {
+            params,
+            tail,
+            query: HashMap::new(),
+            state: None,
+        })
     }
 }
 
 #[derive(Debug, Hash, Eq, PartialEq, Clone)]
 pub struct RoutePart {
     pub is_dynamic: bool,
+    pub is_wildcard: bool,
     pub name: String,
 }
 
+/// Registered routes indexed by path segment, so that matching a request only walks
+/// the segments of its path instead of every registered route.
+#[derive(Debug, Default)]
+struct RouteNode {
+    static_children: HashMap<String, RouteNode>,
+    dynamic_child: Option<Box<RouteNode>>,
+    wildcard_child: Option<Box<RouteNode>>,
+    tail_child: Option<Box<RouteNode>>,
+    routes: Vec<StoredRoute>,
+}
+
+impl RouteNode {
+    fn insert(&mut self, route: StoredRoute) {
+        let tail_index = route.tail_index();
+
+        let mut node = self;
+        for (idx, part) in route.parts.iter().enumerate() {
+            node = if Some(idx) == tail_index {
+                node.tail_child.get_or_insert_with(Default::default)
+            } else if part.is_wildcard {
+                node.wildcard_child.get_or_insert_with(Default::default)
+            } else if part.is_dynamic {
+                node.dynamic_child.get_or_insert_with(Default::default)
+            } else {
+                node.static_children
+                    .entry(part.name.to_owned())
+                    .or_default()
+            };
+        }
+
+        node.routes.push(route);
+    }
+
+    /// Walks the nodes matching `segments`, most specific first (static > dynamic > wildcard >
+    /// trailing wildcard), until `on_match` returns `true`.
+    ///
+    /// Dynamic and `*` parts never capture an empty segment (e.g. `/users//details`), but once
+    /// all segments are consumed dynamic parts may still match with no value.
+    fn visit<'a>(
+        &'a self,
+        segments: &[&str],
+        on_match: &mut dyn FnMut(&'a RouteNode) -> bool,
+    ) -> bool {
+        let Some((segment, rest)) = segments.split_first() else {
+            return on_match(self)
+                || self.tail_child.as_ref().is_some_and(|tail| on_match(tail))
+                || self
+                    .dynamic_child
+                    .as_ref()
+                    .is_some_and(|child| child.visit(segments, on_match));
+        };
+
+        self.static_children
+            .get(*segment)
+            .is_some_and(|child| child.visit(rest, on_match))
+            || self
+                .dynamic_child
+                .as_ref()
+                .filter(|_| !segment.is_empty())
+                .is_some_and(|child| child.visit(rest, on_match))
+            || self
+                .wildcard_child
+                .as_ref()
+                .filter(|_| !segment.is_empty())
+                .is_some_and(|child| child.visit(rest, on_match))
+            || self.tail_child.as_ref().is_some_and(|tail| on_match(tail))
+    }
+}
+
 #[derive(Debug, Hash, Eq, PartialEq, Clone)]
 pub struct RequestRoute {
     pub method: HttpMethod,
@@ -298,6 +824,11 @@ impl RequestRoute {
         let path = path.trim_matches('/').to_owned();
         RequestRoute { method, path }
     }
+
+    /// Percent-decoded segments of the path.
+    pub fn segments(&self) -> Result<Vec<String>> {
+        self.path.split('/').map(percent_encoding::decode).collect()
+    }
 }
 
 impl FromStr for RequestRoute {
@@ -315,12 +846,101 @@ impl FromStr for RequestRoute {
 
 type RoutingCallback = fn(&HttpRequest, &RoutingData) -> Result<HttpResponse>;
 
+type PathRewriter = fn(&str) -> Option<String>;
+
+type ErrorHandler = fn(&HttpRequest, &anyhow::Error) -> Result<HttpResponse>;
+
+/// Signature of a handler whose returned value is serialized as the JSON body of the response.
+pub type JsonCallback<T> = fn(&HttpRequest, &RoutingData) -> Result<T>;
+
+/// Produces the response of the requests matching a route.
+pub trait Handler: Debug + Send + Sync {
+    fn handle(&self, request: &HttpRequest, routing_data: &RoutingData) -> Result<HttpResponse>;
+}
+
+impl Handler for RoutingCallback {
+    fn handle(&self, request: &HttpRequest, routing_data: &RoutingData) -> Result<HttpResponse> {
+        self(request, routing_data)
+    }
+}
+
+impl Handler for WebSocketCallback {
+    fn handle(&self, request: &HttpRequest, _routing_data: &RoutingData) -> Result<HttpResponse> {
+        websocket::upgrade_response(request, *self)
+    }
+}
+
+struct JsonHandler<T>(JsonCallback<T>);
+
+impl<T> Debug for JsonHandler<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("JsonHandler").field(&self.0).finish()
+    }
+}
+
+impl<T: Serialize> Handler for JsonHandler<T> {
+    fn handle(&self, request: &HttpRequest, routing_data: &RoutingData) -> Result<HttpResponse> {
+        let value = (self.0)(request, routing_data)?;
+        HttpResponseBuilder::new().set_json_body(&value)?.build()
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct RoutingData {
     params: HashMap<String, Option<String>>,
+    tail: Option<Vec<String>>,
+    query: HashMap<String, Vec<String>>,
+    state: Option<Arc<dyn Any + Send + Sync>>,
 }
 
 impl RoutingData {
+    /// Returns the state registered with [`Router::with_state`].
+    pub fn state<T: 'static>(&self) -> Result<&T> {
+        self.state
+            .as_ref()
+            .context("no state was registered on the router")?
+            .downcast_ref()
+            .with_context(|| {
+                format!(
+                    "router state is not of type: {}",
+                    std::any::type_name::<T>()
+                )
+            })
+    }
+
+    /// Returns the remaining path matched by a trailing wildcard (`*` or `:name*`).
+    ///
+    /// `None` if the matched route does not end with a wildcard.
+    pub fn get_tail(&self) -> Option<String> {
+        self.tail.as_ref().map(|segments| segments.join("/"))
+    }
+
+    /// Returns the decoded segments of the remaining path matched by a trailing wildcard, e.g.
+    /// `["a", "b", "c"]` for `/files/a/b/c` on `/files/*`.
+    ///
+    /// Empty if the matched route does not end with a wildcard.
+    pub fn tail_segments(&self) -> Vec<String> {
+        self.tail.clone().unwrap_or_default()
+    }
+
+    /// Returns every path parameter of the matched route by name, `None` for optional ones that
+    /// were not in the path.
+    pub fn all(&self) -> &HashMap<String, Option<String>> {
+        &self.params
+    }
+
+    /// Iterates over the path parameters of the matched route, in no particular order.
+    pub fn params(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.params
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_deref()))
+    }
+
+    /// Whether the matched route has a path parameter called `param_name`.
+    pub fn contains(&self, param_name: &str) -> bool {
+        self.params.contains_key(param_name)
+    }
+
     pub fn get_str_value(&self, param_name: &str) -> Result<Option<String>> {
         if let Some(param_value) = self.params.get(param_name) {
             Ok(param_value.to_owned())
@@ -338,15 +958,38 @@ impl RoutingData {
             None => Ok(None),
         }
     }
+
+    /// Returns the first decoded value of a query parameter, `None` if the request does not have
+    /// it.
+    pub fn get_query_str_value(&self, param_name: &str) -> Option<String> {
+        self.query.get(param_name)?.first().cloned()
+    }
+
+    pub fn get_query_value<T: FromStr>(&self, param_name: &str) -> Result<Option<T>> {
+        match self.get_query_str_value(param_name) {
+            Some(str_value) => match str_value.parse::<T>() {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => bail!(
+                    "failed to parse value `{}` for query parameter: {}",
+                    str_value,
+                    param_name
+                ),
+            },
+            None => Ok(None),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::net::IpAddr;
+    use std::{fs, net::IpAddr};
 
     use serde_json::{json, Value};
 
-    use crate::http::{HttpRequestRaw, HttpResponseBuilder};
+    use crate::http::{
+        range::MAX_RANGES, response::STREAM_CHUNK_SIZE, HttpHeader, HttpRequestRaw,
+        HttpResponseBuilder,
+    };
 
     use super::*;
 
@@ -394,12 +1037,12 @@ mod tests {
         let id = routing_data
             .get_str_value("id")
             .unwrap()
-            .unwrap_or(String::new());
+            .unwrap_or_default();
 
         let info_field = routing_data
             .get_str_value("field")
             .unwrap()
-            .unwrap_or(String::new());
+            .unwrap_or_default();
 
         let username = format!("user_{id}");
         let json = json!({ "username": username, "field": info_field });
@@ -407,6 +1050,61 @@ mod tests {
         HttpResponseBuilder::new().set_json_body(&json)?.build()
     }
 
+    fn get_asset(_request: &HttpRequest, routing_data: &RoutingData) -> Result<HttpResponse> {
+        let json = json!({ "tail": routing_data.get_tail() });
+        HttpResponseBuilder::new().set_json_body(&json)?.build()
+    }
+
+    fn get_file_by_rest(
+        _request: &HttpRequest,
+        routing_data: &RoutingData,
+    ) -> Result<HttpResponse> {
+        let json = json!({ "rest": routing_data.get_str_value("rest")? });
+        HttpResponseBuilder::new().set_json_body(&json)?.build()
+    }
+
+    fn get_request(request_line: &str) -> HttpRequest {
+        get_request_with_headers(request_line, Vec::new())
+    }
+
+    fn get_request_with_headers(request_line: &str, headers: Vec<HttpHeader>) -> HttpRequest {
+        HttpRequest::from_raw_request(HttpRequestRaw {
+            request_line: request_line.to_owned(),
+            headers,
+            body: vec![],
+            trailers: vec![],
+            peer_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+            local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+        })
+        .unwrap()
+    }
+
+    fn has_authorization(request: &HttpRequest, _file_path: &Path) -> bool {
+        request.get_header("Authorization").is_some()
+    }
+
+    fn get_protected_file_router(dir: &Path) -> Router {
+        fs::write(dir.join("secret.txt"), "top secret").unwrap();
+
+        let file_server = FileServer::new()
+            .map_dir("/downloads", dir.to_str().unwrap())
+            .unwrap()
+            .with_access_check(has_authorization);
+
+        Router::new().set_file_server(file_server)
+    }
+
+    fn get_ranged_file_router(dir: &Path) -> (Router, Vec<u8>) {
+        let content: Vec<u8> = (0..1000).map(|idx| (idx % 251) as u8).collect();
+        fs::write(dir.join("data.bin"), &content).unwrap();
+
+        let file_server = FileServer::new()
+            .map_dir("/files", dir.to_str().unwrap())
+            .unwrap();
+
+        (Router::new().set_file_server(file_server), content)
+    }
+
     fn post_user_callback(
         _request: &HttpRequest,
         _routing_data: &RoutingData,
@@ -423,6 +1121,7 @@ mod tests {
             request_line: "GET /hello HTTP/1.1".to_owned(),
             headers: Vec::new(),
             body: vec![],
+            trailers: vec![],
             peer_ip: IpAddr::from_str("0.0.0.0").unwrap(),
             local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
         })
@@ -442,6 +1141,7 @@ mod tests {
             request_line: "GET /not-a-real-page HTTP/1.1".to_owned(),
             headers: Vec::new(),
             body: vec![],
+            trailers: vec![],
             peer_ip: IpAddr::from_str("0.0.0.0").unwrap(),
             local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
         })
@@ -463,6 +1163,7 @@ mod tests {
             request_line: "GET /hello HTTP/1.1".to_owned(),
             headers: Vec::new(),
             body: vec![],
+            trailers: vec![],
             peer_ip: IpAddr::from_str("0.0.0.0").unwrap(),
             local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
         })
@@ -486,6 +1187,7 @@ mod tests {
             request_line: "GET /hello HTTP/1.1".to_owned(),
             headers: Vec::new(),
             body: vec![],
+            trailers: vec![],
             peer_ip: IpAddr::from_str("0.0.0.0").unwrap(),
             local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
         })
@@ -503,6 +1205,7 @@ mod tests {
             request_line: "POST /user HTTP/1.1".to_owned(),
             headers: Vec::new(),
             body: vec![],
+            trailers: vec![],
             peer_ip: IpAddr::from_str("0.0.0.0").unwrap(),
             local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
         })
@@ -522,6 +1225,7 @@ mod tests {
             request_line: "GET /users/5/details HTTP/1.1".to_owned(),
             headers: Vec::new(),
             body: vec![],
+            trailers: vec![],
             peer_ip: IpAddr::from_str("0.0.0.0").unwrap(),
             local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
         })
@@ -542,6 +1246,7 @@ mod tests {
             request_line: "GET /users/7/details HTTP/1.1".to_owned(),
             headers: Vec::new(),
             body: vec![],
+            trailers: vec![],
             peer_ip: IpAddr::from_str("0.0.0.0").unwrap(),
             local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
         })
@@ -560,6 +1265,7 @@ mod tests {
             request_line: "GET /users HTTP/1.1".to_owned(),
             headers: Vec::new(),
             body: vec![],
+            trailers: vec![],
             peer_ip: IpAddr::from_str("0.0.0.0").unwrap(),
             local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
         })
@@ -579,6 +1285,7 @@ mod tests {
             request_line: "GET /users/17/info/gender HTTP/1.1".to_owned(),
             headers: Vec::new(),
             body: vec![],
+            trailers: vec![],
             peer_ip: IpAddr::from_str("0.0.0.0").unwrap(),
             local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
         })
@@ -589,4 +1296,1099 @@ mod tests {
         let expected_result = json!({ "username": "user_17", "field": "gender"});
         assert_eq!(expected_result, actual_res);
     }
+
+    #[test]
+    fn test_wildcard_route_zero_segments() {
+        let router = Router::new().get("/assets/*", get_asset).unwrap();
+
+        let response = router
+            .handle_request(&get_request("GET /assets HTTP/1.1"))
+            .unwrap();
+        let actual_res: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(json!({ "tail": "" }), actual_res);
+    }
+
+    #[test]
+    fn test_wildcard_route_one_segment() {
+        let router = Router::new().get("/assets/*", get_asset).unwrap();
+
+        let response = router
+            .handle_request(&get_request("GET /assets/app.js HTTP/1.1"))
+            .unwrap();
+        let actual_res: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(json!({ "tail": "app.js" }), actual_res);
+    }
+
+    #[test]
+    fn test_wildcard_route_deep_nesting() {
+        let router = Router::new().get("/assets/*", get_asset).unwrap();
+
+        let response = router
+            .handle_request(&get_request("GET /assets/js/vendor/lib/app.js HTTP/1.1"))
+            .unwrap();
+        let actual_res: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(json!({ "tail": "js/vendor/lib/app.js" }), actual_res);
+    }
+
+    #[test]
+    fn test_wildcard_route_tail_segments() {
+        let route = StoredRoute::new(HttpMethod::GET, "/files/*").unwrap();
+
+        let routing_data = route.extract_routing_data("/files/a/b/c").unwrap();
+        assert_eq!(vec!["a", "b", "c"], routing_data.tail_segments());
+
+        // an encoded slash stays inside its segment
+        let routing_data = route.extract_routing_data("/files/a%2Fb/c").unwrap();
+        assert_eq!(vec!["a/b", "c"], routing_data.tail_segments());
+
+        let routing_data = route.extract_routing_data("/files").unwrap();
+        assert!(routing_data.tail_segments().is_empty());
+
+        let route = StoredRoute::new(HttpMethod::GET, "/*").unwrap();
+        let routing_data = route.extract_routing_data("/").unwrap();
+        assert!(routing_data.tail_segments().is_empty());
+        let routing_data = route.extract_routing_data("/a/b").unwrap();
+        assert_eq!(vec!["a", "b"], routing_data.tail_segments());
+
+        let route = StoredRoute::new(HttpMethod::GET, "/files/:name").unwrap();
+        let routing_data = route.extract_routing_data("/files/a").unwrap();
+        assert!(routing_data.tail_segments().is_empty());
+    }
+
+    #[test]
+    fn test_wildcard_route_named_tail() {
+        let router = Router::new()
+            .get("/files/:rest*", get_file_by_rest)
+            .unwrap();
+
+        let response = router
+            .handle_request(&get_request("GET /files/docs/readme.md HTTP/1.1"))
+            .unwrap();
+        let actual_res: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(json!({ "rest": "docs/readme.md" }), actual_res);
+    }
+
+    #[test]
+    fn test_wildcard_route_single_segment_in_middle() {
+        let router = Router::new()
+            .get("/users/*/details", get_hello_callback)
+            .unwrap();
+
+        let response = router
+            .handle_request(&get_request("GET /users/42/details HTTP/1.1"))
+            .unwrap();
+        assert_eq!("Hello World!\r\n".as_bytes(), response.body);
+
+        let response = router
+            .handle_request(&get_request("GET /users/42/settings HTTP/1.1"))
+            .unwrap();
+        assert_eq!(HttpStatusCode::NotFound.to_string(), response.status);
+    }
+
+    #[test]
+    fn test_wildcard_route_static_takes_priority() {
+        let router = Router::new()
+            .get("/*", catcher_get_404)
+            .unwrap()
+            .get("/hello", get_hello_callback)
+            .unwrap();
+
+        let response = router
+            .handle_request(&get_request("GET /hello HTTP/1.1"))
+            .unwrap();
+        assert_eq!("Hello World!\r\n".as_bytes(), response.body);
+
+        let response = router
+            .handle_request(&get_request("GET /foo/bar HTTP/1.1"))
+            .unwrap();
+        assert_eq!("404 YOU ARE LOST\r\n".as_bytes(), response.body);
+    }
+
+    #[test]
+    fn test_wildcard_route_invalid_patterns() {
+        assert!(StoredRoute::new(HttpMethod::GET, "/files/:rest*/details").is_err());
+        assert!(StoredRoute::new(HttpMethod::GET, "/files/foo*").is_err());
+        assert!(StoredRoute::new(HttpMethod::GET, "/files/*/:rest*").is_ok());
+    }
+
+    fn get_search(_request: &HttpRequest, routing_data: &RoutingData) -> Result<HttpResponse> {
+        let limit = routing_data.get_query_value::<u32>("limit")?.unwrap_or(20);
+        let page = routing_data.get_query_value::<u32>("page")?;
+        HttpResponseBuilder::new()
+            .set_json_body(&json!({ "limit": limit, "page": page }))?
+            .build()
+    }
+
+    #[test]
+    fn test_query_value_parse() {
+        let router = Router::new()
+            .error_handler(handle_error)
+            .get("/search", get_search)
+            .unwrap();
+
+        let response = router
+            .handle_request(&get_request("GET /search?limit=10 HTTP/1.1"))
+            .unwrap();
+        let actual_res: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(json!({ "limit": 10, "page": null }), actual_res);
+
+        let response = router
+            .handle_request(&get_request("GET /search?limit=abc HTTP/1.1"))
+            .unwrap();
+        let actual_res: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(
+            json!({ "error": "failed to parse value `abc` for query parameter: limit" }),
+            actual_res
+        );
+    }
+
+    fn head_page(_request: &HttpRequest, _routing_data: &RoutingData) -> Result<HttpResponse> {
+        HttpResponseBuilder::new()
+            .set_header("X-Handler", "head")
+            .build()
+    }
+
+    #[test]
+    fn test_auto_head_uses_get_route() {
+        let router = Router::new()
+            .get("/hello", get_hello_callback)
+            .unwrap()
+            .auto_head(true);
+
+        let get_response = router
+            .handle_request(&get_request("GET /hello HTTP/1.1"))
+            .unwrap();
+        let head_response = router
+            .handle_request(&get_request("HEAD /hello HTTP/1.1"))
+            .unwrap();
+        assert_eq!("200 OK", head_response.status);
+        assert_eq!(
+            get_response.headers.get("Content-Length"),
+            head_response.headers.get("Content-Length")
+        );
+
+        let response = router
+            .handle_request(&get_request("DELETE /hello HTTP/1.1"))
+            .unwrap();
+        assert_eq!("GET, HEAD", response.headers.get("Allow").unwrap().value);
+    }
+
+    fn get_failing(_request: &HttpRequest, _routing_data: &RoutingData) -> Result<HttpResponse> {
+        bail!("secret connection string")
+    }
+
+    fn handle_error(_request: &HttpRequest, e: &anyhow::Error) -> Result<HttpResponse> {
+        HttpResponseBuilder::new()
+            .set_status(HttpStatusCode::ServiceUnavailable)
+            .set_json_body(&json!({ "error": e.to_string() }))?
+            .build()
+    }
+
+    #[test]
+    fn test_handler_error_returns_500() {
+        let router = Router::new().get("/fail", get_failing).unwrap();
+
+        let response = router
+            .handle_request(&get_request("GET /fail HTTP/1.1"))
+            .unwrap();
+        assert_eq!(
+            HttpStatusCode::InternalServerError.to_string(),
+            response.status
+        );
+        assert_eq!(b"500 Internal Server Error".to_vec(), response.body);
+    }
+
+    #[test]
+    fn test_custom_error_handler() {
+        let router = Router::new()
+            .error_handler(handle_error)
+            .get("/fail", get_failing)
+            .unwrap();
+
+        let response = router
+            .handle_request(&get_request("GET /fail HTTP/1.1"))
+            .unwrap();
+        assert_eq!(
+            HttpStatusCode::ServiceUnavailable.to_string(),
+            response.status
+        );
+        let actual_res: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(json!({ "error": "secret connection string" }), actual_res);
+    }
+
+    fn rewrite_old_paths(path: &str) -> Option<String> {
+        path.strip_prefix("/old/")
+            .map(|rest| format!("/new/{rest}"))
+    }
+
+    fn get_new_page(request: &HttpRequest, routing_data: &RoutingData) -> Result<HttpResponse> {
+        let json = json!({ "name": routing_data.get_str_value("name")?, "url": request.url });
+        HttpResponseBuilder::new().set_json_body(&json)?.build()
+    }
+
+    #[test]
+    fn test_path_rewriter_dispatches_to_new_route() {
+        let router = Router::new()
+            .path_rewriter(rewrite_old_paths)
+            .get("/new/:name", get_new_page)
+            .unwrap();
+
+        let response = router
+            .handle_request(&get_request("GET /old/page?lang=en HTTP/1.1"))
+            .unwrap();
+        let actual_res: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(json!({ "name": "page", "url": "/old/page" }), actual_res);
+
+        let response = router
+            .handle_request(&get_request("GET /new/other HTTP/1.1"))
+            .unwrap();
+        let actual_res: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(json!({ "name": "other", "url": "/new/other" }), actual_res);
+
+        let response = router
+            .handle_request(&get_request("GET /unknown/page HTTP/1.1"))
+            .unwrap();
+        assert_eq!(HttpStatusCode::NotFound.to_string(), response.status);
+    }
+
+    #[test]
+    fn test_auto_head_disabled_or_overridden() {
+        let router = Router::new().get("/hello", get_hello_callback).unwrap();
+        let response = router
+            .handle_request(&get_request("HEAD /hello HTTP/1.1"))
+            .unwrap();
+        assert_eq!(
+            HttpStatusCode::MethodNotAllowed.to_string(),
+            response.status
+        );
+
+        let router = router.head("/hello", head_page).unwrap().auto_head(true);
+        let response = router
+            .handle_request(&get_request("HEAD /hello HTTP/1.1"))
+            .unwrap();
+        assert_eq!("head", response.headers.get("X-Handler").unwrap().value);
+    }
+
+    #[derive(Serialize)]
+    struct User {
+        id: u32,
+        name: String,
+    }
+
+    fn get_user_json(_request: &HttpRequest, routing_data: &RoutingData) -> Result<User> {
+        let id = routing_data
+            .get_value::<u32>("id")?
+            .context("id is required")?;
+        if id == 0 {
+            bail!("user 0 is reserved");
+        }
+
+        Ok(User {
+            id,
+            name: "Jane".to_owned(),
+        })
+    }
+
+    #[test]
+    fn test_json_route() {
+        let router = Router::new()
+            .json_route(HttpMethod::GET, "/users/:id", get_user_json)
+            .unwrap();
+
+        let response = router
+            .handle_request(&get_request("GET /users/7 HTTP/1.1"))
+            .unwrap();
+        assert_eq!("200 OK", response.status);
+        assert_eq!(
+            "application/json",
+            response.headers.get("Content-Type").unwrap().value
+        );
+        let actual_res: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(json!({ "id": 7, "name": "Jane" }), actual_res);
+
+        let response = router
+            .handle_request(&get_request("GET /users/0 HTTP/1.1"))
+            .unwrap();
+        assert_eq!(
+            HttpStatusCode::InternalServerError.to_string(),
+            response.status
+        );
+        assert_eq!(b"500 Internal Server Error".to_vec(), response.body);
+
+        let router = Router::new()
+            .error_handler(handle_error)
+            .json_route(HttpMethod::GET, "/users/:id", get_user_json)
+            .unwrap();
+        let response = router
+            .handle_request(&get_request("GET /users/0 HTTP/1.1"))
+            .unwrap();
+        assert_eq!(
+            HttpStatusCode::ServiceUnavailable.to_string(),
+            response.status
+        );
+        let actual_res: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(json!({ "error": "user 0 is reserved" }), actual_res);
+    }
+
+    #[test]
+    fn test_validate_pattern() {
+        assert!(StoredRoute::validate_pattern("/users/:id/:rest*").is_ok());
+        assert!(StoredRoute::validate_pattern("/users/:id/*").is_ok());
+        assert!(StoredRoute::validate_pattern("/users/*rest").is_err());
+        assert!(StoredRoute::validate_pattern("/users/:rest*/posts").is_err());
+        assert!(StoredRoute::validate_pattern("/users/:id:name").is_err());
+    }
+
+    #[test]
+    fn test_ambiguous_route_err() {
+        let router = Router::new().get("/users/:id", get_hello_callback).unwrap();
+
+        let err = router.get("/users/:name", get_hello_callback).unwrap_err();
+        assert_eq!(
+            "cannot register route GET /users/:name because it matches the same requests as GET /users/:id",
+            err.to_string()
+        );
+
+        let router = Router::new().get("/files/*", get_hello_callback).unwrap();
+        assert!(router.get("/files/:rest*", get_hello_callback).is_err());
+
+        let router = Router::new().get("/hello", get_hello_callback).unwrap();
+        assert!(router.get("/hello/", get_hello_callback).is_err());
+    }
+
+    #[test]
+    fn test_distinct_routes_coexist() {
+        let router = Router::new()
+            .get("/users/:id", get_hello_callback)
+            .unwrap()
+            .get("/users/:id/details", get_hello_callback)
+            .unwrap()
+            .post("/users/:name", post_hello_callback)
+            .unwrap()
+            .get("/users/me", get_hello_callback)
+            .unwrap()
+            .get("/users/*/posts", get_hello_callback)
+            .unwrap()
+            .get("/users/:id/posts", get_hello_callback)
+            .unwrap();
+
+        assert_eq!(6, router.routes().len());
+    }
+
+    #[test]
+    fn test_method_not_allowed() {
+        let router = Router::new().get("/users", get_hello_callback).unwrap();
+
+        let response = router
+            .handle_request(&get_request("DELETE /users HTTP/1.1"))
+            .unwrap();
+        assert_eq!(
+            HttpStatusCode::MethodNotAllowed.to_string(),
+            response.status
+        );
+        assert_eq!("GET", response.headers.get("Allow").unwrap().value);
+    }
+
+    #[test]
+    fn test_method_not_allowed_lists_all_methods() {
+        let router = Router::new()
+            .post("/users", post_user_callback)
+            .unwrap()
+            .get("/users", get_hello_callback)
+            .unwrap();
+
+        let response = router
+            .handle_request(&get_request("PUT /users HTTP/1.1"))
+            .unwrap();
+        assert_eq!(
+            HttpStatusCode::MethodNotAllowed.to_string(),
+            response.status
+        );
+        assert_eq!("GET, POST", response.headers.get("Allow").unwrap().value);
+    }
+
+    #[test]
+    fn test_method_not_allowed_unknown_path_is_404() {
+        let router = Router::new().get("/users", get_hello_callback).unwrap();
+
+        let response = router
+            .handle_request(&get_request("DELETE /posts HTTP/1.1"))
+            .unwrap();
+        assert_eq!(HttpStatusCode::NotFound.to_string(), response.status);
+        assert!(!response.headers.contains_key("Allow"));
+    }
+
+    fn get_spa_index(_request: &HttpRequest, _routing_data: &RoutingData) -> Result<HttpResponse> {
+        HttpResponseBuilder::new()
+            .set_html_body("<div id=\"app\"></div>")
+            .build()
+    }
+
+    #[test]
+    fn test_routing_data_all_params() {
+        let route = StoredRoute::new(HttpMethod::GET, "/users/:id/info/:field").unwrap();
+        let routing_data = route.extract_routing_data("/users/42/info/email").unwrap();
+
+        let expected = HashMap::from([
+            ("id".to_owned(), Some("42".to_owned())),
+            ("field".to_owned(), Some("email".to_owned())),
+        ]);
+        assert_eq!(&expected, routing_data.all());
+
+        let mut params: Vec<_> = routing_data.params().collect();
+        params.sort();
+        assert_eq!(vec![("field", Some("email")), ("id", Some("42"))], params);
+
+        assert!(routing_data.contains("id"));
+        assert!(routing_data.contains("field"));
+        assert!(!routing_data.contains("name"));
+    }
+
+    #[test]
+    fn test_static_site_without_routes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.html"), "<h1>home</h1>").unwrap();
+        fs::create_dir(dir.path().join("assets")).unwrap();
+        fs::write(dir.path().join("assets").join("app.js"), "run()").unwrap();
+
+        let file_server = FileServer::new()
+            .map_dir("/", dir.path().to_str().unwrap())
+            .unwrap();
+        let router = Router::new()
+            .set_file_server(file_server)
+            .catch_all(HttpMethod::GET, get_spa_index)
+            .unwrap();
+
+        let response = router
+            .handle_request(&get_request("GET / HTTP/1.1"))
+            .unwrap();
+        assert_eq!(b"<h1>home</h1>".to_vec(), response.read_body().unwrap());
+
+        let response = router
+            .handle_request(&get_request("GET /assets/app.js HTTP/1.1"))
+            .unwrap();
+        assert_eq!(b"run()".to_vec(), response.read_body().unwrap());
+
+        // client-side routes fall back to the app shell
+        let response = router
+            .handle_request(&get_request("GET /dashboard/settings HTTP/1.1"))
+            .unwrap();
+        assert!(String::from_utf8(response.body)
+            .unwrap()
+            .contains("<div id=\"app\"></div>"));
+
+        let response = router
+            .handle_request(&get_request("POST /dashboard HTTP/1.1"))
+            .unwrap();
+        assert_eq!(HttpStatusCode::NotFound.to_string(), response.status);
+    }
+
+    #[test]
+    fn test_file_server_access_check_allowed() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = get_protected_file_router(dir.path());
+
+        let request = get_request_with_headers(
+            "GET /downloads/secret.txt HTTP/1.1",
+            vec![HttpHeader::new("Authorization", "Bearer JWT")],
+        );
+
+        let response = router.handle_request(&request).unwrap();
+        assert_eq!(HttpStatusCode::OK.to_string(), response.status);
+        assert_eq!("top secret".as_bytes(), response.read_body().unwrap());
+    }
+
+    #[test]
+    fn test_file_server_access_check_denied() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = get_protected_file_router(dir.path());
+
+        let request = get_request("GET /downloads/secret.txt HTTP/1.1");
+
+        let response = router.handle_request(&request).unwrap();
+        assert_eq!(HttpStatusCode::Forbidden.to_string(), response.status);
+        assert!(response.body.is_empty());
+    }
+
+    #[test]
+    fn test_auto_options_lists_allowed_methods() {
+        let router = Router::new()
+            .post("/users", post_user_callback)
+            .unwrap()
+            .auto_options(true);
+
+        let response = router
+            .handle_request(&get_request("OPTIONS /users HTTP/1.1"))
+            .unwrap();
+        assert_eq!(HttpStatusCode::NoContent.to_string(), response.status);
+        assert_eq!(
+            "POST, OPTIONS",
+            response.headers.get("Allow").unwrap().value
+        );
+    }
+
+    #[test]
+    fn test_auto_options_unknown_path_is_404() {
+        let router = Router::new()
+            .post("/users", post_user_callback)
+            .unwrap()
+            .auto_options(true);
+
+        let response = router
+            .handle_request(&get_request("OPTIONS /posts HTTP/1.1"))
+            .unwrap();
+        assert_eq!(HttpStatusCode::NotFound.to_string(), response.status);
+    }
+
+    #[test]
+    fn test_auto_options_explicit_handler_wins() {
+        let router = Router::new()
+            .post("/users", post_user_callback)
+            .unwrap()
+            .options("/users", get_hello_callback)
+            .unwrap()
+            .auto_options(true);
+
+        let response = router
+            .handle_request(&get_request("OPTIONS /users HTTP/1.1"))
+            .unwrap();
+        assert_eq!("Hello World!\r\n".as_bytes(), response.body);
+    }
+
+    #[test]
+    fn test_auto_options_disabled_by_default() {
+        let router = Router::new().post("/users", post_user_callback).unwrap();
+
+        let response = router
+            .handle_request(&get_request("OPTIONS /users HTTP/1.1"))
+            .unwrap();
+        assert_eq!(
+            HttpStatusCode::MethodNotAllowed.to_string(),
+            response.status
+        );
+    }
+
+    #[test]
+    fn test_route_tree_many_routes() {
+        let mut router = Router::new();
+        for idx in 0..50 {
+            router
+                .add_route(
+                    HttpMethod::GET,
+                    &format!("/resource{idx}"),
+                    get_hello_callback,
+                )
+                .unwrap();
+            router
+                .add_route(
+                    HttpMethod::GET,
+                    &format!("/resource{idx}/:id"),
+                    get_user_by_id,
+                )
+                .unwrap();
+            router
+                .add_route(
+                    HttpMethod::GET,
+                    &format!("/resource{idx}/new"),
+                    get_hello_callback,
+                )
+                .unwrap();
+            router
+                .add_route(
+                    HttpMethod::POST,
+                    &format!("/resource{idx}/:id/info/:field"),
+                    get_user_info,
+                )
+                .unwrap();
+        }
+        assert_eq!(200, router.routes.len());
+
+        for idx in 0..50 {
+            let cases = [
+                (
+                    HttpMethod::GET,
+                    format!("resource{idx}"),
+                    format!("resource{idx}"),
+                ),
+                (
+                    HttpMethod::GET,
+                    format!("resource{idx}/42"),
+                    format!("resource{idx}/:id"),
+                ),
+                (
+                    HttpMethod::GET,
+                    format!("resource{idx}/new"),
+                    format!("resource{idx}/new"),
+                ),
+                (
+                    HttpMethod::POST,
+                    format!("resource{idx}/42/info/name"),
+                    format!("resource{idx}/:id/info/:field"),
+                ),
+            ];
+
+            for (method, path, expected) in cases {
+                let matched = router
+                    .find_matching_route(&RequestRoute::new(method, &path))
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(expected, matched.path);
+            }
+        }
+
+        let unmatched = router
+            .find_matching_route(&RequestRoute::new(HttpMethod::GET, "resource50/42"))
+            .unwrap();
+        assert!(unmatched.is_none());
+    }
+
+    #[test]
+    fn test_route_tree_static_priority_over_dynamic() {
+        let router = Router::new()
+            .get("/users/:id", get_user_by_id)
+            .unwrap()
+            .get("/users/new", get_hello_callback)
+            .unwrap();
+
+        let response = router
+            .handle_request(&get_request("GET /users/new HTTP/1.1"))
+            .unwrap();
+        assert_eq!("Hello World!\r\n".as_bytes(), response.body);
+
+        let response = router
+            .handle_request(&get_request("GET /users/3 HTTP/1.1"))
+            .unwrap();
+        let actual_res: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(3, actual_res["id"]);
+    }
+
+    #[test]
+    fn test_dynamic_route_empty_segment_no_match() {
+        let router = Router::new()
+            .get("/users/:id/details", get_user_by_id)
+            .unwrap();
+
+        let matched = router
+            .find_matching_route(&RequestRoute::new(HttpMethod::GET, "/users//details"))
+            .unwrap();
+        assert!(matched.is_none());
+
+        let response = router
+            .handle_request(&get_request("GET /users//details HTTP/1.1"))
+            .unwrap();
+        assert_eq!(HttpStatusCode::NotFound.to_string(), response.status);
+    }
+
+    #[test]
+    fn test_wildcard_empty_segment_keeps_params_aligned() {
+        let router = Router::new().get("/a/*/:id", get_user_by_id).unwrap();
+
+        let matched = router
+            .find_matching_route(&RequestRoute::new(HttpMethod::GET, "/a//5"))
+            .unwrap();
+        assert!(matched.is_none());
+
+        let route = StoredRoute::new(HttpMethod::GET, "/a/*/:id").unwrap();
+        let routing_data = route.extract_routing_data("/a//5").unwrap();
+        assert_eq!(
+            Some("5".to_owned()),
+            routing_data.get_str_value("id").unwrap()
+        );
+
+        let routing_data = route.extract_routing_data("/a/x/5").unwrap();
+        assert_eq!(
+            Some("5".to_owned()),
+            routing_data.get_str_value("id").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_route_tree_backtracks_from_static() {
+        let router = Router::new()
+            .get("/users/new", get_hello_callback)
+            .unwrap()
+            .get("/users/:id/details", get_user_by_id)
+            .unwrap();
+
+        let matched = router
+            .find_matching_route(&RequestRoute::new(HttpMethod::GET, "users/new/details"))
+            .unwrap()
+            .unwrap();
+        assert_eq!("users/:id/details", matched.path);
+    }
+
+    #[test]
+    fn test_dynamic_route_percent_decoded() {
+        let router = Router::new()
+            .get("/files/:rest*", get_file_by_rest)
+            .unwrap();
+
+        let response = router
+            .handle_request(&get_request("GET /files/my%2Ffile HTTP/1.1"))
+            .unwrap();
+        let actual_res: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(json!({ "rest": "my/file" }), actual_res);
+    }
+
+    #[test]
+    fn test_dynamic_route_encoded_slash_stays_in_segment() {
+        let router = Router::new()
+            .get("/users/:id/info/:field", get_user_info)
+            .unwrap();
+
+        let response = router
+            .handle_request(&get_request("GET /users/a%2Fb/info/first%20name HTTP/1.1"))
+            .unwrap();
+        let actual_res: Value = serde_json::from_slice(&response.body).unwrap();
+        let expected_result = json!({ "username": "user_a/b", "field": "first name"});
+        assert_eq!(expected_result, actual_res);
+    }
+
+    #[test]
+    fn test_file_server_single_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let (router, content) = get_ranged_file_router(dir.path());
+
+        let request = get_request_with_headers(
+            "GET /files/data.bin HTTP/1.1",
+            vec![HttpHeader::new("Range", "bytes=100-199")],
+        );
+
+        let response = router.handle_request(&request).unwrap();
+        assert_eq!(HttpStatusCode::PartialContent.to_string(), response.status);
+        assert_eq!(
+            "bytes 100-199/1000",
+            response.headers.get("Content-Range").unwrap().value
+        );
+        assert_eq!(&content[100..200], response.read_body().unwrap());
+    }
+
+    #[test]
+    fn test_file_server_streams_large_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let content: Vec<u8> = (0..STREAM_CHUNK_SIZE * 4 + 7)
+            .map(|i| (i % 256) as u8)
+            .collect();
+        fs::write(dir.path().join("large.bin"), &content).unwrap();
+
+        let file_server = FileServer::new()
+            .map_dir("/files", dir.path().to_str().unwrap())
+            .unwrap();
+        let router = Router::new().set_file_server(file_server);
+
+        let request = get_request("GET /files/large.bin HTTP/1.1");
+        let response = router.handle_request(&request).unwrap();
+
+        assert!(response.body.is_empty());
+        assert_eq!(
+            content.len().to_string(),
+            response.headers.get("Content-Length").unwrap().value
+        );
+
+        let written = response.to_bytes().unwrap();
+        assert!(written.ends_with(&content));
+        assert!(written.len() < content.len() + 512);
+    }
+
+    #[test]
+    fn test_file_server_multiple_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let (router, content) = get_ranged_file_router(dir.path());
+
+        let request = get_request_with_headers(
+            "GET /files/data.bin HTTP/1.1",
+            vec![HttpHeader::new("Range", "bytes=0-99,200-299")],
+        );
+
+        let response = router.handle_request(&request).unwrap();
+        assert_eq!(HttpStatusCode::PartialContent.to_string(), response.status);
+
+        let content_type = &response.headers.get("Content-Type").unwrap().value;
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+
+        let mut expected = Vec::new();
+        for (start, end) in [(0, 99), (200, 299)] {
+            expected.extend_from_slice(
+                format!(
+                    "--{boundary}\r\nContent-Type: application/octet-stream\r\n\
+Content-Range: bytes {start}-{end}/1000\r\n\r\n"
+                )
+                .as_bytes(),
+            );
+            expected.extend_from_slice(&content[start..=end]);
+            expected.extend_from_slice(b"\r\n");
+        }
+        expected.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+
+        assert!(response.body.is_empty());
+        assert_eq!(
+            expected.len().to_string(),
+            response.headers.get("Content-Length").unwrap().value
+        );
+        let mut written = Vec::new();
+        response.stream.unwrap().copy_to(&mut written).unwrap();
+        assert_eq!(expected, written);
+    }
+
+    #[test]
+    fn test_file_server_repeated_or_too_many_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let (router, _) = get_ranged_file_router(dir.path());
+
+        let request = get_request_with_headers(
+            "GET /files/data.bin HTTP/1.1",
+            vec![HttpHeader::new("Range", "bytes=0-,0-,0-,10-20")],
+        );
+        let response = router.handle_request(&request).unwrap();
+        assert_eq!(HttpStatusCode::PartialContent.to_string(), response.status);
+        assert_eq!(
+            "bytes 0-999/1000",
+            response.headers.get("Content-Range").unwrap().value
+        );
+        assert_eq!(
+            "1000",
+            response.headers.get("Content-Length").unwrap().value
+        );
+
+        let ranges: Vec<_> = (0..=MAX_RANGES)
+            .map(|idx| format!("{}-{}", idx * 10, idx * 10))
+            .collect();
+        let request = get_request_with_headers(
+            "GET /files/data.bin HTTP/1.1",
+            vec![HttpHeader::new(
+                "Range",
+                &format!("bytes={}", ranges.join(",")),
+            )],
+        );
+        let response = router.handle_request(&request).unwrap();
+        assert_eq!("200 OK", response.status);
+        assert_eq!(
+            "1000",
+            response.headers.get("Content-Length").unwrap().value
+        );
+    }
+
+    #[test]
+    fn test_file_server_unsatisfiable_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let (router, _) = get_ranged_file_router(dir.path());
+
+        let request = get_request_with_headers(
+            "GET /files/data.bin HTTP/1.1",
+            vec![HttpHeader::new("Range", "bytes=5000-")],
+        );
+
+        let response = router.handle_request(&request).unwrap();
+        assert_eq!(
+            HttpStatusCode::RangeNotSatisfiable.to_string(),
+            response.status
+        );
+        assert_eq!(
+            "bytes */1000",
+            response.headers.get("Content-Range").unwrap().value
+        );
+    }
+
+    #[test]
+    fn test_file_server_range_on_empty_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("empty.txt"), "").unwrap();
+        let file_server = FileServer::new()
+            .map_dir("/files", dir.path().to_str().unwrap())
+            .unwrap();
+        let router = Router::new().set_file_server(file_server);
+
+        let request = get_request_with_headers(
+            "GET /files/empty.txt HTTP/1.1",
+            vec![HttpHeader::new("Range", "bytes=0-")],
+        );
+
+        let response = router.handle_request(&request).unwrap();
+        assert_eq!(
+            HttpStatusCode::RangeNotSatisfiable.to_string(),
+            response.status
+        );
+        assert_eq!(
+            "bytes */0",
+            response.headers.get("Content-Range").unwrap().value
+        );
+    }
+
+    struct CounterState {
+        hits: std::sync::atomic::AtomicUsize,
+    }
+
+    fn get_hit_count(_request: &HttpRequest, routing_data: &RoutingData) -> Result<HttpResponse> {
+        let state = routing_data.state::<CounterState>()?;
+        let hits = state.hits.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+        HttpResponseBuilder::new()
+            .set_html_body(&hits.to_string())
+            .build()
+    }
+
+    #[test]
+    fn test_shared_state_across_threads() {
+        let router = Arc::new(
+            Router::new()
+                .with_state(CounterState {
+                    hits: std::sync::atomic::AtomicUsize::new(0),
+                })
+                .get("/hits", get_hit_count)
+                .unwrap(),
+        );
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let router = Arc::clone(&router);
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        let response = router
+                            .handle_request(&get_request("GET /hits HTTP/1.1"))
+                            .unwrap();
+                        assert_eq!("200 OK", response.status);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let response = router
+            .handle_request(&get_request("GET /hits HTTP/1.1"))
+            .unwrap();
+        assert_eq!(b"401\r\n".to_vec(), response.body);
+    }
+
+    #[test]
+    fn test_state_missing_or_wrong_type_err() {
+        let routing_data = RoutingData::default();
+        assert!(routing_data.state::<CounterState>().is_err());
+
+        let router = Router::new()
+            .with_state(42u32)
+            .get("/hits", get_hit_count)
+            .unwrap();
+        let response = router
+            .handle_request(&get_request("GET /hits HTTP/1.1"))
+            .unwrap();
+        assert_eq!(
+            HttpStatusCode::InternalServerError.to_string(),
+            response.status
+        );
+    }
+
+    fn get_docs_router(dir: &Path, redirect_directories: bool) -> Router {
+        fs::create_dir(dir.join("docs")).unwrap();
+        fs::write(dir.join("docs").join("index.html"), "<p>docs</p>").unwrap();
+
+        let file_server = FileServer::new()
+            .map_dir("/", dir.to_str().unwrap())
+            .unwrap()
+            .redirect_directories(redirect_directories);
+        Router::new().set_file_server(file_server)
+    }
+
+    #[test]
+    fn test_file_server_redirects_directory_without_trailing_slash() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = get_docs_router(dir.path(), true);
+
+        let response = router
+            .handle_request(&get_request("GET /docs HTTP/1.1"))
+            .unwrap();
+        assert_eq!(
+            HttpStatusCode::MovedPermanently.to_string(),
+            response.status
+        );
+        assert_eq!("/docs/", response.headers.get("Location").unwrap().value);
+
+        let response = router
+            .handle_request(&get_request("GET /docs?page=2 HTTP/1.1"))
+            .unwrap();
+        assert_eq!(
+            "/docs/?page=2",
+            response.headers.get("Location").unwrap().value
+        );
+
+        let response = router
+            .handle_request(&get_request("GET /docs/ HTTP/1.1"))
+            .unwrap();
+        assert_eq!("200 OK", response.status);
+        assert_eq!(b"<p>docs</p>".to_vec(), response.read_body().unwrap());
+    }
+
+    #[test]
+    fn test_file_server_directory_redirect_disabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = get_docs_router(dir.path(), false);
+
+        let response = router
+            .handle_request(&get_request("GET /docs HTTP/1.1"))
+            .unwrap();
+        assert_eq!("200 OK", response.status);
+        assert_eq!(b"<p>docs</p>".to_vec(), response.read_body().unwrap());
+    }
+
+    fn get_site_router(dir: &Path, file_server_first: bool) -> Router {
+        fs::write(dir.join("index.html"), "<p>static index</p>").unwrap();
+        fs::create_dir(dir.join("api")).unwrap();
+        fs::write(dir.join("api").join("status"), "static status").unwrap();
+
+        let file_server = FileServer::new()
+            .map_dir("/", dir.to_str().unwrap())
+            .unwrap();
+
+        Router::new()
+            .get("/api/status", get_hello_callback)
+            .unwrap()
+            .get("/*", catcher_get_404)
+            .unwrap()
+            .set_file_server(file_server)
+            .file_server_first(file_server_first)
+    }
+
+    #[test]
+    fn test_routes_checked_before_file_server_by_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = get_site_router(dir.path(), false);
+
+        let response = router
+            .handle_request(&get_request("GET /index.html HTTP/1.1"))
+            .unwrap();
+        assert_eq!(b"404 YOU ARE LOST\r\n".to_vec(), response.body);
+
+        let response = router
+            .handle_request(&get_request("GET /api/status HTTP/1.1"))
+            .unwrap();
+        assert_eq!(b"Hello World!\r\n".to_vec(), response.body);
+    }
+
+    #[test]
+    fn test_file_server_first_before_wildcard_routes() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = get_site_router(dir.path(), true);
+
+        let response = router
+            .handle_request(&get_request("GET /index.html HTTP/1.1"))
+            .unwrap();
+        assert_eq!(
+            b"<p>static index</p>".to_vec(),
+            response.read_body().unwrap()
+        );
+
+        // exact static routes still win over files
+        let response = router
+            .handle_request(&get_request("GET /api/status HTTP/1.1"))
+            .unwrap();
+        assert_eq!(b"Hello World!\r\n".to_vec(), response.body);
+
+        // the wildcard route still gets what the file server cannot serve
+        let response = router
+            .handle_request(&get_request("GET /missing.html HTTP/1.1"))
+            .unwrap();
+        assert_eq!(b"404 YOU ARE LOST\r\n".to_vec(), response.body);
+    }
 }
@@ -0,0 +1,80 @@
+use anyhow::Result;
+use log::debug;
+use serde_json::{json, Value};
+
+use crate::{
+    http::{response_status_codes::HttpStatusCode, HttpRequest, HttpResponse, HttpResponseBuilder},
+    router::RoutingData,
+};
+
+/// Sends back the JSON body of the request, e.g. to inspect webhook deliveries, or a
+/// `400 Bad Request` when it is not valid JSON.
+///
+/// ```
+/// use rtfw_http::{router::Router, routes};
+///
+/// let router = Router::new().post("/echo", routes::json_echo).unwrap();
+/// ```
+pub fn json_echo(request: &HttpRequest, _routing_data: &RoutingData) -> Result<HttpResponse> {
+    // failing to read the body is not the client's fault
+    request.body()?;
+
+    match request.get_json_body::<Value>() {
+        Ok(body) => HttpResponseBuilder::new().set_json_body(&body)?.build(),
+        Err(e) => {
+            debug!("cannot echo request body: {e}");
+            HttpResponseBuilder::new()
+                .set_status(HttpStatusCode::BadRequest)
+                .set_json_body(&json!({ "error": e.to_string() }))?
+                .build()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, str::FromStr};
+
+    use crate::http::{HttpHeader, HttpRequestRaw};
+
+    use super::*;
+
+    fn get_post_request(body: &str) -> HttpRequest {
+        HttpRequest::from_raw_request(HttpRequestRaw {
+            request_line: "POST /echo HTTP/1.1".to_owned(),
+            headers: vec![
+                HttpHeader::new("Content-Type", "application/json"),
+                HttpHeader::new("Content-Length", &body.len().to_string()),
+            ],
+            body: body.as_bytes().to_vec(),
+            trailers: vec![],
+            peer_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+            local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_json_echo() {
+        let request = get_post_request(r#"{"event": "push", "commits": [1, 2]}"#);
+        let response = json_echo(&request, &RoutingData::default()).unwrap();
+
+        assert_eq!(200, response.status_code);
+        assert_eq!("application/json", response.headers["Content-Type"].value);
+        let actual: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(json!({ "event": "push", "commits": [1, 2] }), actual);
+    }
+
+    #[test]
+    fn test_json_echo_invalid_json_bad_request() {
+        let request = get_post_request("{\"event\": ");
+        let response = json_echo(&request, &RoutingData::default()).unwrap();
+
+        assert_eq!(400, response.status_code);
+        let actual: Value = serde_json::from_slice(&response.body).unwrap();
+        assert!(actual["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("cannot parse JSON body"));
+    }
+}
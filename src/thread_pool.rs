@@ -1,17 +1,23 @@
 use anyhow::Result;
-use log::trace;
+use log::{debug, error, trace};
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Mutex, PoisonError},
     thread,
 };
 
 pub struct ThreadPool {
-    _workers: Vec<Worker>,
-    sender: mpsc::Sender<Job>,
+    workers: Vec<Worker>,
+    sender: Option<mpsc::Sender<Message>>,
 }
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+enum Message {
+    NewJob(Job),
+    Terminate,
+}
+
 impl ThreadPool {
     /// Create a new ThreadPool.
     ///
@@ -35,8 +41,8 @@ impl ThreadPool {
         }
 
         ThreadPool {
-            _workers: workers,
-            sender,
+            workers,
+            sender: Some(sender),
         }
     }
 
@@ -46,32 +52,136 @@ impl ThreadPool {
     {
         let job = Box::new(f);
 
-        self.sender.send(job).unwrap();
+        self.sender
+            .as_ref()
+            .expect("thread pool should not be shut down")
+            .send(Message::NewJob(job))
+            .unwrap();
+    }
+}
+
+impl Drop for ThreadPool {
+    /// Lets the workers finish the queued jobs, then waits for all of them to stop.
+    fn drop(&mut self) {
+        if let Some(sender) = self.sender.take() {
+            debug!("sending terminate message to all workers");
+            for _ in &self.workers {
+                // workers only exit on terminate, so a failed send means they are already gone
+                let _ = sender.send(Message::Terminate);
+            }
+        }
+
+        for worker in &mut self.workers {
+            trace!("shutting down worker {}", worker.id);
+            if let Some(thread) = worker.thread.take() {
+                if thread.join().is_err() {
+                    debug!("worker {} panicked", worker.id);
+                }
+            }
+        }
     }
 }
 
 struct Worker {
-    _id: usize,
-    _thread: thread::JoinHandle<()>,
+    id: usize,
+    thread: Option<thread::JoinHandle<()>>,
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Result<Worker> {
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Result<Worker> {
         let builder = thread::Builder::new();
         let thread = builder.spawn(move || loop {
-            let job = receiver
+            // the guard is only held while waiting, a poisoned lock still holds a valid receiver
+            let message = receiver
                 .lock()
-                .expect("failed to acquire lock on receiver")
-                .recv()
-                .unwrap();
+                .unwrap_or_else(PoisonError::into_inner)
+                .recv();
 
-            trace!("worker {id} got a job; executing.");
-            job();
+            match message {
+                Ok(Message::NewJob(job)) => {
+                    trace!("worker {id} got a job; executing.");
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        error!("worker {id} recovered from a panicking job");
+                    }
+                }
+                Ok(Message::Terminate) | Err(_) => {
+                    trace!("worker {id} was told to terminate.");
+                    break;
+                }
+            }
         })?;
 
         Ok(Worker {
-            _id: id,
-            _thread: thread,
+            id,
+            thread: Some(thread),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_drop_runs_all_submitted_jobs() {
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        let pool = ThreadPool::new(3);
+        for _ in 0..12 {
+            let counter = Arc::clone(&counter);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(5));
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        drop(pool);
+
+        assert_eq!(12, counter.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_worker_survives_panicking_job() {
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        let pool = ThreadPool::new(1);
+        pool.execute(|| panic!("job failed"));
+        let job_counter = Arc::clone(&counter);
+        pool.execute(move || {
+            job_counter.fetch_add(1, Ordering::SeqCst);
+        });
+        drop(pool);
+
+        assert_eq!(1, counter.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_worker_recovers_poisoned_receiver() {
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let poisoner = Arc::clone(&receiver);
+        let _ = thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("panicked while holding the receiver");
         })
+        .join();
+        assert!(receiver.is_poisoned());
+
+        let mut worker = Worker::new(0, Arc::clone(&receiver)).unwrap();
+        let (done_sender, done_receiver) = mpsc::channel();
+        sender
+            .send(Message::NewJob(Box::new(move || {
+                done_sender.send(()).unwrap()
+            })))
+            .unwrap();
+        sender.send(Message::Terminate).unwrap();
+
+        assert!(done_receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        worker.thread.take().unwrap().join().unwrap();
     }
 }
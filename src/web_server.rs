@@ -1,73 +1,1018 @@
-use anyhow::{bail, Result};
-use log::{debug, error, info, trace};
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use log::{debug, error, info, log_enabled, trace, Level};
+use socket2::{Domain, Protocol, Socket, Type};
 use std::{
-    io::Write,
-    net::{TcpListener, TcpStream},
-    sync::{Arc, Mutex},
+    collections::HashMap,
+    io::{self, BufRead, BufWriter, Read, Write},
+    mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Sender},
+        Arc, Mutex, OnceLock, PoisonError,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(unix)]
+use std::{
+    fs,
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
 };
 
 use crate::{
-    http::{HttpRequest, HttpVersion},
+    access_log::{AccessLogEntry, AccessLogFormat},
+    compression::{self, CompressionConfig},
+    http::{
+        response_status_codes::HttpStatusCode, HttpHeader, HttpMethod, HttpRequest, HttpResponse,
+        HttpResponseBuilder, HttpVersion, RequestReadConfig, RequestReadError, SharedReader,
+        WebSocket,
+    },
     router::Router,
     thread_pool::ThreadPool,
 };
 
+/// Header carrying the id of a request, sent back on its response.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Longest client-supplied request id kept, longer ones are replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Default number of body bytes included in the request debug dump.
+const DEFAULT_DUMP_BODY_LIMIT: usize = 1024;
+
+/// Default number of worker threads of the pool handling connections.
+const DEFAULT_POOL_SIZE: usize = 4;
+
+/// Default time a connection may stay idle while reading a request or writing a response.
+const DEFAULT_IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default time a kept-alive connection may wait for its next request, short so that idle
+/// connections do not hold the workers of the pool for long.
+const DEFAULT_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Longest a connection waits for a request before checking whether the server shuts down.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Default number of pending connections the system queues before they are accepted, same as
+/// `TcpListener::bind`.
+pub const DEFAULT_LISTEN_BACKLOG: i32 = 128;
+
+/// `peer_ip` and `local_ip` of the requests received over a Unix domain socket.
+pub const UNIX_SOCKET_IP: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
+/// How accepted connections are dispatched to threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConcurrencyModel {
+    /// Spawns a new thread for every connection.
+    ThreadPerConnection,
+    /// Queues connections to a fixed number of worker threads.
+    FixedPool(usize),
+}
+
 pub struct WebServer {
     pub hostname: String,
-    pub router: Arc<Mutex<Router>>,
+    pub router: Arc<Router>,
     version: HttpVersion,
-    listener: TcpListener,
-    pool: ThreadPool,
+    listeners: Vec<Listener>,
+    pool: Option<ThreadPool>,
+    config: ServerConfig,
+    shutdown: Arc<AtomicBool>,
+    connection_counts: Arc<ConnectionCounts>,
+}
+
+/// Stops a running [`WebServer`] from another thread.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    shutdown: Arc<AtomicBool>,
+    listen_addrs: Vec<ListenAddr>,
+}
+
+impl ShutdownHandle {
+    /// Stops accepting new connections, `WebServer::run` returns once in-flight requests are
+    /// done.
+    pub fn shutdown(&self) {
+        info!("shutdown requested");
+        self.shutdown.store(true, Ordering::SeqCst);
+
+        // wake up the listeners blocked on accept so that they notice the flag
+        for listen_addr in &self.listen_addrs {
+            if let Err(e) = listen_addr.wake() {
+                debug!("failed to wake up listener on {listen_addr:?}: {e}");
+            }
+        }
+    }
+}
+
+/// Socket the server accepts connections on.
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        path: PathBuf,
+    },
+}
+
+/// Connection accepted by a [`Listener`].
+enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// Where a [`Listener`] accepts connections, used to wake it up.
+#[derive(Debug, Clone)]
+enum ListenAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Listener {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Listener::Tcp(listener) => Listener::Tcp(listener.try_clone()?),
+            #[cfg(unix)]
+            Listener::Unix { listener, path } => Listener::Unix {
+                listener: listener.try_clone()?,
+                path: path.to_owned(),
+            },
+        })
+    }
+
+    fn listen_addr(&self) -> io::Result<ListenAddr> {
+        Ok(match self {
+            Listener::Tcp(listener) => ListenAddr::Tcp(listener.local_addr()?),
+            #[cfg(unix)]
+            Listener::Unix { path, .. } => ListenAddr::Unix(path.to_owned()),
+        })
+    }
+
+    /// Sends the accepted connections to `sender` until the server is shut down.
+    fn forward_connections(&self, shutdown: &AtomicBool, sender: &Sender<io::Result<Connection>>) {
+        let connections: Box<dyn Iterator<Item = io::Result<Connection>>> = match self {
+            Listener::Tcp(listener) => {
+                Box::new(listener.incoming().map(|s| s.map(Connection::Tcp)))
+            }
+            #[cfg(unix)]
+            Listener::Unix { listener, .. } => {
+                Box::new(listener.incoming().map(|s| s.map(Connection::Unix)))
+            }
+        };
+
+        for connection in connections {
+            if shutdown.load(Ordering::SeqCst) || sender.send(connection).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+impl ListenAddr {
+    fn wake(&self) -> io::Result<()> {
+        match self {
+            ListenAddr::Tcp(local_addr) => {
+                let mut wake_addr = *local_addr;
+                if wake_addr.ip().is_unspecified() {
+                    wake_addr.set_ip(match wake_addr.ip() {
+                        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                    });
+                }
+                TcpStream::connect(wake_addr).map(drop)
+            }
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => UnixStream::connect(path).map(drop),
+        }
+    }
+}
+
+/// Settings shared with every connection handler.
+#[derive(Debug, Clone)]
+struct ServerConfig {
+    request_dump: bool,
+    dump_body_limit: usize,
+    read_config: RequestReadConfig,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    keep_alive_timeout: Duration,
+    access_log: Option<AccessLogFormat>,
+    compression: Option<CompressionConfig>,
+    max_connections_per_ip: Option<usize>,
+    max_requests_per_connection: Option<usize>,
+    keep_alive_header: bool,
+}
+
+/// Number of connections currently open by each peer IP.
+#[derive(Debug, Default)]
+struct ConnectionCounts(Mutex<HashMap<IpAddr, usize>>);
+
+impl ConnectionCounts {
+    /// Counts a new connection from `ip`, `None` if it already has `limit` connections open.
+    fn acquire(self: &Arc<Self>, ip: IpAddr, limit: usize) -> Option<ConnectionSlot> {
+        let mut counts = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let count = counts.entry(ip).or_default();
+        if *count >= limit {
+            return None;
+        }
+
+        *count += 1;
+        Some(ConnectionSlot {
+            counts: Arc::clone(self),
+            ip,
+        })
+    }
+}
+
+/// A counted connection, released when dropped.
+struct ConnectionSlot {
+    counts: Arc<ConnectionCounts>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut counts = self.counts.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(count) = counts.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.ip);
+            }
+        }
+    }
 }
 
 impl WebServer {
     pub fn new(hostname: &str, router: Router) -> Result<Self> {
-        let listener = TcpListener::bind(hostname).unwrap();
-        let pool = ThreadPool::new(4);
+        Self::bind(&[hostname], router)
+    }
 
-        Ok(WebServer {
-            hostname: hostname.to_owned(),
-            router: Arc::new(Mutex::new(router)),
-            version: HttpVersion::HTTP1_1,
+    /// Listens on all of `addresses` at once, e.g. `["127.0.0.1:7878", "[::1]:7878"]` for
+    /// dual-stack, every connection goes to the same router and threads.
+    pub fn bind(addresses: &[&str], router: Router) -> Result<Self> {
+        Self::bind_with_backlog(addresses, DEFAULT_LISTEN_BACKLOG, router)
+    }
+
+    /// Same as [`WebServer::bind`], with `backlog` pending connections queued by the system
+    /// before new ones are refused.
+    ///
+    /// Outside of Windows, where it would let other processes steal the port, the listeners
+    /// use `SO_REUSEADDR` so that a restarted server can bind while the connections of the
+    /// previous one linger in `TIME_WAIT`.
+    pub fn bind_with_backlog(addresses: &[&str], backlog: i32, router: Router) -> Result<Self> {
+        if addresses.is_empty() {
+            bail!("cannot start a server without any address to listen on");
+        }
+
+        let listeners = addresses
+            .iter()
+            .map(|address| {
+                let listener = bind_tcp(address, backlog)
+                    .with_context(|| format!("cannot listen on {address}"))?;
+                Ok(Listener::Tcp(listener))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self::with_listeners(
+            addresses.join(", "),
+            listeners,
+            router,
+        ))
+    }
+
+    /// Listens on a Unix domain socket created at `path`, which is removed once the server
+    /// shuts down.
+    ///
+    /// Requests received on it have [`UNIX_SOCKET_IP`] as `peer_ip` and `local_ip`.
+    #[cfg(unix)]
+    pub fn bind_unix(path: &str, router: Router) -> Result<Self> {
+        let listener =
+            UnixListener::bind(path).with_context(|| format!("cannot listen on {path}"))?;
+        let listener = Listener::Unix {
             listener,
-            pool,
+            path: PathBuf::from(path),
+        };
+
+        Ok(Self::with_listeners(
+            format!("unix:{path}"),
+            vec![listener],
+            router,
+        ))
+    }
+
+    fn with_listeners(hostname: String, listeners: Vec<Listener>, router: Router) -> Self {
+        let pool = ThreadPool::new(DEFAULT_POOL_SIZE);
+
+        WebServer {
+            hostname,
+            router: Arc::new(router),
+            version: HttpVersion::HTTP1_1,
+            listeners,
+            pool: Some(pool),
+            config: ServerConfig {
+                request_dump: true,
+                dump_body_limit: DEFAULT_DUMP_BODY_LIMIT,
+                read_config: RequestReadConfig::default(),
+                read_timeout: Some(DEFAULT_IO_TIMEOUT),
+                write_timeout: Some(DEFAULT_IO_TIMEOUT),
+                keep_alive_timeout: DEFAULT_KEEP_ALIVE_TIMEOUT,
+                access_log: None,
+                compression: None,
+                max_connections_per_ip: None,
+                max_requests_per_connection: None,
+                keep_alive_header: false,
+            },
+            shutdown: Arc::new(AtomicBool::new(false)),
+            connection_counts: Arc::default(),
+        }
+    }
+
+    /// Address of the first TCP listener, see [`WebServer::local_addrs`] for the others.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        match self.local_addrs()?.first() {
+            Some(local_addr) => Ok(*local_addr),
+            None => bail!("server is not listening on TCP"),
+        }
+    }
+
+    /// Addresses of all the TCP listeners, in the order they were given.
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>> {
+        self.listeners
+            .iter()
+            .filter_map(|listener| match listener {
+                Listener::Tcp(listener) => Some(listener.local_addr().map_err(Into::into)),
+                #[cfg(unix)]
+                Listener::Unix { .. } => None,
+            })
+            .collect()
+    }
+
+    /// Returns a handle that can stop the server once it runs.
+    pub fn shutdown_handle(&self) -> Result<ShutdownHandle> {
+        let listen_addrs = self
+            .listeners
+            .iter()
+            .map(Listener::listen_addr)
+            .collect::<io::Result<_>>()?;
+
+        Ok(ShutdownHandle {
+            shutdown: Arc::clone(&self.shutdown),
+            listen_addrs,
         })
     }
 
-    pub fn run(&self) -> Result<()> {
+    /// Accepts connections until shut down through a [`ShutdownHandle`], then waits for the
+    /// requests being handled to complete.
+    pub fn run(self) -> Result<()> {
         info!("server started on {}", self.hostname);
         info!("awaiting connections...");
 
-        for stream in self.listener.incoming() {
-            debug!("{}", "got new tcp connection!");
-            let stream = stream?;
+        // each listener accepts on its own thread, the connections are dispatched from here
+        let (sender, receiver) = mpsc::channel();
+        let mut accept_threads = vec![];
+        for listener in &self.listeners {
+            let listener = listener.try_clone()?;
+            let sender = sender.clone();
+            let shutdown = Arc::clone(&self.shutdown);
+            accept_threads.push(
+                thread::Builder::new()
+                    .spawn(move || listener.forward_connections(&shutdown, &sender))?,
+            );
+        }
+        drop(sender);
+
+        let mut connection_threads = vec![];
+        for connection in receiver {
+            if self.shutdown.load(Ordering::SeqCst) {
+                info!("server stopped accepting connections");
+                break;
+            }
+
+            debug!("{}", "got new connection!");
+            let connection = connection?;
+
+            let slot = match (&connection, self.config.max_connections_per_ip) {
+                (Connection::Tcp(stream), Some(limit)) => {
+                    // the client may already be gone, which must not stop the server
+                    let peer_ip = match stream.peer_addr() {
+                        Ok(peer_addr) => peer_addr.ip(),
+                        Err(e) => {
+                            debug!("dropping connection without peer address: {e}");
+                            continue;
+                        }
+                    };
+                    match self.connection_counts.acquire(peer_ip, limit) {
+                        Some(slot) => Some(slot),
+                        None => {
+                            info!("rejecting connection: {peer_ip} has {limit} connections open");
+                            if let Err(e) = reject_connection(&self.config, stream) {
+                                debug!("failed to reject connection: {e}");
+                            }
+                            continue;
+                        }
+                    }
+                }
+                _ => None,
+            };
 
             let router_clone = Arc::clone(&self.router);
-            self.pool.execute(move || {
-                let result = handle_connection(router_clone, stream);
+            let config = self.config.clone();
+            let shutdown = Arc::clone(&self.shutdown);
+            let job = move || {
+                // the connection stops counting towards the limit of its IP once handled
+                let _slot = slot;
+                let result = handle_connection(router_clone, &config, &shutdown, connection);
                 if let Err(result) = result {
                     error!("handle_connection failed: {}", result);
                 }
-            });
+            };
+
+            match &self.pool {
+                Some(pool) => pool.execute(job),
+                None => {
+                    connection_threads.retain(|thread: &JoinHandle<()>| !thread.is_finished());
+                    connection_threads.push(thread::Builder::new().spawn(job)?);
+                }
+            }
         }
 
+        for thread in accept_threads {
+            if thread.join().is_err() {
+                error!("accept thread panicked");
+            }
+        }
+
+        #[cfg(unix)]
+        for listener in &self.listeners {
+            if let Listener::Unix { path, .. } = listener {
+                if let Err(e) = fs::remove_file(path) {
+                    error!("failed to remove socket {}: {e}", path.display());
+                }
+            }
+        }
+
+        // dropping the pool lets the workers finish their jobs and joins them
+        drop(self.pool);
+        for thread in connection_threads {
+            if thread.join().is_err() {
+                error!("connection thread panicked");
+            }
+        }
+        info!("server shut down");
         Ok(())
     }
 
+    /// Serves a single connection read from `reader` and written to `writer`, as if it had been
+    /// accepted by a listener, e.g. to test the server without sockets or to serve another
+    /// kind of stream. Returns once the connection is closed.
+    pub fn serve_connection(
+        &self,
+        reader: impl Read + Send + 'static,
+        writer: impl Write + Send + 'static,
+        peer_ip: IpAddr,
+        local_ip: IpAddr,
+    ) -> Result<()> {
+        let reader = self.config.read_config.buf_reader(reader);
+        let idle = IdleWait {
+            shutdown: &self.shutdown,
+            set_read_timeout: None,
+        };
+        serve_connection(
+            &self.router,
+            &self.config,
+            reader,
+            writer,
+            (peer_ip, local_ip),
+            idle,
+        )
+    }
+
+    /// Selects how connections are dispatched to threads (defaults to a pool of 4 workers).
+    pub fn concurrency(mut self, model: ConcurrencyModel) -> Self {
+        self.pool = match model {
+            ConcurrencyModel::ThreadPerConnection => None,
+            ConcurrencyModel::FixedPool(size) => Some(ThreadPool::new(size)),
+        };
+        self
+    }
+
     pub fn http_version(mut self, version: HttpVersion) -> Self {
         self.version = version;
         self
     }
+
+    /// Logs a dump of every request at `debug` level, built only when that level is enabled.
+    /// Enabled by default.
+    pub fn request_dump(mut self, enabled: bool) -> Self {
+        self.config.request_dump = enabled;
+        self
+    }
+
+    /// Sets how many body bytes are included in the request debug dump (defaults to 1024).
+    pub fn dump_body_limit(mut self, limit: usize) -> Self {
+        self.config.dump_body_limit = limit;
+        self
+    }
+
+    /// Sets the largest request line and headers accepted together, bigger requests get a
+    /// `431 Request Header Fields Too Large` (defaults to 32 KiB).
+    pub fn max_head_size(mut self, bytes: usize) -> Self {
+        self.config.read_config.max_head_size = bytes;
+        self
+    }
+
+    /// Sets how long a client may take to send the request line and headers once it started,
+    /// slower requests get a `408 Request Timeout` (defaults to 10 seconds, `None` waits
+    /// forever).
+    pub fn head_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.read_config.head_timeout = timeout;
+        self
+    }
+
+    /// Sets the largest request body accepted, bigger requests get a `413 Content Too Large`
+    /// (defaults to 2 MiB).
+    pub fn max_body_size(mut self, bytes: usize) -> Self {
+        self.config.read_config.max_body_size = bytes;
+        self
+    }
+
+    /// Sets the capacity of the buffer reading requests from each connection (defaults to
+    /// 8 KiB), a larger one means fewer reads on the socket for big requests.
+    pub fn read_buffer_size(mut self, bytes: usize) -> Self {
+        self.config.read_config.read_buffer_size = bytes;
+        self
+    }
+
+    /// Sets the methods whose request body is read, defaults to
+    /// [`DEFAULT_BODY_METHODS`](crate::http::request_raw::DEFAULT_BODY_METHODS). Bodies sent with other
+    /// methods are discarded without reaching the handlers.
+    pub fn body_methods(mut self, methods: &[HttpMethod]) -> Self {
+        self.config.read_config.body_methods = methods.to_vec();
+        self
+    }
+
+    /// Logs every request served in the given format, with `info` level on the `access_log`
+    /// target.
+    pub fn access_log(mut self, format: AccessLogFormat) -> Self {
+        self.config.access_log = Some(format);
+        self
+    }
+
+    /// Sets how long a read from a client may block before the connection is dropped
+    /// (defaults to 5 seconds, `None` waits forever).
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.read_timeout = timeout;
+        self
+    }
+
+    /// Sets how long a kept-alive connection may stay idle waiting for its next request before
+    /// it is closed (defaults to 2 seconds). Idle connections hold a worker of the pool, see
+    /// [`WebServer::concurrency`].
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.config.keep_alive_timeout = timeout;
+        self
+    }
+
+    /// Sets how long a write to a client may block before the connection is dropped
+    /// (defaults to 5 seconds, `None` waits forever).
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.write_timeout = timeout;
+        self
+    }
+
+    /// Limits how many connections a single peer IP may keep open at once, the connections over
+    /// the limit get a `503 Service Unavailable` and are closed. Unlimited by default.
+    pub fn max_connections_per_ip(mut self, limit: usize) -> Self {
+        self.config.max_connections_per_ip = Some(limit);
+        self
+    }
+
+    /// Closes connections once they have served `limit` requests, the last response carries
+    /// `Connection: close`. Unlimited by default.
+    pub fn max_requests_per_connection(mut self, limit: usize) -> Self {
+        self.config.max_requests_per_connection = Some(limit);
+        self
+    }
+
+    /// When enabled, responses keeping the connection open carry a `Keep-Alive` header with the
+    /// keep-alive timeout as `timeout` and the number of requests still allowed as `max`, when they
+    /// are limited. Disabled by default.
+    pub fn keep_alive_header(mut self, enabled: bool) -> Self {
+        self.config.keep_alive_header = enabled;
+        self
+    }
+
+    /// Compresses with gzip the responses matching `config`, from handlers and the file server
+    /// alike, for the clients that accept it. Disabled by default.
+    pub fn with_compression(mut self, config: CompressionConfig) -> Self {
+        self.config.compression = Some(config);
+        self
+    }
+}
+
+/// Listens on the first address `address` resolves to that can be bound, like
+/// `TcpListener::bind`.
+fn bind_tcp(address: &str, backlog: i32) -> Result<TcpListener> {
+    let mut last_error = None;
+    for socket_addr in address.to_socket_addrs()? {
+        let socket = Socket::new(
+            Domain::for_address(socket_addr),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        )?;
+        #[cfg(not(windows))]
+        socket.set_reuse_address(true)?;
+
+        match socket
+            .bind(&socket_addr.into())
+            .and_then(|_| socket.listen(backlog))
+        {
+            Ok(()) => return Ok(socket.into()),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    match last_error {
+        Some(error) => Err(error.into()),
+        None => bail!("{address} does not resolve to any address"),
+    }
+}
+
+/// Answers `503 Service Unavailable` without reading anything from the connection.
+fn reject_connection(config: &ServerConfig, mut stream: &TcpStream) -> Result<()> {
+    stream.set_write_timeout(config.write_timeout)?;
+    HttpResponseBuilder::new()
+        .set_status(HttpStatusCode::ServiceUnavailable)
+        .set_header("Connection", "close")
+        .set_empty_content_length(true)
+        .build()?
+        .write_to(&mut stream)
+}
+
+fn handle_connection(
+    router: Arc<Router>,
+    config: &ServerConfig,
+    shutdown: &AtomicBool,
+    connection: Connection,
+) -> Result<()> {
+    match connection {
+        Connection::Tcp(stream) => {
+            stream.set_read_timeout(config.read_timeout)?;
+            stream.set_write_timeout(config.write_timeout)?;
+
+            let peer_ip = stream.peer_addr()?.ip();
+            let local_ip = stream.local_addr()?.ip();
+            let reader = config.read_config.buf_reader(stream.try_clone()?);
+            let timeout_stream = stream.try_clone()?;
+            let idle = IdleWait {
+                shutdown,
+                set_read_timeout: Some(Box::new(move |timeout| {
+                    timeout_stream.set_read_timeout(timeout)
+                })),
+            };
+            serve_connection(&router, config, reader, stream, (peer_ip, local_ip), idle)
+        }
+        #[cfg(unix)]
+        Connection::Unix(stream) => {
+            stream.set_read_timeout(config.read_timeout)?;
+            stream.set_write_timeout(config.write_timeout)?;
+
+            let reader = config.read_config.buf_reader(stream.try_clone()?);
+            let timeout_stream = stream.try_clone()?;
+            let idle = IdleWait {
+                shutdown,
+                set_read_timeout: Some(Box::new(move |timeout| {
+                    timeout_stream.set_read_timeout(timeout)
+                })),
+            };
+            serve_connection(
+                &router,
+                config,
+                reader,
+                stream,
+                (UNIX_SOCKET_IP, UNIX_SOCKET_IP),
+                idle,
+            )
+        }
+    }
+}
+
+/// Sets the read timeout of a connection.
+type SetReadTimeout = Box<dyn Fn(Option<Duration>) -> io::Result<()>>;
+
+/// How a connection waits for its next request.
+struct IdleWait<'a> {
+    /// Set once the server shuts down, the connection is then closed instead of kept alive.
+    shutdown: &'a AtomicBool,
+    /// Changes the read timeout of the connection, so that the wait can be cut in short slices
+    /// checking `shutdown`. Without it, the wait lasts as long as the reader blocks.
+    set_read_timeout: Option<SetReadTimeout>,
+}
+
+impl IdleWait<'_> {
+    fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Waits up to `limit` for the client to send data, `false` means the connection should be
+    /// closed: the client closed it, stayed idle for too long or the server shuts down.
+    fn wait_for_request(
+        &self,
+        reader: &SharedReader,
+        limit: Option<Duration>,
+        read_timeout: Option<Duration>,
+    ) -> Result<bool> {
+        let Some(set_read_timeout) = &self.set_read_timeout else {
+            return Ok(!self.is_shutdown() && has_pending_data(reader)?);
+        };
+
+        let deadline = limit.map(|limit| Instant::now() + limit);
+        loop {
+            if self.is_shutdown() {
+                return Ok(false);
+            }
+
+            let slice = match deadline {
+                Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+                None => IDLE_POLL_INTERVAL,
+            };
+            if slice.is_zero() {
+                return Ok(false);
+            }
+
+            set_read_timeout(Some(slice.min(IDLE_POLL_INTERVAL)))?;
+            match has_pending_data(reader) {
+                Ok(has_data) => {
+                    set_read_timeout(read_timeout)?;
+                    return Ok(has_data);
+                }
+                Err(error) if is_timeout(&error) => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
 }
 
-fn handle_connection(router: Arc<Mutex<Router>>, mut stream: TcpStream) -> Result<()> {
-    let request = HttpRequest::from_tcp(&stream);
-    if let Err(error) = request {
-        bail!("failed to create request from TCP: {error} (could be that client is trying to initiate a TLS handshake)");
+/// Answers the requests read from `reader` on `writer` until the client closes the connection
+/// or a response asks to close it.
+fn serve_connection<R, W>(
+    router: &Router,
+    config: &ServerConfig,
+    reader: R,
+    mut stream: W,
+    (peer_ip, local_ip): (IpAddr, IpAddr),
+    idle: IdleWait,
+) -> Result<()>
+where
+    R: BufRead + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let reader: SharedReader = Arc::new(Mutex::new(reader));
+
+    // pipelined requests are handled one at a time, so the responses are always written in
+    // the order the requests arrived, the next request is only read once the previous
+    // response is sent
+    let mut served = 0;
+    loop {
+        // the first request may take as long as any read, the next ones have their own limit
+        let idle_limit = match served {
+            0 => config.read_timeout,
+            _ => Some(config.keep_alive_timeout),
+        };
+        match idle.wait_for_request(&reader, idle_limit, config.read_timeout) {
+            Ok(true) => {}
+            Ok(false) => {
+                debug!("closing connection: closed by the client, idle or server shutting down");
+                return Ok(());
+            }
+            Err(error) if is_timeout(&error) => {
+                debug!("closing idle connection");
+                return Ok(());
+            }
+            Err(error) => return Err(error),
+        }
+
+        let request =
+            HttpRequest::from_reader(Arc::clone(&reader), peer_ip, local_ip, &config.read_config);
+        if let Err(error) = request {
+            if is_timeout(&error) {
+                info!("dropping connection: timed out waiting for the request");
+                return Ok(());
+            }
+
+            if let Some(read_error) = error.downcast_ref::<RequestReadError>() {
+                debug!("rejecting request: {read_error}");
+                let response = HttpResponseBuilder::new()
+                    .set_status(read_error.status_code())
+                    .set_header("Connection", "close")
+                    .build()?;
+                response.write_to(&mut stream)?;
+                return Ok(());
+            }
+
+            bail!("failed to read request: {error}");
+        }
+
+        let mut request = request?;
+        let request_id = assign_request_id(&mut request);
+        let received_at = Utc::now();
+        let started = Instant::now();
+        if wants_request_dump(config, log_enabled!(Level::Debug)) {
+            debug!("{}", format_request_dump(&request, config.dump_body_limit));
+        }
+
+        let mut response =
+            match panic::catch_unwind(AssertUnwindSafe(|| router.handle_request(&request))) {
+                Ok(Ok(response)) => response,
+                Ok(Err(e)) => {
+                    error!(
+                        "failed to handle: {} {}: {e:#}",
+                        request.method, request.resource_path
+                    );
+                    internal_server_error()?
+                }
+                Err(_) => {
+                    error!(
+                        "handler panicked on: {} {}",
+                        request.method, request.resource_path
+                    );
+                    internal_server_error()?
+                }
+            };
+
+        if let Some(compression) = &config.compression {
+            compression::gzip_response(&request, &mut response, compression)?;
+        }
+
+        // an empty in-memory body still needs its length for the next response to be found,
+        // streams of unknown length can only end with the connection
+        let is_head = request.method == HttpMethod::HEAD;
+        if !is_head && !response.is_delimited() && response.stream.is_none() {
+            let body = mem::take(&mut response.body);
+            response.set_body(body);
+        }
+
+        if response.get_header(REQUEST_ID_HEADER).is_none() {
+            response.set_header(REQUEST_ID_HEADER, &request_id);
+        }
+
+        served += 1;
+        let requests_left = config
+            .max_requests_per_connection
+            .map(|limit| limit.saturating_sub(served));
+        let keep_alive = wants_keep_alive(&request)
+            && requests_left != Some(0)
+            && !idle.is_shutdown()
+            && (is_head || response.is_delimited())
+            && !response
+                .get_header("Connection")
+                .is_some_and(|header| has_token(&header.value, "close"));
+        // the upgrade response keeps its own `Connection: Upgrade`
+        if response.upgrade.is_none() {
+            let connection = if keep_alive { "keep-alive" } else { "close" };
+            response.set_header("Connection", connection);
+        }
+        if keep_alive && config.keep_alive_header {
+            let mut parameters = format!("timeout={}", config.keep_alive_timeout.as_secs());
+            if let Some(left) = requests_left {
+                parameters.push_str(&format!(", max={left}"));
+            }
+            response.set_header("Keep-Alive", &parameters);
+        }
+
+        // HEAD responses announce the body of the GET response without sending it
+        let mut writer = BufWriter::new(&mut stream);
+        match request.method {
+            HttpMethod::HEAD => response.write_head_to(&mut writer)?,
+            _ => response.write_to(&mut writer)?,
+        }
+        drop(writer);
+
+        if let Some(format) = config.access_log {
+            let entry = AccessLogEntry::new(&request, &response, received_at, started.elapsed());
+            info!(target: "access_log", "{}", entry.format(format));
+        }
+
+        // leave nothing unread on the connection when the handler ignored the body, the next
+        // request starts right after it
+        request.body.drain()?;
+
+        if let Some(callback) = response.upgrade {
+            debug!("connection upgraded to WebSocket");
+            let mut websocket = WebSocket::new(reader, stream);
+            return callback(&request, &mut websocket);
+        }
+
+        if !keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+/// Answers requests the router could not, when a handler panicked or no response could be built.
+fn internal_server_error() -> Result<HttpResponse> {
+    HttpResponseBuilder::new()
+        .set_status(HttpStatusCode::InternalServerError)
+        .build()
+}
+
+/// Gives the request an `X-Request-Id` header unless the client sent a valid one, returns its
+/// value.
+fn assign_request_id(request: &mut HttpRequest) -> String {
+    if let Some(header) = request.get_header(REQUEST_ID_HEADER) {
+        if is_valid_request_id(&header.value) {
+            return header.value.clone();
+        }
+        debug!(
+            "replacing invalid client {REQUEST_ID_HEADER}: {:?}",
+            header.value
+        );
+    }
+
+    let request_id = next_request_id();
+    request
+        .headers
+        .retain(|name, _| !name.eq_ignore_ascii_case(REQUEST_ID_HEADER));
+    request.headers.insert(
+        REQUEST_ID_HEADER.to_owned(),
+        vec![HttpHeader::new(REQUEST_ID_HEADER, &request_id)],
+    );
+    request_id
+}
+
+/// Whether a client-supplied request id can be echoed in the response and the logs as is: up
+/// to 128 letters, digits, `.`, `_` or `-`.
+fn is_valid_request_id(request_id: &str) -> bool {
+    (1..=MAX_REQUEST_ID_LEN).contains(&request_id.len())
+        && request_id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'.' | b'_' | b'-'))
+}
+
+/// Short id unique to this process, made of a prefix picked at startup and a counter.
+fn next_request_id() -> String {
+    static PREFIX: OnceLock<u32> = OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let prefix = PREFIX.get_or_init(|| {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        nanos ^ std::process::id().rotate_left(16)
+    });
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{prefix:08x}-{count:x}")
+}
+
+/// Waits for the client to send more data, `false` means the connection was closed.
+fn has_pending_data(reader: &SharedReader) -> Result<bool> {
+    let mut reader = reader.lock().unwrap_or_else(PoisonError::into_inner);
+    Ok(!reader.fill_buf()?.is_empty())
+}
+
+/// HTTP/1.1 connections are persistent unless closed, HTTP/1.0 ones only when asked for.
+fn wants_keep_alive(request: &HttpRequest) -> bool {
+    let connection = request.header_values("Connection");
+    if connection.iter().any(|value| has_token(value, "close")) {
+        return false;
     }
 
-    let request = request?;
+    match request.version {
+        HttpVersion::HTTP1_1 => true,
+        _ => connection
+            .iter()
+            .any(|value| has_token(value, "keep-alive")),
+    }
+}
+
+fn has_token(header_value: &str, token: &str) -> bool {
+    header_value
+        .split(',')
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+fn is_timeout(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<io::Error>().is_some_and(|error| {
+        matches!(
+            error.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        )
+    })
+}
+
+/// Whether the request dump should be built, it is expensive for large headers and bodies so it
+/// is skipped unless `debug_enabled` tells that debug logs are written.
+fn wants_request_dump(config: &ServerConfig, debug_enabled: bool) -> bool {
+    config.request_dump && debug_enabled
+}
 
+fn format_request_dump(request: &HttpRequest, body_limit: usize) -> String {
     let mut request_dbg = String::new();
     request_dbg.push_str("\r\n>>> Request START <<<\r\n");
     request_dbg.push_str(
@@ -80,14 +1025,32 @@ fn handle_connection(router: Arc<Mutex<Router>>, mut stream: TcpStream) -> Resul
 
     request_dbg.push_str(">>> HEADERS <<<\r\n");
 
-    for header in request.headers.values() {
+    for header in request.headers.values().flatten() {
         request_dbg.push_str(format!("{}: {}\r\n", header.name, header.value).as_str());
     }
 
-    if !request.body.is_empty() {
+    let Some(body) = request.body.loaded() else {
+        request_dbg.push_str(">>> BODY <<<\r\n::NOT READ YET::\r\n");
+        request_dbg.push_str(">>> Request END <<<\r\n");
+        return request_dbg;
+    };
+
+    if !body.is_empty() {
         request_dbg.push_str(">>> BODY <<<\r\n");
-        match String::from_utf8(request.body.clone()) {
-            Ok(value) => request_dbg.push_str(format!("::TEXT DATA::\r\n{}\r\n", value).as_str()),
+        match std::str::from_utf8(body) {
+            Ok(value) => {
+                let mut end = body_limit.min(value.len());
+                while !value.is_char_boundary(end) {
+                    end -= 1;
+                }
+
+                request_dbg.push_str(format!("::TEXT DATA::\r\n{}", &value[..end]).as_str());
+                if end < value.len() {
+                    request_dbg
+                        .push_str(format!("... ({} more bytes)", value.len() - end).as_str());
+                }
+                request_dbg.push_str("\r\n");
+            }
             Err(e) => {
                 trace!(
                     "failed to parse to UTF8 str -> likely got binary body: {}",
@@ -99,14 +1062,1096 @@ fn handle_connection(router: Arc<Mutex<Router>>, mut stream: TcpStream) -> Resul
     }
 
     request_dbg.push_str(">>> Request END <<<\r\n");
-    debug!("{}", request_dbg);
+    request_dbg
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::IpAddr,
+        str::FromStr,
+        sync::Once,
+    };
 
-    let response = router
-        .lock()
+    use crate::{
+        http::{
+            sse,
+            websocket::{self, Message},
+            BodyStream, HttpRequestRaw, SseEvent,
+        },
+        middleware::BeforeCallback,
+        router::RoutingData,
+    };
+
+    use super::*;
+
+    fn get_post_request(body: &[u8]) -> HttpRequest {
+        HttpRequest::from_raw_request(HttpRequestRaw {
+            request_line: "POST /upload HTTP/1.1".to_owned(),
+            headers: vec![],
+            body: body.to_vec(),
+            trailers: vec![],
+            peer_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+            local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+        })
         .unwrap()
-        .handle_request(&request)?
-        .to_bytes()?;
+    }
+
+    #[test]
+    fn test_request_dump_skipped_without_debug_logs() {
+        let server = WebServer::new("127.0.0.1:0", Router::new()).unwrap();
+        assert!(wants_request_dump(&server.config, true));
+        assert!(!wants_request_dump(&server.config, false));
+
+        let server = server.request_dump(false);
+        assert!(!wants_request_dump(&server.config, true));
+        assert!(!wants_request_dump(&server.config, false));
+    }
+
+    #[test]
+    fn test_request_dump_small_body() {
+        let request = get_post_request("hello".as_bytes());
+        let dump = format_request_dump(&request, DEFAULT_DUMP_BODY_LIMIT);
+
+        assert!(dump.contains("::TEXT DATA::\r\nhello\r\n"));
+    }
+
+    #[test]
+    fn test_request_dump_large_body_truncated() {
+        let request = get_post_request(&[b'a'; 10_000]);
+        let dump = format_request_dump(&request, 16);
+
+        let expected = format!(
+            "::TEXT DATA::\r\n{}... (9984 more bytes)\r\n",
+            "a".repeat(16)
+        );
+        assert!(dump.contains(&expected));
+        assert!(!dump.contains(&"a".repeat(17)));
+    }
+
+    #[test]
+    fn test_request_dump_truncates_on_char_boundary() {
+        let request = get_post_request("ééé".as_bytes());
+        let dump = format_request_dump(&request, 3);
+
+        assert!(dump.contains("::TEXT DATA::\r\né... (4 more bytes)\r\n"));
+    }
+
+    fn get_hello(_request: &HttpRequest, _routing_data: &RoutingData) -> Result<HttpResponse> {
+        HttpResponseBuilder::new().set_html_body("hello").build()
+    }
+
+    fn send_request(addr: SocketAddr, raw_request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(raw_request.as_bytes()).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn get_panic(_request: &HttpRequest, _routing_data: &RoutingData) -> Result<HttpResponse> {
+        panic!("handler bug")
+    }
+
+    fn get_failing(_request: &HttpRequest, _routing_data: &RoutingData) -> Result<HttpResponse> {
+        bail!("database password is hunter2")
+    }
+
+    fn get_empty(_request: &HttpRequest, _routing_data: &RoutingData) -> Result<HttpResponse> {
+        HttpResponseBuilder::new().build()
+    }
+
+    fn get_unframed_stream(
+        _request: &HttpRequest,
+        _routing_data: &RoutingData,
+    ) -> Result<HttpResponse> {
+        let (sender, events) = sse::channel();
+        sender.send(SseEvent::new("done"))?;
+
+        let mut response = HttpResponseBuilder::new().build()?;
+        response.stream = Some(BodyStream::Events(events));
+        Ok(response)
+    }
+
+    /// Keeps the lines logged on the `access_log` target.
+    struct AccessLogCapture(Mutex<Vec<String>>);
+
+    impl log::Log for AccessLogCapture {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.target() == "access_log"
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                self.0.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static ACCESS_LOG: AccessLogCapture = AccessLogCapture(Mutex::new(Vec::new()));
+    static INSTALL_ACCESS_LOG: Once = Once::new();
+
+    /// Installs the capture as the global logger, once for all the tests.
+    fn install_access_log() {
+        INSTALL_ACCESS_LOG.call_once(|| {
+            // the logger can only be set once per process, another one may already be in place
+            if log::set_logger(&ACCESS_LOG).is_ok() {
+                log::set_max_level(log::LevelFilter::Info);
+            }
+        });
+    }
+
+    /// Serves a single request while `idle_connections` other clients hold a connection open.
+    fn serve_with_idle_connections(model: ConcurrencyModel, idle_connections: usize) -> String {
+        let router = Router::new().get("/hello", get_hello).unwrap();
+        let server = WebServer::new("127.0.0.1:0", router)
+            .unwrap()
+            .concurrency(model);
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        let idle: Vec<_> = (0..idle_connections)
+            .map(|_| TcpStream::connect(addr).unwrap())
+            .collect();
+        let response = send_request(addr, "GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n");
+
+        drop(idle);
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+        response
+    }
+
+    #[test]
+    fn test_fixed_pool_serves_request() {
+        let response = serve_with_idle_connections(ConcurrencyModel::FixedPool(2), 0);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("hello\r\n"));
+    }
+
+    #[test]
+    fn test_thread_per_connection_does_not_queue() {
+        // with a pool, the idle connections would occupy every worker and block the request
+        let response = serve_with_idle_connections(ConcurrencyModel::ThreadPerConnection, 8);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("hello\r\n"));
+    }
+
+    #[test]
+    fn test_handler_panic_returns_500_and_keeps_serving() {
+        let router = Router::new()
+            .get("/hello", get_hello)
+            .unwrap()
+            .get("/panic", get_panic)
+            .unwrap();
+        let server = WebServer::new("127.0.0.1:0", router)
+            .unwrap()
+            .concurrency(ConcurrencyModel::FixedPool(1));
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        let response = send_request(addr, "GET /panic HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+
+        let response = send_request(addr, "GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_handler_error_returns_500_without_details() {
+        let router = Router::new().get("/fail", get_failing).unwrap();
+        let server = WebServer::new("127.0.0.1:0", router).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        let response = send_request(addr, "GET /fail HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(!response.contains("hunter2"));
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_middleware_error_returns_500_and_keeps_serving() {
+        fn failing_before(request: &HttpRequest) -> Result<Option<HttpResponse>> {
+            if request.resource_path == "/fail" {
+                bail!("session store unreachable");
+            }
+            Ok(None)
+        }
+
+        let router = Router::new()
+            .use_middleware(failing_before as BeforeCallback)
+            .get("/hello", get_hello)
+            .unwrap()
+            .get("/fail", get_hello)
+            .unwrap();
+        let server = WebServer::new("127.0.0.1:0", router).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        let response = send_request(
+            addr,
+            "GET /fail HTTP/1.1\r\n\r\nGET /hello HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(!response.contains("session store"));
+        assert!(response.contains("HTTP/1.1 200 OK\r\n"));
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_handler_connection_close_any_case() {
+        fn get_closing(
+            _request: &HttpRequest,
+            _routing_data: &RoutingData,
+        ) -> Result<HttpResponse> {
+            HttpResponseBuilder::new()
+                .set_header("connection", "Close")
+                .set_html_body("bye")
+                .build()
+        }
+
+        let router = Router::new().get("/bye", get_closing).unwrap();
+        let server = WebServer::new("127.0.0.1:0", router).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        // the pipelined request is not answered once the handler asked to close
+        let response = send_request(addr, "GET /bye HTTP/1.1\r\n\r\nGET /bye HTTP/1.1\r\n\r\n");
+        assert_eq!(1, response.matches("HTTP/1.1 200 OK\r\n").count());
+        assert_eq!(
+            1,
+            response.to_ascii_lowercase().matches("connection:").count()
+        );
+        assert!(response.contains("Connection: close\r\n"));
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_keep_alive_response_without_length() {
+        let router = Router::new()
+            .get("/empty", get_empty)
+            .unwrap()
+            .get("/stream", get_unframed_stream)
+            .unwrap();
+        let server = WebServer::new("127.0.0.1:0", router).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        // the empty body gets a length, the connection stays open for the second request
+        let response = send_request(
+            addr,
+            "GET /empty HTTP/1.1\r\n\r\nGET /empty HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        assert_eq!(2, response.matches("HTTP/1.1 200 OK\r\n").count());
+        assert_eq!(2, response.matches("Content-Length: 0\r\n").count());
+        assert!(response.contains("Connection: keep-alive\r\n"));
+
+        // the stream can only end with the connection, the pipelined request is not answered
+        let response = send_request(
+            addr,
+            "GET /stream HTTP/1.1\r\n\r\nGET /empty HTTP/1.1\r\n\r\n",
+        );
+        assert_eq!(1, response.matches("HTTP/1.1 200 OK\r\n").count());
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("data: done\n\n"));
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_access_log_line_written() {
+        install_access_log();
+
+        let router = Router::new().get("/logged", get_hello).unwrap();
+        let server = WebServer::new("127.0.0.1:0", router)
+            .unwrap()
+            .access_log(AccessLogFormat::CommonWithDuration);
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        send_request(
+            addr,
+            "GET /logged?v=1 HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+
+        let lines = ACCESS_LOG.0.lock().unwrap();
+        let line = lines
+            .iter()
+            .find(|line| line.contains("/logged"))
+            .expect("request should be logged");
+        assert!(line.starts_with("127.0.0.1 - - ["));
+
+        let (_, fields) = line.split_once("] ").unwrap();
+        let fields: Vec<_> = fields.split(' ').collect();
+        assert_eq!(
+            vec!["\"GET", "/logged?v=1", "HTTP/1.1\"", "200", "7"],
+            fields[..5]
+        );
+        assert!(fields[5].parse::<u128>().is_ok());
+    }
+
+    #[test]
+    fn test_request_id_generated_or_echoed() {
+        fn get_traced(_request: &HttpRequest, _routing_data: &RoutingData) -> Result<HttpResponse> {
+            HttpResponseBuilder::new()
+                .set_header("x-request-id", "upstream-7")
+                .build()
+        }
+
+        let router = Router::new()
+            .get("/hello", get_hello)
+            .unwrap()
+            .get("/traced", get_traced)
+            .unwrap();
+        let server = WebServer::new("127.0.0.1:0", router).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        let request_ids: Vec<_> = (0..2)
+            .map(|_| {
+                let response =
+                    send_request(addr, "GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n");
+                let (_, rest) = response.split_once("X-Request-Id: ").unwrap();
+                rest.split("\r\n").next().unwrap().to_owned()
+            })
+            .collect();
+        assert!(!request_ids[0].is_empty());
+        assert_ne!(request_ids[0], request_ids[1]);
+
+        // routing misses get the header too
+        let response = send_request(
+            addr,
+            "GET /missing HTTP/1.1\r\nX-Request-Id: client-id.42\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.contains("\r\nX-Request-Id: client-id.42\r\n"));
+
+        // ids that could inject headers or log lines are replaced
+        let response = send_request(
+            addr,
+            "GET /hello HTTP/1.1\r\nx-request-id: a\rX-Injected: 1\r\nConnection: close\r\n\r\n",
+        );
+        let (_, rest) = response.split_once("X-Request-Id: ").unwrap();
+        let request_id = rest.split("\r\n").next().unwrap();
+        assert!(is_valid_request_id(request_id));
+        assert!(!response.contains("X-Injected"));
+
+        // the id set by the handler is kept, whatever its case
+        let response = send_request(addr, "GET /traced HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.contains("\r\nx-request-id: upstream-7\r\n"));
+        assert_eq!(
+            1,
+            response
+                .to_ascii_lowercase()
+                .matches("x-request-id:")
+                .count()
+        );
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_is_valid_request_id() {
+        assert!(is_valid_request_id("4bf92f35-77b3.4a_0"));
+        assert!(is_valid_request_id(&"a".repeat(MAX_REQUEST_ID_LEN)));
+
+        assert!(!is_valid_request_id(""));
+        assert!(!is_valid_request_id(&"a".repeat(MAX_REQUEST_ID_LEN + 1)));
+        assert!(!is_valid_request_id("client id"));
+        assert!(!is_valid_request_id("id\rX-Injected: 1"));
+        assert!(!is_valid_request_id("id\u{e9}"));
+    }
+
+    #[test]
+    fn test_keep_alive_header_and_request_limit() {
+        let router = Router::new().get("/hello", get_hello).unwrap();
+        let server = WebServer::new("127.0.0.1:0", router)
+            .unwrap()
+            .keep_alive_timeout(Duration::from_secs(5))
+            .max_requests_per_connection(3)
+            .keep_alive_header(true);
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        // the connection is closed after the third request, the fourth is never answered
+        let response = send_request(addr, &"GET /hello HTTP/1.1\r\n\r\n".repeat(4));
+        let responses: Vec<_> = response.split("HTTP/1.1 200 OK\r\n").skip(1).collect();
+        assert_eq!(3, responses.len());
+        assert!(responses[0].contains("\r\nKeep-Alive: timeout=5, max=2\r\n"));
+        assert!(responses[1].contains("\r\nKeep-Alive: timeout=5, max=1\r\n"));
+        assert!(responses[2].starts_with("Connection: close\r\n"));
+        assert!(!responses[2].contains("Keep-Alive:"));
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_request_head_limits() {
+        let router = Router::new().get("/hello", get_hello).unwrap();
+        let server = WebServer::new("127.0.0.1:0", router)
+            .unwrap()
+            .max_head_size(128)
+            .head_timeout(Some(Duration::from_millis(100)));
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        let large_head = format!(
+            "GET /hello HTTP/1.1\r\nX-Padding: {}\r\n\r\n",
+            "a".repeat(128)
+        );
+        let response = send_request(addr, &large_head);
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+
+        let mut slow_client = TcpStream::connect(addr).unwrap();
+        slow_client.write_all(b"GET /hello HTTP/1.1\r\n").unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        slow_client.write_all(b"Host: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        slow_client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_read_timeout_frees_worker() {
+        let router = Router::new().get("/hello", get_hello).unwrap();
+        let server = WebServer::new("127.0.0.1:0", router)
+            .unwrap()
+            .concurrency(ConcurrencyModel::FixedPool(1))
+            .read_timeout(Some(Duration::from_millis(200)));
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        // occupies the only worker with a request line that never ends
+        let mut slow_client = TcpStream::connect(addr).unwrap();
+        slow_client.write_all(b"GET /hel").unwrap();
+
+        let start = std::time::Instant::now();
+        let response = send_request(addr, "GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(start.elapsed() < Duration::from_secs(3));
+
+        // the server dropped the slow connection
+        let mut rest = vec![];
+        slow_client.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+    }
 
-    stream.write_all(&response)?;
-    Ok(())
+    #[test]
+    fn test_keep_alive_serves_pipelined_requests() {
+        let router = Router::new().get("/hello", get_hello).unwrap();
+        let server = WebServer::new("127.0.0.1:0", router).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        // the first body is never read by the handler and must not leak into the next request
+        let response = send_request(
+            addr,
+            "GET /hello HTTP/1.1\r\nContent-Length: 13\r\n\r\nGET /nope\r\n\r\n\
+GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+
+        let responses: Vec<_> = response.split("HTTP/1.1 ").skip(1).collect();
+        assert_eq!(2, responses.len());
+        assert!(responses[0].starts_with("200 OK\r\n"));
+        assert!(responses[0].contains("Connection: keep-alive\r\n"));
+        assert!(responses[1].starts_with("200 OK\r\n"));
+        assert!(responses[1].contains("Connection: close\r\n"));
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+    }
+
+    fn get_slow(_request: &HttpRequest, _routing_data: &RoutingData) -> Result<HttpResponse> {
+        thread::sleep(Duration::from_millis(200));
+        HttpResponseBuilder::new().set_html_body("slow").build()
+    }
+
+    #[test]
+    fn test_idle_keep_alive_connection_frees_worker() {
+        let router = Router::new().get("/hello", get_hello).unwrap();
+        let server = WebServer::new("127.0.0.1:0", router)
+            .unwrap()
+            .concurrency(ConcurrencyModel::FixedPool(1))
+            .read_timeout(None)
+            .keep_alive_timeout(Duration::from_millis(200));
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        // occupies the only worker with a connection kept alive after its request
+        let mut idle_client = TcpStream::connect(addr).unwrap();
+        idle_client
+            .write_all(b"GET /hello HTTP/1.1\r\n\r\n")
+            .unwrap();
+
+        let start = Instant::now();
+        let response = send_request(addr, "GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(start.elapsed() < Duration::from_secs(3));
+
+        let mut rest = String::new();
+        idle_client.read_to_string(&mut rest).unwrap();
+        assert!(rest.contains("Connection: keep-alive\r\n"));
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_shutdown_closes_kept_alive_connections() {
+        let router = Router::new().get("/slow", get_slow).unwrap();
+        let server = WebServer::new("127.0.0.1:0", router)
+            .unwrap()
+            .read_timeout(None)
+            .keep_alive_timeout(Duration::from_secs(60));
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        // never sends anything, the server must not wait for it to shut down
+        let _idle_client = TcpStream::connect(addr).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        // the request being handled gets its response, the connection is closed right after
+        let start = Instant::now();
+        handle.shutdown();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+
+        server_thread.join().unwrap().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_pipelined_responses_keep_request_order() {
+        let router = Router::new()
+            .get("/slow", get_slow)
+            .unwrap()
+            .get("/hello", get_hello)
+            .unwrap();
+        let server = WebServer::new("127.0.0.1:0", router).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        let response = send_request(
+            addr,
+            "GET /slow HTTP/1.1\r\n\r\nGET /hello HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+
+        let slow = response.find("slow\r\n").unwrap();
+        let hello = response.find("hello\r\n").unwrap();
+        assert!(slow < hello);
+        assert_eq!(2, response.matches("HTTP/1.1 200 OK\r\n").count());
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+    }
+
+    fn post_echo(request: &HttpRequest, _routing_data: &RoutingData) -> Result<HttpResponse> {
+        HttpResponseBuilder::new()
+            .set_html_body(&request.get_str_body()?)
+            .build()
+    }
+
+    #[test]
+    fn test_read_buffer_size_tiny_and_large() {
+        for buffer_size in [1, 1024 * 1024] {
+            let router = Router::new().post("/echo", post_echo).unwrap();
+            let server = WebServer::new("127.0.0.1:0", router)
+                .unwrap()
+                .read_buffer_size(buffer_size);
+            let addr = server.local_addr().unwrap();
+            let handle = server.shutdown_handle().unwrap();
+            let server_thread = std::thread::spawn(move || server.run());
+
+            let response = send_request(
+                addr,
+                "POST /echo HTTP/1.1\r\nContent-Length: 5\r\n\r\nfirst\
+POST /echo HTTP/1.1\r\nContent-Length: 6\r\nConnection: close\r\n\r\nsecond",
+            );
+
+            let responses: Vec<_> = response.split("HTTP/1.1 ").skip(1).collect();
+            assert_eq!(2, responses.len(), "buffer of {buffer_size} bytes");
+            assert!(responses[0].ends_with("first\r\n"));
+            assert!(responses[1].ends_with("second\r\n"));
+
+            handle.shutdown();
+            server_thread.join().unwrap().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_head_response_has_no_body() {
+        let router = Router::new()
+            .head("/hello", get_hello)
+            .unwrap()
+            .get("/hello", get_hello)
+            .unwrap();
+        let server = WebServer::new("127.0.0.1:0", router).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        let response = send_request(
+            addr,
+            "HEAD /hello HTTP/1.1\r\n\r\nGET /hello HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+
+        let responses: Vec<_> = response.split("HTTP/1.1 ").skip(1).collect();
+        assert_eq!(2, responses.len());
+        assert!(responses[0].contains("Content-Length: 7\r\n"));
+        assert!(responses[0].ends_with("\r\n\r\n"));
+        assert!(responses[1].ends_with("\r\n\r\nhello\r\n"));
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_max_connections_per_ip() {
+        let router = Router::new().get("/hello", get_hello).unwrap();
+        let server = WebServer::new("127.0.0.1:0", router)
+            .unwrap()
+            .max_connections_per_ip(2);
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        let first = TcpStream::connect(addr).unwrap();
+        let _second = TcpStream::connect(addr).unwrap();
+
+        let mut rejected = TcpStream::connect(addr).unwrap();
+        let mut response = String::new();
+        rejected.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+        // closing a connection frees a slot for the next one
+        drop(first);
+        let start = Instant::now();
+        loop {
+            let response = send_request(addr, "GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n");
+            if response.starts_with("HTTP/1.1 200 OK\r\n") {
+                break;
+            }
+            assert!(start.elapsed() < Duration::from_secs(3));
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+    }
+
+    fn get_peer_ip(request: &HttpRequest, _routing_data: &RoutingData) -> Result<HttpResponse> {
+        HttpResponseBuilder::new()
+            .set_text_body(&format!("{} -> {}", request.peer_ip, request.local_ip))
+            .build()
+    }
+
+    #[test]
+    fn test_bind_ipv4_and_ipv6_loopback() {
+        let router = Router::new().get("/ip", get_peer_ip).unwrap();
+        let server = WebServer::bind(&["127.0.0.1:0", "[::1]:0"], router).unwrap();
+        let addrs = server.local_addrs().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        assert!(addrs[0].is_ipv4());
+        assert!(addrs[1].is_ipv6());
+        for (addr, expected) in addrs
+            .into_iter()
+            .zip(["127.0.0.1 -> 127.0.0.1", "::1 -> ::1"])
+        {
+            let response = send_request(addr, "GET /ip HTTP/1.1\r\nConnection: close\r\n\r\n");
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(response.ends_with(expected));
+        }
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket_serves_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("server.sock");
+        let router = Router::new()
+            .get("/hello", get_hello)
+            .unwrap()
+            .get("/ip", get_peer_ip)
+            .unwrap();
+        let server = WebServer::bind_unix(socket_path.to_str().unwrap(), router).unwrap();
+        assert!(server.local_addr().is_err());
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        let mut stream = UnixStream::connect(&socket_path).unwrap();
+        stream
+            .write_all(b"GET /hello HTTP/1.1\r\n\r\nGET /ip HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let responses: Vec<_> = response.split("HTTP/1.1 ").skip(1).collect();
+        assert_eq!(2, responses.len());
+        assert!(responses[0].starts_with("200 OK\r\n"));
+        assert!(responses[0].ends_with("hello\r\n"));
+        assert!(responses[1].ends_with("0.0.0.0 -> 0.0.0.0"));
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+        assert!(!socket_path.exists());
+    }
+
+    #[test]
+    fn test_rebind_same_address_after_shutdown() {
+        let router = Router::new().get("/hello", get_hello).unwrap();
+        let server = WebServer::bind_with_backlog(&["127.0.0.1:0"], 16, router).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        // the server closes this connection first, leaving it in TIME_WAIT on its side
+        let response = send_request(addr, "GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+
+        let router = Router::new().get("/hello", get_hello).unwrap();
+        let server = WebServer::new(&addr.to_string(), router).unwrap();
+        assert_eq!(addr, server.local_addr().unwrap());
+    }
+
+    #[test]
+    fn test_bind_without_address_err() {
+        assert!(WebServer::bind(&[], Router::new()).is_err());
+    }
+
+    /// In-memory connection output, still readable once handed to the server.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_serve_connection_in_memory() {
+        let router = Router::new().post("/echo", post_echo).unwrap();
+        let server = WebServer::new("127.0.0.1:0", router).unwrap();
+        let raw_requests = "POST /echo HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello\
+GET /missing HTTP/1.1\r\nConnection: close\r\n\r\n";
+        let output = SharedBuffer::default();
+
+        let ip = IpAddr::from_str("192.0.2.1").unwrap();
+        server
+            .serve_connection(
+                io::Cursor::new(raw_requests.as_bytes().to_vec()),
+                output.clone(),
+                ip,
+                ip,
+            )
+            .unwrap();
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let responses: Vec<_> = output.split("HTTP/1.1 ").skip(1).collect();
+        assert_eq!(2, responses.len());
+        assert!(responses[0].starts_with("200 OK\r\n"));
+        assert!(responses[0].contains("Connection: keep-alive\r\n"));
+        assert!(responses[0].ends_with("\r\n\r\nhello\r\n"));
+        assert!(responses[1].starts_with("404 Not Found\r\n"));
+        assert!(responses[1].contains("Connection: close\r\n"));
+    }
+
+    #[test]
+    fn test_lowercase_content_length_keeps_body_off_next_request() {
+        let router = Router::new()
+            .post("/echo", post_echo)
+            .unwrap()
+            .get("/admin", get_hello)
+            .unwrap();
+        let server = WebServer::new("127.0.0.1:0", router).unwrap();
+        let raw_requests =
+            "POST /echo HTTP/1.1\r\ncontent-length: 23\r\n\r\nGET /admin HTTP/1.1\r\n\r\n\
+GET /missing HTTP/1.1\r\nconnection: close\r\n\r\n";
+        let output = SharedBuffer::default();
+
+        let ip = IpAddr::from_str("192.0.2.1").unwrap();
+        server
+            .serve_connection(
+                io::Cursor::new(raw_requests.as_bytes().to_vec()),
+                output.clone(),
+                ip,
+                ip,
+            )
+            .unwrap();
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let responses: Vec<_> = output.split("HTTP/1.1 ").skip(1).collect();
+        assert_eq!(2, responses.len());
+        assert!(responses[0].starts_with("200 OK\r\n"));
+        assert!(responses[0].ends_with("\r\n\r\nGET /admin HTTP/1.1\r\n\r\n\r\n"));
+        assert!(responses[1].starts_with("404 Not Found\r\n"));
+        assert!(responses[1].contains("Connection: close\r\n"));
+    }
+
+    #[test]
+    fn test_malformed_request_gets_400() {
+        let router = Router::new().get("/search", get_hello).unwrap();
+        let server = WebServer::new("127.0.0.1:0", router).unwrap();
+        let ip = IpAddr::from_str("192.0.2.1").unwrap();
+
+        for raw_request in [
+            b"GET /search?q=%ZZ HTTP/1.1\r\n\r\nGET /search HTTP/1.1\r\n\r\n".as_slice(),
+            b"BREW /search HTTP/1.1\r\n\r\n",
+            b"POST /search HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+            b"GET /search HTTP/1.1\r\nX-Bytes: \xff\r\n\r\n",
+        ] {
+            let output = SharedBuffer::default();
+            server
+                .serve_connection(
+                    io::Cursor::new(raw_request.to_vec()),
+                    output.clone(),
+                    ip,
+                    ip,
+                )
+                .unwrap();
+
+            let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+            assert!(output.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+            assert!(output.contains("Connection: close\r\n"));
+            assert_eq!(1, output.matches("HTTP/1.1 ").count());
+        }
+    }
+
+    #[test]
+    fn test_wants_keep_alive() {
+        let request = |version: &str, connection: Option<&str>| {
+            HttpRequest::from_raw_request(HttpRequestRaw {
+                request_line: format!("GET / {version}"),
+                headers: connection
+                    .map(|value| vec![HttpHeader::new("Connection", value)])
+                    .unwrap_or_default(),
+                body: vec![],
+                trailers: vec![],
+                peer_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+                local_ip: IpAddr::from_str("0.0.0.0").unwrap(),
+            })
+            .unwrap()
+        };
+
+        assert!(wants_keep_alive(&request("HTTP/1.1", None)));
+        assert!(!wants_keep_alive(&request("HTTP/1.1", Some("close"))));
+        assert!(!wants_keep_alive(&request("HTTP/1.0", None)));
+        assert!(wants_keep_alive(&request("HTTP/1.0", Some("Keep-Alive"))));
+    }
+
+    #[test]
+    fn test_shutdown_stops_run() {
+        let server = WebServer::new("127.0.0.1:0", Router::new()).unwrap();
+        let handle = server.shutdown_handle().unwrap();
+
+        let server_thread = std::thread::spawn(move || server.run());
+        handle.shutdown();
+
+        assert!(server_thread.join().unwrap().is_ok());
+    }
+
+    fn get_long_page(_request: &HttpRequest, _routing_data: &RoutingData) -> Result<HttpResponse> {
+        HttpResponseBuilder::new()
+            .set_html_body(&"<li>item</li>".repeat(500))
+            .build()
+    }
+
+    #[test]
+    fn test_gzip_compression_of_large_html() {
+        let router = Router::new().get("/page", get_long_page).unwrap();
+        let server = WebServer::new("127.0.0.1:0", router)
+            .unwrap()
+            .with_compression(CompressionConfig::new());
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /page HTTP/1.1\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = vec![];
+        stream.read_to_end(&mut response).unwrap();
+
+        let head_end = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap()
+            + 4;
+        let head = std::str::from_utf8(&response[..head_end]).unwrap();
+        let body = &response[head_end..];
+        assert!(head.contains("Content-Encoding: gzip\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(body)
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(format!("{}\r\n", "<li>item</li>".repeat(500)), decompressed);
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+    }
+
+    fn get_events(_request: &HttpRequest, _routing_data: &RoutingData) -> Result<HttpResponse> {
+        let (sender, events) = sse::channel();
+        thread::spawn(move || {
+            for i in 0.. {
+                if sender.send(SseEvent::new(&format!("tick {i}"))).is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+
+        HttpResponseBuilder::new().set_event_stream(events).build()
+    }
+
+    #[test]
+    fn test_server_sent_events() {
+        let router = Router::new().get("/events", get_events).unwrap();
+        let server = WebServer::new("127.0.0.1:0", router).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+        let mut reader = BufReader::new(stream);
+
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            reader.read_line(&mut head).unwrap();
+        }
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("Content-Type: text/event-stream\r\n"));
+        assert!(head.contains("Cache-Control: no-cache\r\n"));
+        assert!(!head.contains("Content-Length"));
+
+        let mut events = String::new();
+        while events.matches("\n\n").count() < 2 {
+            reader.read_line(&mut events).unwrap();
+        }
+        assert_eq!("data: tick 0\n\ndata: tick 1\n\n", events);
+        drop(reader);
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+    }
+
+    fn websocket_echo(_request: &HttpRequest, websocket: &mut WebSocket) -> Result<()> {
+        loop {
+            match websocket.read_message()? {
+                Message::Text(text) => websocket.send_text(&text)?,
+                Message::Binary(bytes) => websocket.send_binary(&bytes)?,
+                Message::Close => return Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_websocket_upgrade_echoes_text_frame() {
+        let router = Router::new().websocket("/echo", websocket_echo).unwrap();
+        let server = WebServer::new("127.0.0.1:0", router).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let server_thread = std::thread::spawn(move || server.run());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let key = "dGhlIHNhbXBsZSBub25jZQ==";
+        let handshake = format!(
+            "GET /echo HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+Sec-WebSocket-Key: {key}\r\nSec-WebSocket-Version: 13\r\n\r\n"
+        );
+        stream.write_all(handshake.as_bytes()).unwrap();
+
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            reader.read_line(&mut head).unwrap();
+        }
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("Connection: Upgrade\r\n"));
+        assert!(head.contains(&format!(
+            "Sec-WebSocket-Accept: {}\r\n",
+            websocket::accept_key(key)
+        )));
+
+        // masked text frame "hi", as browsers send it
+        let mask = [1, 2, 3, 4];
+        let mut frame = vec![0x81, 0x80 | 2];
+        frame.extend(mask);
+        frame.extend([b'h' ^ mask[0], b'i' ^ mask[1]]);
+        stream.write_all(&frame).unwrap();
+
+        let mut echoed = [0; 4];
+        reader.read_exact(&mut echoed).unwrap();
+        assert_eq!([0x81, 2, b'h', b'i'], echoed);
+
+        // close frame with status 1000
+        let mut frame = vec![0x88, 0x80 | 2];
+        frame.extend(mask);
+        frame.extend([0x03 ^ mask[0], 0xE8 ^ mask[1]]);
+        stream.write_all(&frame).unwrap();
+
+        let mut closing = vec![];
+        reader.read_to_end(&mut closing).unwrap();
+        assert_eq!(vec![0x88, 2, 0x03, 0xE8], closing);
+
+        handle.shutdown();
+        server_thread.join().unwrap().unwrap();
+    }
 }